@@ -1,57 +1,701 @@
-use std::env;
+pub mod cipher;
+pub mod cookie;
+pub mod guard;
+pub mod keys;
+pub mod oidc;
+pub mod totp;
 
-use actix_web::http::header::HeaderValue;
-use chrono::Utc;
+use std::{env, ops::Deref};
+
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest};
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::future::LocalBoxFuture;
 use jsonwebtoken::errors::Error;
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Header, Validation};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::error::AppError;
+use crate::{
+    domain::{
+        email_verification::EmailVerificationPolicy,
+        personal_access_token::{Scope, TOKEN_MARKER},
+        user::Role,
+    },
+    error::AppError,
+    handlers::{
+        personal_access_token::DynPersonalAccessTokenHandler,
+        token_revocation::DynTokenRevocationHandler, user::DynUserHandler,
+    },
+    utils::env_var_or,
+};
+
+use self::{
+    cookie::{check_csrf, ACCESS_TOKEN_COOKIE},
+    keys::JwtKeys,
+};
+
+pub const TWO_FACTOR_CHALLENGE_LIFETIME_SECONDS: i64 = 300;
+
+/// Registered claims put in and checked on every access token. Each environment should
+/// use its own issuer or audience so its tokens are refused by the others even when they
+/// share a signing key.
+#[derive(Debug, Clone)]
+pub struct JwtSettings {
+    pub issuer: String,
+    pub audience: String,
+    pub access_token_lifetime: chrono::Duration,
+    /// Seconds of clock skew between servers tolerated on `exp`, `nbf` and `iat`.
+    pub leeway: u64,
+}
+
+impl Default for JwtSettings {
+    fn default() -> Self {
+        JwtSettings {
+            issuer: "users-actix".to_string(),
+            audience: "users-actix".to_string(),
+            access_token_lifetime: chrono::Duration::seconds(300),
+            leeway: 30,
+        }
+    }
+}
+
+impl JwtSettings {
+    pub fn from_env() -> JwtSettings {
+        let default = JwtSettings::default();
+        JwtSettings {
+            issuer: env::var("JWT_ISSUER").unwrap_or(default.issuer),
+            audience: env::var("JWT_AUDIENCE").unwrap_or(default.audience),
+            access_token_lifetime: chrono::Duration::seconds(env_var_or(
+                "ACCESS_TOKEN_LIFETIME_SECONDS",
+                default.access_token_lifetime.num_seconds(),
+            )),
+            leeway: env_var_or("JWT_LEEWAY_SECONDS", default.leeway),
+        }
+    }
+
+    /// How long after being issued a token may still be accepted.
+    pub fn max_token_age(&self) -> chrono::Duration {
+        self.access_token_lifetime + chrono::Duration::seconds(self.leeway as i64)
+    }
+}
 
-#[derive(Serialize, Deserialize)]
-struct Claims {
-    sub: Uuid,
-    exp: usize,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+    pub iss: String,
+    pub aud: String,
+    pub sub: Uuid,
+    pub exp: usize,
+    pub nbf: usize,
+    pub iat: usize,
+    pub jti: Uuid,
+    /// The sign-in session the token was issued in, see [`crate::domain::session`], or
+    /// for OAuth tokens the grant, see [`crate::domain::oauth::OAuthGrant`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    /// The OAuth client the token was issued to, see [`crate::domain::oauth`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<Uuid>,
+    /// The space separated scopes of a token issued to an OAuth client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub role: Role,
 }
 
-pub fn create_jwt(uuid: Uuid) -> Result<String, Error> {
-    //TODO: instantiate in another place
-    let jwt_secret =
-        env::var("JWT_ENCODING_SECRET").expect("JWT_ENCODING_SECRET not set in .env file");
+impl Claims {
+    pub fn issued_at(&self) -> DateTime<Utc> {
+        Utc.timestamp_opt(self.iat as i64, 0).unwrap()
+    }
 
-    let expiration = Utc::now()
-        .checked_add_signed(chrono::Duration::seconds(300))
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        Utc.timestamp_opt(self.exp as i64, 0).unwrap()
+    }
+}
+
+pub fn create_jwt(
+    uuid: Uuid,
+    role: Role,
+    session_id: Option<Uuid>,
+    keys: &JwtKeys,
+) -> Result<String, Error> {
+    let claims = Claims {
+        sid: session_id,
+        ..new_claims(uuid, role, keys.settings())
+    };
+    sign_jwt(&claims, keys)
+}
+
+/// Issues an access token to an OAuth client, on behalf of the user `uuid` under their
+/// grant `grant_id`, or of the client itself when it is its own id. Like personal access
+/// tokens, it never carries more than the base role.
+pub fn create_oauth_jwt(
+    uuid: Uuid,
+    client_id: Uuid,
+    scopes: &[Scope],
+    grant_id: Option<Uuid>,
+    keys: &JwtKeys,
+) -> Result<String, Error> {
+    let scope = scopes
+        .iter()
+        .map(|scope| scope.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let claims = Claims {
+        sid: grant_id,
+        client_id: Some(client_id),
+        scope: Some(scope),
+        ..new_claims(uuid, Role::User, keys.settings())
+    };
+    sign_jwt(&claims, keys)
+}
+
+fn new_claims(uuid: Uuid, role: Role, settings: &JwtSettings) -> Claims {
+    let now = Utc::now();
+    let expiration = now
+        .checked_add_signed(settings.access_token_lifetime)
         .expect("Invalid timestamp")
         .timestamp();
 
-    let claims = Claims {
+    Claims {
+        iss: settings.issuer.clone(),
+        aud: settings.audience.clone(),
         sub: uuid,
         exp: expiration as usize,
-    };
-    let header = Header::new(Algorithm::HS512);
+        nbf: now.timestamp() as usize,
+        iat: now.timestamp() as usize,
+        jti: Uuid::new_v4(),
+        sid: None,
+        client_id: None,
+        scope: None,
+        role,
+    }
+}
+
+fn sign_jwt(claims: &Claims, keys: &JwtKeys) -> Result<String, Error> {
+    let signing_key = keys.signing_key();
+    let mut header = Header::new(signing_key.algorithm);
+    header.kid = Some(signing_key.kid.clone());
     encode(
         &header,
-        &claims,
-        &EncodingKey::from_secret(jwt_secret.as_bytes()),
+        claims,
+        signing_key
+            .encoding_key()
+            .expect("Signing key must have a private key"),
     )
 }
 
-pub fn get_id_auth_header(header: &HeaderValue) -> Result<Uuid, AppError> {
+pub fn decode_jwt(token: &str, keys: &JwtKeys) -> Result<Claims, AppError> {
+    let invalid_token = || AppError::unauthorized("Invalid token".to_string());
+
+    let header = decode_header(token).map_err(|_| invalid_token())?;
+    let key = keys
+        .verification_key(header.kid.as_deref())
+        .ok_or_else(invalid_token)?;
+
+    let settings = keys.settings();
+    let mut validation = Validation::new(key.algorithm);
+    validation.set_issuer(&[&settings.issuer]);
+    validation.set_audience(&[&settings.audience]);
+    validation.set_required_spec_claims(&["iss", "aud", "sub", "exp", "nbf", "iat"]);
+    validation.validate_nbf = true;
+    validation.leeway = settings.leeway;
+
+    let token =
+        decode::<Claims>(token, key.decoding_key(), &validation).map_err(|_| invalid_token())?;
+
+    // jsonwebtoken does not check `iat`, a token from the future would outlive its `exp`
+    // as far as revocations are concerned.
+    if token.claims.issued_at() > Utc::now() + chrono::Duration::seconds(settings.leeway as i64) {
+        return Err(invalid_token());
+    }
+
+    Ok(token.claims)
+}
+
+/// The caller identified by a valid, non-revoked `Authorization: Bearer <token>` header,
+/// holding either a JWT or a personal access token, or else by the access token cookie
+/// set for browser clients. Extracting it rejects the request with 401 otherwise, and
+/// with 403 when a cookie authenticated request that is not read-only lacks its CSRF
+/// token.
+#[derive(Debug)]
+pub struct AuthenticatedUser {
+    pub id: Uuid,
+    pub role: Role,
+    pub credential: Credential,
+}
+
+#[derive(Debug)]
+pub enum Credential {
+    Jwt(Claims),
+    PersonalAccessToken {
+        scopes: Vec<Scope>,
+    },
+    /// Issued by the authorization server to the client in `claims.client_id`, see
+    /// [`create_oauth_jwt`]. For the client credentials grant, the user is the client
+    /// itself.
+    OAuth {
+        scopes: Vec<Scope>,
+        claims: Claims,
+    },
+}
+
+impl AuthenticatedUser {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.role >= role
+    }
+
+    /// JWTs come from an interactive login and carry every scope, personal access
+    /// tokens and OAuth tokens only the scopes they were granted.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        match &self.credential {
+            Credential::Jwt(_) => true,
+            Credential::PersonalAccessToken { scopes } | Credential::OAuth { scopes, .. } => {
+                scopes.contains(&scope)
+            }
+        }
+    }
+
+    pub fn require_scope(&self, scope: Scope) -> Result<(), AppError> {
+        if self.has_scope(scope) {
+            return Ok(());
+        }
+        Err(AppError::forbidden(format!(
+            "This token is missing the {scope} scope"
+        )))
+    }
+
+    /// Allows acting on the account `id` when it is the user's own, or when their role
+    /// is at least `role`. Anyone else gets 403.
+    pub fn authorize_user(&self, id: Uuid, role: Role) -> Result<(), AppError> {
+        if self.id == id || self.has_role(role) {
+            return Ok(());
+        }
+        Err(AppError::forbidden(
+            "You are not allowed to perform this action on this user".to_string(),
+        ))
+    }
+
+    /// Like [`AuthenticatedUser::authorize_user`], for actions nobody may take on
+    /// someone else's behalf.
+    pub fn authorize_self(&self, id: Uuid) -> Result<(), AppError> {
+        if self.id == id {
+            return Ok(());
+        }
+        Err(AppError::forbidden(
+            "You are not allowed to perform this action on this user".to_string(),
+        ))
+    }
+
+    /// Rejects OAuth access tokens on routes securing the account itself, which no
+    /// granted scope lets an app act on.
+    pub fn reject_oauth(&self) -> Result<(), AppError> {
+        if let Credential::OAuth { .. } = self.credential {
+            return Err(AppError::forbidden(
+                "OAuth access tokens cannot manage account security".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An [`AuthenticatedUser`] allowed to change things. Unless the
+/// [`EmailVerificationPolicy`] is `Optional`, users who have not verified their email yet
+/// are rejected with 403.
+#[derive(Debug)]
+pub struct VerifiedUser(pub AuthenticatedUser);
+
+impl Deref for VerifiedUser {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let token = request_token(&req)?.ok_or_else(|| {
+                AppError::unauthorized("Missing authorization header".to_string())
+            })?;
+            authenticate(&req, &token).await
+        })
+    }
+}
+
+impl FromRequest for VerifiedUser {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let user = AuthenticatedUser::from_request(&req, payload);
+        Box::pin(async move {
+            let user = user.await?;
+
+            let policy = req
+                .app_data::<web::Data<EmailVerificationPolicy>>()
+                .ok_or_else(|| {
+                    AppError::internal("Email verification is not configured".to_string())
+                })?;
+            if *policy.get_ref() == EmailVerificationPolicy::Optional {
+                return Ok(VerifiedUser(user));
+            }
+
+            let user_handler = req
+                .app_data::<web::Data<DynUserHandler>>()
+                .ok_or_else(|| AppError::internal("Users are not configured".to_string()))?;
+            let account = user_handler.get_user_by_id(user.id).await?;
+            if account.is_some_and(|account| account.email_verified_at.is_some()) {
+                return Ok(VerifiedUser(user));
+            }
+            Err(AppError::forbidden(
+                "Verify your email address before making changes".to_string(),
+            ))
+        })
+    }
+}
+
+/// The header wins over the cookie so API clients are never subject to CSRF checks.
+fn request_token(req: &HttpRequest) -> Result<Option<String>, AppError> {
+    if let Some(token) = bearer_token(req)? {
+        return Ok(Some(token));
+    }
+    let Some(cookie) = req.cookie(ACCESS_TOKEN_COOKIE) else {
+        return Ok(None);
+    };
+    if !req.method().is_safe() {
+        check_csrf(req)?;
+    }
+    Ok(Some(cookie.value().to_string()))
+}
+
+fn bearer_token(req: &HttpRequest) -> Result<Option<String>, AppError> {
+    let Some(header) = req.headers().get(header::AUTHORIZATION) else {
+        return Ok(None);
+    };
+
     let header_str = header
         .to_str()
-        .map_err(|_| AppError::bad_request("Invalid header".to_string()))?;
+        .map_err(|_| AppError::unauthorized("Invalid authorization header".to_string()))?;
 
-    //TODO: instantiate in another place
-    let jwt_secret =
-        env::var("JWT_ENCODING_SECRET").expect("JWT_ENCODING_SECRET not set in .env file");
+    match header_str.split_once(' ') {
+        Some((scheme, token))
+            if scheme.eq_ignore_ascii_case("Bearer") && !token.trim().is_empty() =>
+        {
+            Ok(Some(token.trim().to_string()))
+        }
+        _ => Err(AppError::unauthorized(
+            "Authorization header must use the Bearer scheme".to_string(),
+        )),
+    }
+}
 
-    let token = decode::<Claims>(
-        header_str,
-        &DecodingKey::from_secret(jwt_secret.as_bytes()),
-        &Validation::new(Algorithm::HS512),
-    )
-    .map_err(|_| AppError::bad_request("Invalid token".to_string()))?;
+async fn authenticate(req: &HttpRequest, token: &str) -> Result<AuthenticatedUser, AppError> {
+    if token.starts_with(TOKEN_MARKER) {
+        return authenticate_personal_access_token(req, token).await;
+    }
+
+    let keys = req
+        .app_data::<web::Data<JwtKeys>>()
+        .ok_or_else(|| AppError::internal("JWT keys are not configured".to_string()))?;
+    let revocation_handler = req
+        .app_data::<web::Data<DynTokenRevocationHandler>>()
+        .ok_or_else(|| AppError::internal("Token revocation is not configured".to_string()))?;
+
+    let claims = decode_jwt(token, keys)?;
+
+    if revocation_handler
+        .is_token_revoked(claims.jti, claims.sub, claims.sid, claims.issued_at())
+        .await?
+    {
+        return Err(AppError::unauthorized("Invalid token".to_string()));
+    }
+
+    if let Some(client_id) = claims.client_id {
+        return authenticate_oauth_token(revocation_handler.get_ref(), client_id, claims).await;
+    }
+
+    Ok(AuthenticatedUser {
+        id: claims.sub,
+        role: claims.role,
+        credential: Credential::Jwt(claims),
+    })
+}
+
+/// Deleting a client revokes the tokens issued to it as if it were a user, so they are
+/// checked against its id as well.
+async fn authenticate_oauth_token(
+    revocation_handler: &DynTokenRevocationHandler,
+    client_id: Uuid,
+    claims: Claims,
+) -> Result<AuthenticatedUser, AppError> {
+    let invalid_token = || AppError::unauthorized("Invalid token".to_string());
+
+    if revocation_handler
+        .is_token_revoked(claims.jti, client_id, None, claims.issued_at())
+        .await?
+    {
+        return Err(invalid_token());
+    }
+
+    let scopes = claims
+        .scope
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<Scope>, _>>()
+        .map_err(|_| invalid_token())?;
+
+    Ok(AuthenticatedUser {
+        id: claims.sub,
+        role: Role::User,
+        credential: Credential::OAuth { scopes, claims },
+    })
+}
+
+/// Personal access tokens are meant for scripts, so they never carry more than the
+/// base role whatever the role of their owner.
+async fn authenticate_personal_access_token(
+    req: &HttpRequest,
+    token: &str,
+) -> Result<AuthenticatedUser, AppError> {
+    let personal_access_token_handler = req
+        .app_data::<web::Data<DynPersonalAccessTokenHandler>>()
+        .ok_or_else(|| {
+            AppError::internal("Personal access tokens are not configured".to_string())
+        })?;
+
+    let personal_access_token = personal_access_token_handler
+        .authenticate(token.to_string())
+        .await?;
+
+    Ok(AuthenticatedUser {
+        id: personal_access_token.user_id,
+        role: Role::User,
+        credential: Credential::PersonalAccessToken {
+            scopes: personal_access_token.scopes,
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use actix_web::{cookie::Cookie, http::Method, test::TestRequest, ResponseError};
+
+    use super::*;
+    use crate::{
+        auth::{
+            cookie::{CSRF_COOKIE, CSRF_HEADER},
+            keys::JwtKey,
+        },
+        handlers::token_revocation::MockTokenRevocationHandler,
+    };
+
+    fn request(revoked: bool) -> TestRequest {
+        let mut revocation_handler = MockTokenRevocationHandler::new();
+        revocation_handler
+            .expect_is_token_revoked()
+            .returning(move |_, _, _, _| Ok(revoked));
+        let revocation_handler: Arc<DynTokenRevocationHandler> = Arc::new(revocation_handler);
+
+        TestRequest::default()
+            .app_data(web::Data::new(JwtKeys::from_secret(b"secret")))
+            .app_data(web::Data::from(revocation_handler))
+    }
+
+    #[actix_web::test]
+    async fn extracts_user_from_bearer_token() {
+        let user_id = Uuid::new_v4();
+        let token =
+            create_jwt(user_id, Role::User, None, &JwtKeys::from_secret(b"secret")).unwrap();
+
+        let req = request(false)
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .to_http_request();
+
+        let user = AuthenticatedUser::extract(&req)
+            .await
+            .expect("Failed to authenticate user");
+
+        assert_eq!(user.id, user_id);
+    }
+
+    #[actix_web::test]
+    async fn rejects_token_without_bearer_scheme() {
+        let token = create_jwt(
+            Uuid::new_v4(),
+            Role::User,
+            None,
+            &JwtKeys::from_secret(b"secret"),
+        )
+        .unwrap();
+
+        let req = request(false)
+            .insert_header((header::AUTHORIZATION, token))
+            .to_http_request();
+
+        let error = AuthenticatedUser::extract(&req).await.unwrap_err();
+
+        assert_eq!(error.status_code(), 401);
+    }
+
+    #[actix_web::test]
+    async fn rejects_revoked_token() {
+        let token = create_jwt(
+            Uuid::new_v4(),
+            Role::User,
+            None,
+            &JwtKeys::from_secret(b"secret"),
+        )
+        .unwrap();
+
+        let req = request(true)
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .to_http_request();
+
+        let error = AuthenticatedUser::extract(&req).await.unwrap_err();
+
+        assert_eq!(error.status_code(), 401);
+    }
+
+    #[actix_web::test]
+    async fn extracts_user_from_cookie_with_csrf_token_on_writes() {
+        let user_id = Uuid::new_v4();
+        let token =
+            create_jwt(user_id, Role::User, None, &JwtKeys::from_secret(b"secret")).unwrap();
+
+        let read = request(false)
+            .cookie(Cookie::new(ACCESS_TOKEN_COOKIE, token.clone()))
+            .to_http_request();
+        let write_without_csrf = request(false)
+            .method(Method::POST)
+            .cookie(Cookie::new(ACCESS_TOKEN_COOKIE, token.clone()))
+            .cookie(Cookie::new(CSRF_COOKIE, "csrf"))
+            .to_http_request();
+        let write_with_csrf = request(false)
+            .method(Method::POST)
+            .cookie(Cookie::new(ACCESS_TOKEN_COOKIE, token))
+            .cookie(Cookie::new(CSRF_COOKIE, "csrf"))
+            .insert_header((CSRF_HEADER, "csrf"))
+            .to_http_request();
+
+        assert_eq!(AuthenticatedUser::extract(&read).await.unwrap().id, user_id);
+        assert_eq!(
+            AuthenticatedUser::extract(&write_without_csrf)
+                .await
+                .unwrap_err()
+                .status_code(),
+            403
+        );
+        assert_eq!(
+            AuthenticatedUser::extract(&write_with_csrf)
+                .await
+                .unwrap()
+                .id,
+            user_id
+        );
+    }
+
+    #[actix_web::test]
+    async fn limits_oauth_token_to_its_scopes_and_client() {
+        let user_id = Uuid::new_v4();
+        let client_id = Uuid::new_v4();
+        let token = create_oauth_jwt(
+            user_id,
+            client_id,
+            &[Scope::UsersRead],
+            None,
+            &JwtKeys::from_secret(b"secret"),
+        )
+        .unwrap();
+
+        let mut revocation_handler = MockTokenRevocationHandler::new();
+        revocation_handler
+            .expect_is_token_revoked()
+            .withf(move |_, id, _, _| *id == user_id)
+            .returning(|_, _, _, _| Ok(false));
+        revocation_handler
+            .expect_is_token_revoked()
+            .withf(move |_, id, _, _| *id == client_id)
+            .returning(|_, _, _, _| Ok(false));
+        let revocation_handler: Arc<DynTokenRevocationHandler> = Arc::new(revocation_handler);
+        let req = TestRequest::default()
+            .app_data(web::Data::new(JwtKeys::from_secret(b"secret")))
+            .app_data(web::Data::from(revocation_handler))
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .to_http_request();
+
+        let user = AuthenticatedUser::extract(&req)
+            .await
+            .expect("Failed to authenticate OAuth token");
+
+        assert_eq!(user.id, user_id);
+        assert_eq!(user.role, Role::User);
+        assert!(user.has_scope(Scope::UsersRead));
+        assert!(!user.has_scope(Scope::UsersWrite));
+        assert!(
+            matches!(user.credential, Credential::OAuth { claims, .. } if claims.client_id == Some(client_id))
+        );
+    }
+
+    #[test]
+    fn verifies_tokens_signed_with_previous_key() {
+        let previous_keys =
+            JwtKeys::new(vec![JwtKey::from_secret("previous", b"old")], "previous").unwrap();
+        let token = create_jwt(Uuid::new_v4(), Role::User, None, &previous_keys).unwrap();
+
+        let rotated_keys = JwtKeys::new(
+            vec![
+                JwtKey::from_secret("current", b"new"),
+                JwtKey::from_secret("previous", b"old"),
+            ],
+            "current",
+        )
+        .unwrap();
+
+        assert!(decode_jwt(&token, &rotated_keys).is_ok());
+        assert!(decode_header(
+            &create_jwt(Uuid::new_v4(), Role::User, None, &rotated_keys).unwrap()
+        )
+        .unwrap()
+        .kid
+        .is_some_and(|kid| kid == "current"));
+    }
+
+    #[test]
+    fn rejects_tokens_minted_for_another_environment() {
+        let staging_keys = JwtKeys::from_secret(b"secret").with_settings(JwtSettings {
+            audience: "staging".to_string(),
+            ..JwtSettings::default()
+        });
+        let token = create_jwt(Uuid::new_v4(), Role::User, None, &staging_keys).unwrap();
+
+        assert!(decode_jwt(&token, &staging_keys).is_ok());
+        assert!(decode_jwt(&token, &JwtKeys::from_secret(b"secret")).is_err());
+    }
+
+    #[test]
+    fn rejects_expired_tokens_beyond_leeway() {
+        let keys = JwtKeys::from_secret(b"secret").with_settings(JwtSettings {
+            access_token_lifetime: chrono::Duration::seconds(-10),
+            leeway: 5,
+            ..JwtSettings::default()
+        });
+        let lenient_keys = JwtKeys::from_secret(b"secret").with_settings(JwtSettings {
+            leeway: 60,
+            ..JwtSettings::default()
+        });
+        let token = create_jwt(Uuid::new_v4(), Role::User, None, &keys).unwrap();
 
-    Ok(token.claims.sub)
+        assert!(decode_jwt(&token, &keys).is_err());
+        assert!(decode_jwt(&token, &lenient_keys).is_ok());
+    }
 }
@@ -0,0 +1,83 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+
+const NONCE_LENGTH: usize = 12;
+
+/// Encrypts secrets that have to be read back, such as TOTP seeds, with AES-256-GCM.
+/// Ciphertexts are stored as the base64 encoding of the nonce followed by the sealed
+/// data.
+pub struct SecretCipher {
+    cipher: Aes256Gcm,
+}
+
+impl SecretCipher {
+    pub fn new(key: &[u8]) -> Result<SecretCipher, String> {
+        let cipher = Aes256Gcm::new_from_slice(key)
+            .map_err(|_| "Encryption keys must be 32 bytes long".to_string())?;
+        Ok(SecretCipher { cipher })
+    }
+
+    /// Reads the base64 encoded key from `TWO_FACTOR_ENCRYPTION_KEY`, which can be
+    /// generated with `openssl rand -base64 32`.
+    pub fn from_env() -> Result<SecretCipher, String> {
+        let key = std::env::var("TWO_FACTOR_ENCRYPTION_KEY")
+            .map_err(|_| "TWO_FACTOR_ENCRYPTION_KEY not set in .env file".to_string())?;
+        let key = STANDARD
+            .decode(key.trim())
+            .map_err(|_| "TWO_FACTOR_ENCRYPTION_KEY is not valid base64".to_string())?;
+        SecretCipher::new(&key)
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> String {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .expect("Encrypting in memory cannot fail");
+        URL_SAFE_NO_PAD.encode([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub fn decrypt(&self, encoded: &str) -> Result<Vec<u8>, String> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(encoded)
+            .map_err(|_| "Invalid ciphertext encoding".to_string())?;
+        if bytes.len() < NONCE_LENGTH {
+            return Err("Ciphertext is too short".to_string());
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Could not decrypt secret".to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decrypts_what_it_encrypted() {
+        let cipher = SecretCipher::new(&[7; 32]).unwrap();
+
+        let encrypted = cipher.encrypt(b"secret");
+
+        assert_ne!(encrypted.as_bytes(), b"secret");
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn rejects_ciphertext_from_another_key() {
+        let encrypted = SecretCipher::new(&[7; 32]).unwrap().encrypt(b"secret");
+
+        assert!(SecretCipher::new(&[8; 32])
+            .unwrap()
+            .decrypt(&encrypted)
+            .is_err());
+    }
+}
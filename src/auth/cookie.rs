@@ -0,0 +1,264 @@
+use std::env;
+
+use actix_web::{
+    cookie::{time, Cookie, SameSite},
+    dev::Payload,
+    web, FromRequest, HttpRequest,
+};
+use futures_util::future::{ready, Ready};
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+
+use crate::{error::AppError, utils::env_var_or};
+
+pub const ACCESS_TOKEN_COOKIE: &str = "access_token";
+pub const REFRESH_TOKEN_COOKIE: &str = "refresh_token";
+/// Readable by scripts on purpose, they have to copy it into [`CSRF_HEADER`].
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Refresh tokens are only ever sent back to the auth routes.
+const REFRESH_TOKEN_PATH: &str = "/auth";
+
+/// Attributes of the cookies browser clients get their tokens in, so scripts never see
+/// them. Cookies are always `HttpOnly` but the CSRF one.
+#[derive(Debug, Clone)]
+pub struct CookieSettings {
+    pub secure: bool,
+    pub same_site: SameSite,
+    pub domain: Option<String>,
+    /// How long the refresh token and CSRF cookies are kept, the lifetime of the refresh
+    /// token itself.
+    pub session_lifetime: chrono::Duration,
+}
+
+impl Default for CookieSettings {
+    fn default() -> Self {
+        CookieSettings {
+            secure: true,
+            same_site: SameSite::Strict,
+            domain: None,
+            session_lifetime: chrono::Duration::days(30),
+        }
+    }
+}
+
+impl CookieSettings {
+    /// `AUTH_COOKIE_SECURE` should only be turned off to try things out over plain HTTP.
+    pub fn from_env() -> Result<CookieSettings, String> {
+        let default = CookieSettings::default();
+        let same_site = match env::var("AUTH_COOKIE_SAME_SITE") {
+            Ok(value) => match value.to_lowercase().as_str() {
+                "strict" => SameSite::Strict,
+                "lax" => SameSite::Lax,
+                "none" => SameSite::None,
+                _ => {
+                    return Err(
+                        "AUTH_COOKIE_SAME_SITE must be one of strict, lax or none".to_string()
+                    )
+                }
+            },
+            Err(_) => default.same_site,
+        };
+        let settings = CookieSettings {
+            secure: env_var_or("AUTH_COOKIE_SECURE", default.secure),
+            same_site,
+            domain: env::var("AUTH_COOKIE_DOMAIN").ok(),
+            session_lifetime: chrono::Duration::days(env_var_or(
+                "REFRESH_TOKEN_LIFETIME_DAYS",
+                default.session_lifetime.num_days(),
+            )),
+        };
+        if settings.same_site == SameSite::None && !settings.secure {
+            return Err("AUTH_COOKIE_SAME_SITE=none requires AUTH_COOKIE_SECURE".to_string());
+        }
+        Ok(settings)
+    }
+
+    /// The cookies handing out a freshly issued access token, refresh token and CSRF
+    /// token.
+    pub fn session_cookies(
+        &self,
+        access_token: String,
+        access_token_lifetime: chrono::Duration,
+        refresh_token: String,
+        csrf_token: String,
+    ) -> Vec<Cookie<'static>> {
+        vec![
+            self.cookie(
+                ACCESS_TOKEN_COOKIE,
+                access_token,
+                "/",
+                true,
+                access_token_lifetime,
+            ),
+            self.cookie(
+                REFRESH_TOKEN_COOKIE,
+                refresh_token,
+                REFRESH_TOKEN_PATH,
+                true,
+                self.session_lifetime,
+            ),
+            self.cookie(CSRF_COOKIE, csrf_token, "/", false, self.session_lifetime),
+        ]
+    }
+
+    /// Cookies telling the browser to drop the ones set by
+    /// [`CookieSettings::session_cookies`].
+    pub fn removal_cookies(&self) -> Vec<Cookie<'static>> {
+        [
+            (ACCESS_TOKEN_COOKIE, "/", true),
+            (REFRESH_TOKEN_COOKIE, REFRESH_TOKEN_PATH, true),
+            (CSRF_COOKIE, "/", false),
+        ]
+        .into_iter()
+        .map(|(name, path, http_only)| {
+            let mut cookie = self.cookie(
+                name,
+                String::new(),
+                path,
+                http_only,
+                chrono::Duration::zero(),
+            );
+            cookie.make_removal();
+            cookie
+        })
+        .collect()
+    }
+
+    fn cookie(
+        &self,
+        name: &'static str,
+        value: String,
+        path: &'static str,
+        http_only: bool,
+        max_age: chrono::Duration,
+    ) -> Cookie<'static> {
+        let mut cookie = Cookie::build(name, value)
+            .path(path)
+            .http_only(http_only)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .max_age(time::Duration::seconds(max_age.num_seconds()))
+            .finish();
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+}
+
+/// Checks the double-submit CSRF token of a request authenticated by cookie: another
+/// site can make the browser send the cookies, but cannot read them to copy the CSRF
+/// one into the header.
+pub fn check_csrf(req: &HttpRequest) -> Result<(), AppError> {
+    let invalid_csrf_token = || AppError::forbidden("Invalid or missing CSRF token".to_string());
+
+    let cookie = req.cookie(CSRF_COOKIE).ok_or_else(invalid_csrf_token)?;
+    let header = req
+        .headers()
+        .get(CSRF_HEADER)
+        .ok_or_else(invalid_csrf_token)?;
+
+    if cookie.value().is_empty() || !bool::from(cookie.value().as_bytes().ct_eq(header.as_bytes()))
+    {
+        return Err(invalid_csrf_token());
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum TokenDeliveryMode {
+    #[default]
+    Body,
+    Cookie,
+}
+
+#[derive(Deserialize)]
+struct TokenDeliveryQuery {
+    #[serde(default)]
+    mode: TokenDeliveryMode,
+}
+
+/// How the routes issuing tokens hand them out, picked by the client with the `mode`
+/// query parameter: in the JSON body by default, or in cookies with `?mode=cookie`.
+pub enum TokenDelivery {
+    Body,
+    Cookie(web::Data<CookieSettings>),
+}
+
+impl FromRequest for TokenDelivery {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(token_delivery(req))
+    }
+}
+
+fn token_delivery(req: &HttpRequest) -> Result<TokenDelivery, AppError> {
+    let query = web::Query::<TokenDeliveryQuery>::from_query(req.query_string())
+        .map_err(|_| AppError::bad_request("mode must be either body or cookie".to_string()))?;
+
+    match query.mode {
+        TokenDeliveryMode::Body => Ok(TokenDelivery::Body),
+        TokenDeliveryMode::Cookie => {
+            let settings = req
+                .app_data::<web::Data<CookieSettings>>()
+                .ok_or_else(|| AppError::internal("Cookies are not configured".to_string()))?;
+            Ok(TokenDelivery::Cookie(settings.clone()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{cookie::Cookie, test::TestRequest, ResponseError};
+
+    use super::*;
+
+    #[test]
+    fn accepts_matching_csrf_token() {
+        let req = TestRequest::post()
+            .cookie(Cookie::new(CSRF_COOKIE, "token"))
+            .insert_header((CSRF_HEADER, "token"))
+            .to_http_request();
+
+        assert!(check_csrf(&req).is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_or_mismatched_csrf_token() {
+        let missing_header = TestRequest::post()
+            .cookie(Cookie::new(CSRF_COOKIE, "token"))
+            .to_http_request();
+        let mismatched_header = TestRequest::post()
+            .cookie(Cookie::new(CSRF_COOKIE, "token"))
+            .insert_header((CSRF_HEADER, "other"))
+            .to_http_request();
+
+        assert_eq!(check_csrf(&missing_header).unwrap_err().status_code(), 403);
+        assert_eq!(
+            check_csrf(&mismatched_header).unwrap_err().status_code(),
+            403
+        );
+    }
+
+    #[test]
+    fn keeps_csrf_cookie_readable_by_scripts() {
+        let cookies = CookieSettings::default().session_cookies(
+            "access".to_string(),
+            chrono::Duration::minutes(5),
+            "refresh".to_string(),
+            "csrf".to_string(),
+        );
+
+        for cookie in &cookies {
+            assert_eq!(cookie.http_only(), Some(cookie.name() != CSRF_COOKIE));
+            assert_eq!(cookie.secure(), Some(true));
+            assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+        }
+        assert_eq!(cookies[1].path(), Some(REFRESH_TOKEN_PATH));
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures_util::future::LocalBoxFuture;
+
+use crate::{domain::user::Role, error::AppError};
+
+use super::AuthenticatedUser;
+
+/// Middleware restricting a scope or resource to authenticated users holding at least
+/// `role`, e.g. `web::scope("/admin").wrap(RequireRole::new(Role::Admin))`.
+/// Anonymous requests get 401, users with a lower role 403.
+pub struct RequireRole {
+    role: Role,
+}
+
+impl RequireRole {
+    pub fn new(role: Role) -> RequireRole {
+        RequireRole { role }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireRole
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireRoleMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireRoleMiddleware {
+            service: Rc::new(service),
+            role: self.role,
+        }))
+    }
+}
+
+pub struct RequireRoleMiddleware<S> {
+    service: Rc<S>,
+    role: Role,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireRoleMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let role = self.role;
+
+        Box::pin(async move {
+            let authorized = match req.extract::<AuthenticatedUser>().await {
+                Ok(user) if user.has_role(role) => Ok(()),
+                Ok(_) => Err(AppError::forbidden(
+                    "You do not have permission to perform this action".to_string(),
+                )),
+                Err(error) => Err(error),
+            };
+
+            match authorized {
+                Ok(()) => service
+                    .call(req)
+                    .await
+                    .map(ServiceResponse::map_into_left_body),
+                Err(error) => Ok(req.error_response(error).map_into_right_body()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use actix_web::{http::header, test, web, App, HttpResponse};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        auth::{create_jwt, keys::JwtKeys},
+        handlers::token_revocation::{DynTokenRevocationHandler, MockTokenRevocationHandler},
+    };
+
+    async fn status_for(role: Option<Role>) -> u16 {
+        let mut revocation_handler = MockTokenRevocationHandler::new();
+        revocation_handler
+            .expect_is_token_revoked()
+            .returning(|_, _, _, _| Ok(false));
+        let revocation_handler: Arc<DynTokenRevocationHandler> = Arc::new(revocation_handler);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(JwtKeys::from_secret(b"secret")))
+                .app_data(web::Data::from(revocation_handler))
+                .service(
+                    web::resource("/admin")
+                        .wrap(RequireRole::new(Role::Admin))
+                        .to(HttpResponse::Ok),
+                ),
+        )
+        .await;
+
+        let mut req = test::TestRequest::get().uri("/admin");
+        if let Some(role) = role {
+            let token =
+                create_jwt(Uuid::new_v4(), role, None, &JwtKeys::from_secret(b"secret")).unwrap();
+            req = req.insert_header((header::AUTHORIZATION, format!("Bearer {token}")));
+        }
+
+        test::call_service(&app, req.to_request())
+            .await
+            .status()
+            .as_u16()
+    }
+
+    #[actix_web::test]
+    async fn rejects_anonymous_request() {
+        assert_eq!(status_for(None).await, 401);
+    }
+
+    #[actix_web::test]
+    async fn rejects_insufficient_role() {
+        assert_eq!(status_for(Some(Role::Moderator)).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn allows_required_role() {
+        assert_eq!(status_for(Some(Role::Admin)).await, 200);
+    }
+}
@@ -0,0 +1,351 @@
+use std::{env, fs, str::FromStr};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{
+    pkcs8::{EncodePrivateKey, KeypairBytes},
+    SigningKey, VerifyingKey,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    traits::PublicKeyParts,
+    RsaPrivateKey, RsaPublicKey,
+};
+use serde::Serialize;
+
+use super::JwtSettings;
+
+const DEFAULT_KEY_ID: &str = "default";
+
+/// A single signing or verification key. Keys loaded from a public PEM can only verify
+/// tokens, which is how a retired key is kept around until the tokens it signed expire.
+pub struct JwtKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    encoding_key: Option<EncodingKey>,
+    decoding_key: DecodingKey,
+    jwk: Option<Jwk>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Jwk {
+    kty: &'static str,
+    #[serde(rename = "use")]
+    key_use: &'static str,
+    kid: String,
+    alg: Algorithm,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+impl JwtKey {
+    pub fn from_secret(kid: &str, secret: &[u8]) -> JwtKey {
+        JwtKey {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS512,
+            encoding_key: Some(EncodingKey::from_secret(secret)),
+            decoding_key: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    /// Builds a key from a PEM encoded RSA or Ed25519 key. Private keys (PKCS#8, or PKCS#1
+    /// for RSA) can sign and verify, public keys can only verify.
+    pub fn from_pem(kid: &str, algorithm: Algorithm, pem: &str) -> Result<JwtKey, String> {
+        let is_private = pem.contains("PRIVATE KEY-----");
+
+        match algorithm {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => {
+                let public_key = if is_private {
+                    RsaPrivateKey::from_pkcs8_pem(pem)
+                        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+                        .map(|private_key| private_key.to_public_key())
+                        .map_err(|e| format!("Invalid RSA private key {kid}: {e}"))?
+                } else {
+                    RsaPublicKey::from_public_key_pem(pem)
+                        .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+                        .map_err(|e| format!("Invalid RSA public key {kid}: {e}"))?
+                };
+                let n = URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be());
+                let e = URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be());
+
+                let encoding_key = is_private
+                    .then(|| EncodingKey::from_rsa_pem(pem.as_bytes()))
+                    .transpose()
+                    .map_err(|e| format!("Invalid RSA private key {kid}: {e}"))?;
+                let decoding_key = DecodingKey::from_rsa_components(&n, &e)
+                    .map_err(|e| format!("Invalid RSA public key {kid}: {e}"))?;
+
+                Ok(JwtKey {
+                    kid: kid.to_string(),
+                    algorithm,
+                    encoding_key,
+                    decoding_key,
+                    jwk: Some(Jwk {
+                        kty: "RSA",
+                        key_use: "sig",
+                        kid: kid.to_string(),
+                        alg: algorithm,
+                        n: Some(n),
+                        e: Some(e),
+                        crv: None,
+                        x: None,
+                    }),
+                })
+            }
+            Algorithm::EdDSA => {
+                let (public_key, encoding_key) = if is_private {
+                    let signing_key = SigningKey::from_pkcs8_pem(pem)
+                        .map_err(|e| format!("Invalid Ed25519 private key {kid}: {e}"))?;
+                    // The signing backend only accepts PKCS#8 v1, so keys that embed their
+                    // public half are re-encoded without it.
+                    let der = KeypairBytes {
+                        secret_key: signing_key.to_bytes(),
+                        public_key: None,
+                    }
+                    .to_pkcs8_der()
+                    .map_err(|e| format!("Invalid Ed25519 private key {kid}: {e}"))?;
+                    (
+                        signing_key.verifying_key(),
+                        Some(EncodingKey::from_ed_der(der.as_bytes())),
+                    )
+                } else {
+                    let public_key = VerifyingKey::from_public_key_pem(pem)
+                        .map_err(|e| format!("Invalid Ed25519 public key {kid}: {e}"))?;
+                    (public_key, None)
+                };
+                let x = URL_SAFE_NO_PAD.encode(public_key.as_bytes());
+
+                let decoding_key = DecodingKey::from_ed_components(&x)
+                    .map_err(|e| format!("Invalid Ed25519 public key {kid}: {e}"))?;
+
+                Ok(JwtKey {
+                    kid: kid.to_string(),
+                    algorithm,
+                    encoding_key,
+                    decoding_key,
+                    jwk: Some(Jwk {
+                        kty: "OKP",
+                        key_use: "sig",
+                        kid: kid.to_string(),
+                        alg: algorithm,
+                        n: None,
+                        e: None,
+                        crv: Some("Ed25519"),
+                        x: Some(x),
+                    }),
+                })
+            }
+            _ => Err(format!(
+                "Unsupported algorithm {algorithm:?} for key {kid}, expected RS*, PS* or EdDSA"
+            )),
+        }
+    }
+
+    pub fn encoding_key(&self) -> Option<&EncodingKey> {
+        self.encoding_key.as_ref()
+    }
+
+    pub fn decoding_key(&self) -> &DecodingKey {
+        &self.decoding_key
+    }
+}
+
+/// Every key accepted when verifying tokens, and the one currently used to sign them.
+///
+/// To rotate, add the new key, point `JWT_SIGNING_KEY_ID` at it and keep the previous
+/// one listed (its public half is enough) until the tokens it signed have expired.
+pub struct JwtKeys {
+    keys: Vec<JwtKey>,
+    signing_key_index: usize,
+    settings: JwtSettings,
+}
+
+impl JwtKeys {
+    pub fn new(keys: Vec<JwtKey>, signing_kid: &str) -> Result<JwtKeys, String> {
+        let signing_key_index = keys
+            .iter()
+            .position(|key| key.kid == signing_kid)
+            .ok_or_else(|| format!("Signing key {signing_kid} is not configured"))?;
+
+        if keys[signing_key_index].encoding_key.is_none() {
+            return Err(format!("Signing key {signing_kid} has no private key"));
+        }
+
+        Ok(JwtKeys {
+            keys,
+            signing_key_index,
+            settings: JwtSettings::default(),
+        })
+    }
+
+    pub fn from_secret(secret: &[u8]) -> JwtKeys {
+        JwtKeys {
+            keys: vec![JwtKey::from_secret(DEFAULT_KEY_ID, secret)],
+            signing_key_index: 0,
+            settings: JwtSettings::default(),
+        }
+    }
+
+    pub fn with_settings(self, settings: JwtSettings) -> JwtKeys {
+        JwtKeys { settings, ..self }
+    }
+
+    pub fn settings(&self) -> &JwtSettings {
+        &self.settings
+    }
+
+    /// Loads keys listed in `JWT_KEYS` as comma separated `kid:algorithm:pem_path` entries,
+    /// signing with `JWT_SIGNING_KEY_ID` (the first private key by default). Falls back to
+    /// a HS512 key derived from `JWT_ENCODING_SECRET` when `JWT_KEYS` is not set.
+    pub fn from_env() -> Result<JwtKeys, String> {
+        let Ok(key_list) = env::var("JWT_KEYS") else {
+            let jwt_secret = env::var("JWT_ENCODING_SECRET")
+                .map_err(|_| "Neither JWT_KEYS nor JWT_ENCODING_SECRET set in .env file")?;
+            return Ok(JwtKeys::from_secret(jwt_secret.as_bytes()));
+        };
+
+        let mut keys = vec![];
+        for entry in key_list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut parts = entry.splitn(3, ':');
+            let (Some(kid), Some(algorithm), Some(path)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(format!(
+                    "Invalid JWT_KEYS entry {entry}, expected kid:algorithm:path"
+                ));
+            };
+            let algorithm = Algorithm::from_str(algorithm)
+                .map_err(|_| format!("Unknown algorithm {algorithm} for key {kid}"))?;
+            let pem = fs::read_to_string(path)
+                .map_err(|e| format!("Could not read key {kid} from {path}: {e}"))?;
+            keys.push(JwtKey::from_pem(kid, algorithm, &pem)?);
+        }
+
+        let signing_kid = match env::var("JWT_SIGNING_KEY_ID") {
+            Ok(kid) => kid,
+            Err(_) => keys
+                .iter()
+                .find(|key| key.encoding_key.is_some())
+                .map(|key| key.kid.clone())
+                .ok_or("JWT_KEYS does not contain any private key")?,
+        };
+
+        JwtKeys::new(keys, &signing_kid)
+    }
+
+    pub fn signing_key(&self) -> &JwtKey {
+        &self.keys[self.signing_key_index]
+    }
+
+    /// Finds the key a token was signed with. Tokens without a `kid` predate key
+    /// rotation and are checked against the current signing key.
+    pub fn verification_key(&self, kid: Option<&str>) -> Option<&JwtKey> {
+        match kid {
+            Some(kid) => self.keys.iter().find(|key| key.kid == kid),
+            None => Some(self.signing_key()),
+        }
+    }
+
+    /// Public keys that other services can use to verify our tokens. Shared secrets are
+    /// never published.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().filter_map(|key| key.jwk.clone()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, EncodePublicKey};
+
+    use super::*;
+
+    fn ed25519_pems(seed: u8) -> (String, String) {
+        let signing_key = SigningKey::from_bytes(&[seed; 32]);
+        let private_pem = signing_key
+            .to_pkcs8_pem(LineEnding::LF)
+            .unwrap()
+            .to_string();
+        let public_pem = signing_key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        (private_pem, public_pem)
+    }
+
+    #[test]
+    fn publishes_only_asymmetric_keys() {
+        let (private_pem, _) = ed25519_pems(1);
+        let keys = JwtKeys::new(
+            vec![
+                JwtKey::from_pem("current", Algorithm::EdDSA, &private_pem).unwrap(),
+                JwtKey::from_secret("legacy", b"secret"),
+            ],
+            "current",
+        )
+        .unwrap();
+
+        let jwks = keys.jwks();
+
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(jwks.keys[0].kid, "current");
+        assert_eq!(jwks.keys[0].crv, Some("Ed25519"));
+    }
+
+    #[test]
+    fn verifies_with_public_half_of_signing_key() {
+        let (private_pem, public_pem) = ed25519_pems(2);
+        let signing_key = JwtKey::from_pem("current", Algorithm::EdDSA, &private_pem).unwrap();
+        let verifying_key = JwtKey::from_pem("current", Algorithm::EdDSA, &public_pem).unwrap();
+
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(Algorithm::EdDSA),
+            &serde_json::json!({ "sub": "user", "exp": usize::MAX }),
+            signing_key.encoding_key().unwrap(),
+        )
+        .unwrap();
+
+        let decoded = jsonwebtoken::decode::<serde_json::Value>(
+            &token,
+            verifying_key.decoding_key(),
+            &jsonwebtoken::Validation::new(Algorithm::EdDSA),
+        );
+
+        assert!(decoded.is_ok());
+    }
+
+    #[test]
+    fn does_not_sign_with_public_key() {
+        let (_, public_pem) = ed25519_pems(1);
+        let key = JwtKey::from_pem("retired", Algorithm::EdDSA, &public_pem).unwrap();
+
+        assert!(key.encoding_key().is_none());
+        assert!(JwtKeys::new(vec![key], "retired").is_err());
+    }
+
+    #[test]
+    fn rejects_symmetric_algorithm_for_pem_key() {
+        let (private_pem, _) = ed25519_pems(1);
+
+        assert!(JwtKey::from_pem("current", Algorithm::HS256, &private_pem).is_err());
+    }
+}
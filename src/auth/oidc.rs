@@ -0,0 +1,294 @@
+use std::env;
+
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use reqwest::Url;
+use serde::Deserialize;
+use tokio::sync::{OnceCell, RwLock};
+
+use crate::repositories::error::{
+    ErrorMessage::InvalidIdToken,
+    RepositoryError::{self, IdentityProvider, InvalidToken},
+};
+
+/// Seconds of clock skew tolerated on the ID tokens of the provider.
+const ID_TOKEN_LEEWAY: u64 = 60;
+/// The JWKS is fetched again when a token is signed with a key it does not list, to pick
+/// up rotated keys, but not more often than this.
+const JWKS_REFRESH_INTERVAL_SECONDS: i64 = 60;
+
+/// The identity provider users can sign in with, as registered with it.
+#[derive(Debug, Clone)]
+pub struct OidcSettings {
+    /// Where the discovery document is read from, and the `iss` of its ID tokens.
+    pub issuer_url: String,
+    pub client_id: String,
+    /// Sent as `client_secret_post`. Public clients rely on PKCE alone.
+    pub client_secret: Option<String>,
+    /// The page of the frontend the provider sends users back to, which hands the code
+    /// and state over to the callback route.
+    pub redirect_url: String,
+    pub scopes: String,
+}
+
+impl OidcSettings {
+    /// Returns `None` when `OIDC_ISSUER_URL` is not set, leaving OpenID Connect sign-in
+    /// off.
+    pub fn from_env() -> Result<Option<OidcSettings>, String> {
+        let Ok(issuer_url) = env::var("OIDC_ISSUER_URL") else {
+            return Ok(None);
+        };
+        let required = |name: &str| {
+            env::var(name).map_err(|_| format!("{name} must be set along with OIDC_ISSUER_URL"))
+        };
+        Ok(Some(OidcSettings {
+            issuer_url: issuer_url.trim_end_matches('/').to_string(),
+            client_id: required("OIDC_CLIENT_ID")?,
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_url: required("OIDC_REDIRECT_URL")?,
+            scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+        }))
+    }
+}
+
+/// The parts of the discovery document the relying party needs.
+#[derive(Deserialize, Debug)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The claims of a validated ID token the sign-in relies on.
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub azp: Option<String>,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub name: Option<String>,
+}
+
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: DateTime<Utc>,
+}
+
+/// Talks to the identity provider: builds the authorization URL, exchanges codes and
+/// validates ID tokens. The discovery document is read once, the JWKS cached until a
+/// token refers to a key it does not hold.
+pub struct OidcProvider {
+    settings: OidcSettings,
+    http: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+    jwks: RwLock<Option<CachedJwks>>,
+}
+
+impl OidcProvider {
+    pub fn new(settings: OidcSettings) -> OidcProvider {
+        OidcProvider {
+            settings,
+            http: reqwest::Client::new(),
+            metadata: OnceCell::new(),
+            jwks: RwLock::new(None),
+        }
+    }
+
+    /// Where to send the user to sign in at the provider.
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_challenge: &str,
+    ) -> Result<String, RepositoryError> {
+        let metadata = self.metadata().await?;
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.settings.client_id),
+                ("redirect_uri", &self.settings.redirect_url),
+                ("scope", &self.settings.scopes),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| IdentityProvider(format!("invalid authorization endpoint: {e}")))?;
+        Ok(url.into())
+    }
+
+    /// Redeems the authorization code, returning the raw ID token.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<String, RepositoryError> {
+        let metadata = self.metadata().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.settings.redirect_url),
+            ("client_id", &self.settings.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &self.settings.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| IdentityProvider(e.to_string()))?;
+        // A rejected code is the user's problem, not the provider's.
+        if response.status().is_client_error() {
+            return Err(InvalidToken(InvalidIdToken));
+        }
+        let response: TokenResponse = response
+            .error_for_status()
+            .map_err(|e| IdentityProvider(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| IdentityProvider(format!("invalid token response: {e}")))?;
+        Ok(response.id_token)
+    }
+
+    /// Checks the signature against the JWKS of the provider, then the issuer, audience,
+    /// expiry and nonce. Symmetric algorithms are refused since the client secret is
+    /// optional and shared.
+    pub async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, RepositoryError> {
+        let metadata = self.metadata().await?;
+
+        let header = decode_header(id_token).map_err(|_| InvalidToken(InvalidIdToken))?;
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(InvalidToken(InvalidIdToken));
+        }
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.settings.client_id]);
+        validation.set_required_spec_claims(&["iss", "aud", "sub", "exp", "iat"]);
+        validation.leeway = ID_TOKEN_LEEWAY;
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|_| InvalidToken(InvalidIdToken))?
+            .claims;
+
+        let nonce_matches = claims.nonce.as_deref() == Some(nonce);
+        let azp_matches = claims
+            .azp
+            .as_ref()
+            .is_none_or(|azp| *azp == self.settings.client_id);
+        if !nonce_matches || !azp_matches {
+            return Err(InvalidToken(InvalidIdToken));
+        }
+        Ok(claims)
+    }
+
+    async fn metadata(&self) -> Result<&ProviderMetadata, RepositoryError> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.settings.issuer_url
+                );
+                let metadata: ProviderMetadata = self.get_json(&url).await?;
+                // The document must describe the provider it was fetched from.
+                if metadata.issuer.trim_end_matches('/') != self.settings.issuer_url {
+                    return Err(IdentityProvider(format!(
+                        "discovery document is for issuer {}",
+                        metadata.issuer
+                    )));
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey, RepositoryError> {
+        if let Some(key) = self.cached_key(kid).await? {
+            return Ok(key);
+        }
+
+        let mut jwks = self.jwks.write().await;
+        let is_stale = jwks.as_ref().is_none_or(|cached| {
+            Utc::now() - cached.fetched_at
+                > chrono::Duration::seconds(JWKS_REFRESH_INTERVAL_SECONDS)
+        });
+        if is_stale {
+            let metadata = self.metadata().await?;
+            *jwks = Some(CachedJwks {
+                keys: self.get_json(&metadata.jwks_uri).await?,
+                fetched_at: Utc::now(),
+            });
+        }
+        drop(jwks);
+
+        self.cached_key(kid)
+            .await?
+            .ok_or(InvalidToken(InvalidIdToken))
+    }
+
+    /// Without a `kid`, the key is only picked when the provider has a single one.
+    async fn cached_key(&self, kid: Option<&str>) -> Result<Option<DecodingKey>, RepositoryError> {
+        let jwks = self.jwks.read().await;
+        let Some(cached) = jwks.as_ref() else {
+            return Ok(None);
+        };
+        let jwk = match kid {
+            Some(kid) => cached.keys.find(kid),
+            None if cached.keys.keys.len() == 1 => cached.keys.keys.first(),
+            None => None,
+        };
+        match jwk {
+            Some(jwk) if matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_)) => {
+                Err(InvalidToken(InvalidIdToken))
+            }
+            Some(jwk) => DecodingKey::from_jwk(jwk)
+                .map(Some)
+                .map_err(|e| IdentityProvider(format!("invalid JWKS key: {e}"))),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        url: &str,
+    ) -> Result<T, RepositoryError> {
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| IdentityProvider(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| IdentityProvider(format!("invalid response from {url}: {e}")))
+    }
+}
@@ -0,0 +1,115 @@
+//! Time-based one-time passwords as described in RFC 6238, with the defaults every
+//! authenticator app understands: HMAC-SHA1, six digits and a 30 second step.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+const DIGITS: u32 = 6;
+const STEP_SECONDS: i64 = 30;
+/// Number of steps accepted on either side of the current one to allow for clock drift.
+const SKEW_STEPS: i64 = 1;
+const SECRET_LENGTH: usize = 20;
+
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// Authenticator apps expect secrets typed in by hand in unpadded base32.
+pub fn encode_secret(secret: &[u8]) -> String {
+    BASE32_NOPAD.encode(secret)
+}
+
+pub fn otpauth_uri(issuer: &str, account_name: &str, secret: &[u8]) -> String {
+    let issuer = utf8_percent_encode(issuer, NON_ALPHANUMERIC).to_string();
+    let account_name = utf8_percent_encode(account_name, NON_ALPHANUMERIC);
+    format!(
+        "otpauth://totp/{issuer}:{account_name}?secret={}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        encode_secret(secret)
+    )
+}
+
+fn code_at_step(secret: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// Checks `code` against the steps around `now` and returns the step it matched. Steps
+/// up to `last_used_step` are skipped so a code cannot be replayed.
+pub fn verify_code(
+    secret: &[u8],
+    code: &str,
+    now: DateTime<Utc>,
+    last_used_step: Option<i64>,
+) -> Option<i64> {
+    let current_step = now.timestamp() / STEP_SECONDS;
+    (current_step - SKEW_STEPS..=current_step + SKEW_STEPS)
+        .filter(|step| last_used_step.is_none_or(|last_used_step| *step > last_used_step))
+        .find(|step| {
+            code_at_step(secret, *step)
+                .as_bytes()
+                .ct_eq(code.trim().as_bytes())
+                .into()
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn matches_rfc_6238_test_vectors() {
+        assert_eq!(code_at_step(RFC_SECRET, 59 / STEP_SECONDS), "287082");
+        assert_eq!(
+            code_at_step(RFC_SECRET, 1111111109 / STEP_SECONDS),
+            "081804"
+        );
+        assert_eq!(
+            code_at_step(RFC_SECRET, 2000000000 / STEP_SECONDS),
+            "279037"
+        );
+    }
+
+    #[test]
+    fn accepts_previous_step_but_not_replayed_code() {
+        let now = Utc.timestamp_opt(1111111109, 0).unwrap();
+        let previous_step = now.timestamp() / STEP_SECONDS - 1;
+        let code = code_at_step(RFC_SECRET, previous_step);
+
+        assert_eq!(
+            verify_code(RFC_SECRET, &code, now, None),
+            Some(previous_step)
+        );
+        assert_eq!(
+            verify_code(RFC_SECRET, &code, now, Some(previous_step)),
+            None
+        );
+    }
+
+    #[test]
+    fn builds_otpauth_uri() {
+        let uri = otpauth_uri("users actix", "john@example.com", RFC_SECRET);
+
+        assert!(uri.starts_with("otpauth://totp/users%20actix:john%40example%2Ecom?secret="));
+        assert!(uri.contains("issuer=users%20actix"));
+    }
+}
@@ -0,0 +1,68 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::repositories::error::RepositoryError;
+
+/// A pending change of the email of `user_id` to `new_email`, applied once the link sent
+/// to the new address is followed.
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct EmailChangeRequest {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub new_email: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait EmailChangeRepository {
+    /// Stores a new request, replacing any pending one of the user.
+    async fn create_request(
+        &self,
+        user_id: Uuid,
+        new_email: String,
+        token_hash: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError>;
+    async fn get_request_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<EmailChangeRequest>, RepositoryError>;
+    /// Consumes the request and sets its email on the user, marked verified, in one
+    /// transaction. Returns `false` if the request was already used, and fails with
+    /// [`RepositoryError::Conflict`] if another account took the address meanwhile.
+    async fn apply_request(&self, request: EmailChangeRequest) -> Result<bool, RepositoryError>;
+}
+
+pub mod payload {
+    use serde::{Deserialize, Serialize};
+    use validator::Validate;
+
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct EmailChangePayload {
+        #[validate(length(min = 1))]
+        pub password: String,
+        #[validate(email)]
+        pub new_email: String,
+    }
+
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    pub struct EmailChangeConfirmPayload {
+        #[validate(length(min = 1))]
+        pub token: String,
+    }
+}
+
+pub mod mocks {
+    use super::*;
+
+    factori::factori!(EmailChangeRequest, {
+        default {
+            id = Uuid::new_v4(),
+            user_id = Uuid::new_v4(),
+            new_email = "new_johndoe@gmail.com".to_string(),
+            expires_at = Utc::now() + chrono::Duration::days(1),
+        }
+    });
+}
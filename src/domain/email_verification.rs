@@ -0,0 +1,93 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::repositories::error::RepositoryError;
+
+/// What unverified accounts are kept from doing, set with `EMAIL_VERIFICATION_POLICY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmailVerificationPolicy {
+    /// Verification is offered but not required.
+    #[default]
+    Optional,
+    /// Unverified users can sign in and read, but not change anything.
+    BlockWrites,
+    /// Unverified users cannot sign in at all.
+    BlockLogin,
+}
+
+impl FromStr for EmailVerificationPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "optional" => Ok(EmailVerificationPolicy::Optional),
+            "block_writes" => Ok(EmailVerificationPolicy::BlockWrites),
+            "block_login" => Ok(EmailVerificationPolicy::BlockLogin),
+            other => Err(format!(
+                "expected optional, block_writes or block_login, got {other}"
+            )),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct EmailVerificationToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub creation_time: DateTime<Utc>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait EmailVerificationRepository {
+    /// Stores a new token, replacing the one previously sent to the user.
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        token_hash: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError>;
+    async fn get_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<EmailVerificationToken>, RepositoryError>;
+    async fn get_token_by_user(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<EmailVerificationToken>, RepositoryError>;
+    /// Returns `false` if the token was already used.
+    async fn delete_token(&self, id: Uuid) -> Result<bool, RepositoryError>;
+}
+
+pub mod payload {
+    use serde::{Deserialize, Serialize};
+    use validator::Validate;
+
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    pub struct VerifyEmailPayload {
+        #[validate(length(min = 1))]
+        pub token: String,
+    }
+
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    pub struct ResendVerificationPayload {
+        #[validate(email)]
+        pub email: String,
+    }
+}
+
+pub mod mocks {
+    use super::*;
+
+    factori::factori!(EmailVerificationToken, {
+        default {
+            id = Uuid::new_v4(),
+            user_id = Uuid::new_v4(),
+            expires_at = Utc::now() + chrono::Duration::days(1),
+            creation_time = Utc::now(),
+        }
+    });
+}
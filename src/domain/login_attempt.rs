@@ -0,0 +1,37 @@
+use chrono::{DateTime, Utc};
+
+use crate::repositories::error::RepositoryError;
+
+/// Failed login counter for a throttling key, either an account (`account:<email>`) or
+/// a client address (`ip:<address>`).
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct LoginAttempt {
+    pub key: String,
+    pub failed_attempts: i32,
+    pub blocked_until: Option<DateTime<Utc>>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait LoginAttemptRepository {
+    async fn get_attempts(&self, keys: Vec<String>) -> Result<Vec<LoginAttempt>, RepositoryError>;
+    /// Increments the counter for `key` in one statement, starting over from one if the
+    /// previous attempt happened before `window_start`. A counter reaching `threshold` is
+    /// held back until `hold_until` on the way. Returns `None` without counting anything
+    /// while `key` is held back.
+    async fn start_attempt(
+        &self,
+        key: String,
+        threshold: i32,
+        window_start: DateTime<Utc>,
+        hold_until: DateTime<Utc>,
+    ) -> Result<Option<LoginAttempt>, RepositoryError>;
+    /// Decrements the counter for `key` and lifts its hold.
+    async fn take_back_attempt(&self, key: String) -> Result<(), RepositoryError>;
+    async fn set_blocked_until(
+        &self,
+        key: String,
+        blocked_until: DateTime<Utc>,
+    ) -> Result<(), RepositoryError>;
+    async fn delete_attempts(&self, key: String) -> Result<(), RepositoryError>;
+}
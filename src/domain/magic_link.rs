@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::repositories::error::RepositoryError;
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct MagicLinkToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub creation_time: DateTime<Utc>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait MagicLinkRepository {
+    /// Stores a new token, replacing the link previously sent to the user.
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        token_hash: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError>;
+    async fn get_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<MagicLinkToken>, RepositoryError>;
+    async fn get_token_by_user(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<MagicLinkToken>, RepositoryError>;
+    /// Returns `false` if the token was already used.
+    async fn delete_token(&self, id: Uuid) -> Result<bool, RepositoryError>;
+}
+
+pub mod payload {
+    use serde::{Deserialize, Serialize};
+    use validator::Validate;
+
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    pub struct MagicLinkRequestPayload {
+        #[validate(email)]
+        pub email: String,
+    }
+
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    pub struct MagicLinkConsumePayload {
+        #[validate(length(min = 1))]
+        pub token: String,
+    }
+}
+
+pub mod mocks {
+    use super::*;
+
+    factori::factori!(MagicLinkToken, {
+        default {
+            id = Uuid::new_v4(),
+            user_id = Uuid::new_v4(),
+            expires_at = Utc::now() + chrono::Duration::minutes(15),
+            creation_time = Utc::now(),
+        }
+    });
+}
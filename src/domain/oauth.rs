@@ -0,0 +1,234 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    domain::personal_access_token::Scope, repositories::error::RepositoryError, utils::serialize_dt,
+};
+
+/// A third-party app registered by a user to call the API on behalf of other users.
+/// Confidential clients authenticate with a secret and may also get tokens of their own
+/// through the client credentials grant, public ones rely on PKCE alone.
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuthClient {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub name: String,
+    /// Authorization requests must use one of these exactly.
+    pub redirect_uris: Vec<String>,
+    /// The most a token issued to the client can hold.
+    pub scopes: Vec<Scope>,
+    pub confidential: bool,
+    #[serde(skip)]
+    pub secret_hash: Option<String>,
+    #[serde(serialize_with = "serialize_dt")]
+    pub creation_time: DateTime<Utc>,
+}
+
+/// Handed to the client through its redirect URI once the user consented, and
+/// redeemed for an access token with the PKCE verifier.
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct OAuthAuthorizationCode {
+    pub client_id: Uuid,
+    pub user_id: Uuid,
+    pub redirect_uri: String,
+    pub scopes: Vec<Scope>,
+    pub code_challenge: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The consent of a user to a client, kept until they revoke it. Access tokens issued
+/// under it carry its id as their `sid`, so revoking it revokes them like a session.
+/// Consenting again after a revocation starts a new grant.
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuthGrant {
+    #[serde(skip)]
+    pub id: Uuid,
+    pub client_id: Uuid,
+    pub client_name: String,
+    /// The scopes of the latest consent.
+    pub scopes: Vec<Scope>,
+    #[serde(serialize_with = "serialize_dt")]
+    pub creation_time: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_dt")]
+    pub update_time: DateTime<Utc>,
+}
+
+/// The error codes of RFC 6749 the authorization server answers with.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum OAuthErrorCode {
+    InvalidRequest,
+    InvalidClient,
+    InvalidGrant,
+    UnauthorizedClient,
+    UnsupportedGrantType,
+    UnsupportedResponseType,
+    InvalidScope,
+    AccessDenied,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait OAuthRepository {
+    async fn create_client(
+        &self,
+        owner_id: Uuid,
+        payload: payload::NewOAuthClientPayload,
+        secret_hash: Option<String>,
+    ) -> Result<OAuthClient, RepositoryError>;
+    async fn get_clients_by_owner(
+        &self,
+        owner_id: Uuid,
+    ) -> Result<Vec<OAuthClient>, RepositoryError>;
+    async fn get_client(&self, id: Uuid) -> Result<Option<OAuthClient>, RepositoryError>;
+    /// Returns `false` if the user has no client with this id.
+    async fn delete_client(&self, owner_id: Uuid, id: Uuid) -> Result<bool, RepositoryError>;
+    async fn create_authorization_code(
+        &self,
+        code_hash: String,
+        authorization_code: OAuthAuthorizationCode,
+    ) -> Result<(), RepositoryError>;
+    /// Deletes the code and returns it, so that it can only be redeemed once.
+    async fn take_authorization_code(
+        &self,
+        code_hash: String,
+    ) -> Result<Option<OAuthAuthorizationCode>, RepositoryError>;
+    /// Records the consent, updating the scopes of the user's grant to the client if
+    /// there is one already. Returns the id of the grant.
+    async fn save_grant(
+        &self,
+        user_id: Uuid,
+        client_id: Uuid,
+        scopes: Vec<Scope>,
+    ) -> Result<Uuid, RepositoryError>;
+    async fn get_grant(
+        &self,
+        user_id: Uuid,
+        client_id: Uuid,
+    ) -> Result<Option<OAuthGrant>, RepositoryError>;
+    async fn get_grants_by_user(&self, user_id: Uuid) -> Result<Vec<OAuthGrant>, RepositoryError>;
+    /// Returns the id of the deleted grant, `None` if the user had none for the client.
+    async fn delete_grant(
+        &self,
+        user_id: Uuid,
+        client_id: Uuid,
+    ) -> Result<Option<Uuid>, RepositoryError>;
+}
+
+pub mod payload {
+    use reqwest::Url;
+    use serde::{Deserialize, Serialize};
+    use uuid::Uuid;
+    use validator::{Validate, ValidationError};
+
+    use super::Scope;
+
+    #[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct NewOAuthClientPayload {
+        #[validate(length(min = 1, max = 100))]
+        pub name: String,
+        #[validate(length(min = 1, max = 10), custom = "validate_redirect_uris")]
+        pub redirect_uris: Vec<String>,
+        #[validate(length(min = 1))]
+        pub scopes: Vec<Scope>,
+        /// Whether the client can keep a secret, like a server-side app.
+        #[serde(default)]
+        pub confidential: bool,
+    }
+
+    /// Codes must only travel over HTTPS, but to apps running on the user's machine.
+    fn validate_redirect_uris(redirect_uris: &Vec<String>) -> Result<(), ValidationError> {
+        for redirect_uri in redirect_uris {
+            let url = Url::parse(redirect_uri)
+                .map_err(|_| ValidationError::new("redirect URIs must be absolute URLs"))?;
+            let is_loopback = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+            if url.scheme() != "https" && !(url.scheme() == "http" && is_loopback) {
+                return Err(ValidationError::new(
+                    "redirect URIs must use https unless they point to the loopback interface",
+                ));
+            }
+            if url.fragment().is_some() {
+                return Err(ValidationError::new(
+                    "redirect URIs must not have a fragment",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The query of an authorization request, as sent by the client to the consent page
+    /// and passed on by it.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct AuthorizationRequest {
+        pub response_type: String,
+        pub client_id: Uuid,
+        pub redirect_uri: String,
+        /// Space separated, all the scopes of the client when missing.
+        pub scope: Option<String>,
+        pub state: Option<String>,
+        pub code_challenge: String,
+        pub code_challenge_method: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct AuthorizationDecisionPayload {
+        pub approved: bool,
+    }
+
+    /// The form posted to the token endpoint, for every grant type. Clients may also
+    /// authenticate with HTTP Basic instead of `client_id` and `client_secret`.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct TokenRequestPayload {
+        pub grant_type: String,
+        pub code: Option<String>,
+        pub redirect_uri: Option<String>,
+        pub code_verifier: Option<String>,
+        pub client_id: Option<String>,
+        pub client_secret: Option<String>,
+        pub scope: Option<String>,
+    }
+}
+
+pub mod mocks {
+    use super::*;
+
+    factori::factori!(OAuthClient, {
+        default {
+            id = Uuid::new_v4(),
+            owner_id = Uuid::new_v4(),
+            name = "Partner app".to_string(),
+            redirect_uris = vec!["https://partner.example.com/callback".to_string()],
+            scopes = vec![Scope::UsersRead, Scope::UsersWrite],
+            confidential = false,
+            secret_hash = None,
+            creation_time = Utc::now(),
+        }
+    });
+
+    factori::factori!(OAuthAuthorizationCode, {
+        default {
+            client_id = Uuid::new_v4(),
+            user_id = Uuid::new_v4(),
+            redirect_uri = "https://partner.example.com/callback".to_string(),
+            scopes = vec![Scope::UsersRead],
+            code_challenge = String::new(),
+            expires_at = Utc::now() + chrono::Duration::minutes(1),
+        }
+    });
+
+    factori::factori!(OAuthGrant, {
+        default {
+            id = Uuid::new_v4(),
+            client_id = Uuid::new_v4(),
+            client_name = "Partner app".to_string(),
+            scopes = vec![Scope::UsersRead],
+            creation_time = Utc::now(),
+            update_time = Utc::now(),
+        }
+    });
+}
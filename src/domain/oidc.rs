@@ -0,0 +1,73 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::repositories::error::RepositoryError;
+
+/// A sign-in started with the identity provider, waiting for the user to come back with
+/// an authorization code. Looked up by the hash of its `state`, the PKCE verifier and
+/// nonce are kept to finish it.
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct OidcLoginRequest {
+    pub nonce: String,
+    pub code_verifier: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A user account of the identity provider, linked to a local user. Looked up by its
+/// issuer and subject, only the local user is read back.
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct ExternalIdentity {
+    pub user_id: Uuid,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait OidcRepository {
+    async fn create_login_request(
+        &self,
+        state_hash: String,
+        nonce: String,
+        code_verifier: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError>;
+    /// Deletes and returns the request, so a `state` can only be used once.
+    async fn take_login_request(
+        &self,
+        state_hash: String,
+    ) -> Result<Option<OidcLoginRequest>, RepositoryError>;
+    async fn get_identity(
+        &self,
+        issuer: String,
+        subject: String,
+    ) -> Result<Option<ExternalIdentity>, RepositoryError>;
+    async fn create_identity(
+        &self,
+        user_id: Uuid,
+        issuer: String,
+        subject: String,
+    ) -> Result<(), RepositoryError>;
+}
+
+pub mod payload {
+    use serde::{Deserialize, Serialize};
+    use validator::Validate;
+
+    /// What the identity provider appended to the redirect URL.
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    pub struct OidcCallbackPayload {
+        #[validate(length(min = 1))]
+        pub code: String,
+        #[validate(length(min = 1))]
+        pub state: String,
+    }
+}
+
+pub mod mocks {
+    use super::*;
+
+    factori::factori!(ExternalIdentity, {
+        default {
+            user_id = Uuid::new_v4(),
+        }
+    });
+}
@@ -0,0 +1,281 @@
+use std::{collections::HashMap, env, fmt, fs};
+
+use data_encoding::HEXUPPER;
+use sha1::{Digest, Sha1};
+
+use crate::utils::env_var_or;
+
+/// Rules new passwords are checked against, on sign up, password change and reset.
+/// Existing passwords are never checked again, so tightening the policy does not lock
+/// anyone out.
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// From 0 to 4, see [`estimate_strength`].
+    pub min_strength: u8,
+    pub breached_passwords: Option<BreachedPasswords>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 128,
+            min_strength: 2,
+            breached_passwords: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordViolation {
+    TooShort(usize),
+    TooLong(usize),
+    TooWeak,
+    ContainsNickname,
+    ContainsEmail,
+    Breached,
+}
+
+impl fmt::Display for PasswordViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordViolation::TooShort(min) => write!(f, "must be at least {min} characters long"),
+            PasswordViolation::TooLong(max) => write!(f, "must be at most {max} characters long"),
+            PasswordViolation::TooWeak => write!(
+                f,
+                "is too easy to guess, make it longer or mix letters, digits and symbols"
+            ),
+            PasswordViolation::ContainsNickname => write!(f, "must not contain your nickname"),
+            PasswordViolation::ContainsEmail => write!(f, "must not contain your email"),
+            PasswordViolation::Breached => write!(
+                f,
+                "appeared in a data breach, choose one you have never used elsewhere"
+            ),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Result<PasswordPolicy, String> {
+        let default = PasswordPolicy::default();
+        let policy = PasswordPolicy {
+            min_length: env_var_or("PASSWORD_MIN_LENGTH", default.min_length),
+            max_length: env_var_or("PASSWORD_MAX_LENGTH", default.max_length),
+            min_strength: env_var_or("PASSWORD_MIN_STRENGTH", default.min_strength),
+            breached_passwords: match env::var("PASSWORD_BREACHED_LIST_FILE") {
+                Ok(path) => Some(BreachedPasswords::from_file(&path)?),
+                Err(_) => None,
+            },
+        };
+        if policy.min_length == 0 || policy.min_length > policy.max_length {
+            return Err(
+                "PASSWORD_MIN_LENGTH must be at least 1 and at most PASSWORD_MAX_LENGTH"
+                    .to_string(),
+            );
+        }
+        if policy.min_strength > 4 {
+            return Err("PASSWORD_MIN_STRENGTH must be between 0 and 4".to_string());
+        }
+        Ok(policy)
+    }
+
+    /// Returns every rule the password breaks, not just the first one.
+    pub fn check(
+        &self,
+        password: &str,
+        nickname: &str,
+        email: &str,
+    ) -> Result<(), Vec<PasswordViolation>> {
+        let mut violations = vec![];
+        let length = password.chars().count();
+        if length < self.min_length {
+            violations.push(PasswordViolation::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            violations.push(PasswordViolation::TooLong(self.max_length));
+        }
+        if estimate_strength(password) < self.min_strength {
+            violations.push(PasswordViolation::TooWeak);
+        }
+
+        let lowercase_password = password.to_lowercase();
+        if contains_identifier(&lowercase_password, nickname) {
+            violations.push(PasswordViolation::ContainsNickname);
+        }
+        let local_part = email.split('@').next().unwrap_or_default();
+        if contains_identifier(&lowercase_password, email)
+            || contains_identifier(&lowercase_password, local_part)
+        {
+            violations.push(PasswordViolation::ContainsEmail);
+        }
+
+        if self
+            .breached_passwords
+            .as_ref()
+            .is_some_and(|breached| breached.contains(password))
+        {
+            violations.push(PasswordViolation::Breached);
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// Identifiers shorter than 3 characters would match too many unrelated passwords.
+fn contains_identifier(lowercase_password: &str, identifier: &str) -> bool {
+    identifier.chars().count() >= 3 && lowercase_password.contains(&identifier.to_lowercase())
+}
+
+/// Scores a password from 0 (trivial) to 4 (very strong) by the bits of entropy it would
+/// have if drawn at random from the character classes it uses. Characters repeating or
+/// continuing a run of the previous ones (`aaa`, `abc`, `321`) only count for half.
+pub fn estimate_strength(password: &str) -> u8 {
+    let chars: Vec<char> = password.chars().collect();
+    let pool_size: u32 = [
+        (chars.iter().any(char::is_ascii_lowercase), 26),
+        (chars.iter().any(char::is_ascii_uppercase), 26),
+        (chars.iter().any(char::is_ascii_digit), 10),
+        (chars.iter().any(char::is_ascii_punctuation), 33),
+        (chars.iter().any(|c| !c.is_ascii_graphic()), 100),
+    ]
+    .iter()
+    .filter(|(used, _)| *used)
+    .map(|(_, size)| size)
+    .sum();
+    if pool_size == 0 {
+        return 0;
+    }
+
+    let effective_length: f64 = chars
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let predictable = i > 0 && (*c as i64 - chars[i - 1] as i64).abs() <= 1;
+            if predictable {
+                0.5
+            } else {
+                1.0
+            }
+        })
+        .sum();
+    let entropy = effective_length * (pool_size as f64).log2();
+
+    match entropy {
+        e if e < 28.0 => 0,
+        e if e < 36.0 => 1,
+        e if e < 60.0 => 2,
+        e if e < 128.0 => 3,
+        _ => 4,
+    }
+}
+
+/// Known breached passwords, as the uppercase hex SHA-1 of each, indexed by their first 5
+/// characters like the k-anonymity range queries of Have I Been Pwned.
+///
+/// The file holds one hash per line, optionally followed by `:count` as in the downloadable
+/// HIBP lists. Lines may also be truncated to a shorter prefix, at least 5 characters long,
+/// to trade a few false positives for a smaller file.
+pub struct BreachedPasswords {
+    ranges: HashMap<String, Vec<String>>,
+}
+
+const RANGE_PREFIX_LENGTH: usize = 5;
+
+impl BreachedPasswords {
+    pub fn from_file(path: &str) -> Result<BreachedPasswords, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read breached password list {path}: {e}"))?;
+        BreachedPasswords::parse(&content)
+            .map_err(|line| format!("Invalid line {line} in breached password list {path}"))
+    }
+
+    /// Fails with the number of the first invalid line.
+    fn parse(content: &str) -> Result<BreachedPasswords, usize> {
+        let mut ranges: HashMap<String, Vec<String>> = HashMap::new();
+        for (number, line) in content.lines().enumerate() {
+            let hash = line.split(':').next().unwrap_or_default().trim();
+            if hash.is_empty() {
+                continue;
+            }
+            if hash.len() < RANGE_PREFIX_LENGTH
+                || hash.len() > 40
+                || !hash.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                return Err(number + 1);
+            }
+            let hash = hash.to_ascii_uppercase();
+            let (prefix, suffix) = hash.split_at(RANGE_PREFIX_LENGTH);
+            ranges
+                .entry(prefix.to_string())
+                .or_default()
+                .push(suffix.to_string());
+        }
+        Ok(BreachedPasswords { ranges })
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        let hash = HEXUPPER.encode(&Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(RANGE_PREFIX_LENGTH);
+        self.ranges
+            .get(prefix)
+            .is_some_and(|suffixes| suffixes.iter().any(|s| suffix.starts_with(s.as_str())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reports_every_broken_rule() {
+        let policy = PasswordPolicy {
+            min_length: 12,
+            ..PasswordPolicy::default()
+        };
+
+        let result = policy.check("johndoe1", "johndoe", "johndoe@gmail.com");
+
+        assert_eq!(
+            result,
+            Err(vec![
+                PasswordViolation::TooShort(12),
+                PasswordViolation::ContainsNickname,
+                PasswordViolation::ContainsEmail,
+            ])
+        );
+    }
+
+    #[test]
+    fn scores_predictable_passwords_lower() {
+        assert_eq!(estimate_strength("aaaaaaaa"), 0);
+        assert_eq!(estimate_strength("12345678"), 0);
+        assert!(estimate_strength("correct horse battery staple") >= 3);
+        assert!(estimate_strength("Tr0ub4dor&3") >= 2);
+    }
+
+    #[test]
+    fn matches_breached_hashes_and_prefixes() {
+        // SHA-1 of "password" and a truncated SHA-1 of "123456".
+        let breached = BreachedPasswords::parse(
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493\n7c4a8d09\n",
+        )
+        .unwrap();
+
+        assert!(breached.contains("password"));
+        assert!(breached.contains("123456"));
+        assert!(!breached.contains("not in the list"));
+    }
+
+    #[test]
+    fn rejects_malformed_breached_list() {
+        assert_eq!(
+            BreachedPasswords::parse("5BAA61E4\nnot a hash\n").err(),
+            Some(2)
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::repositories::error::RepositoryError;
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct PasswordResetToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait PasswordResetRepository {
+    /// Stores a new token, dropping the tokens previously requested for the user so only
+    /// the latest mail works.
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        token_hash: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError>;
+    async fn get_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<PasswordResetToken>, RepositoryError>;
+    /// Marks the token used, sets the password and drops the other tokens of the user in
+    /// one transaction, so a failure leaves the token usable for another try. Returns
+    /// `false` if the token had already been used.
+    async fn reset_password(
+        &self,
+        token: PasswordResetToken,
+        password: String,
+    ) -> Result<bool, RepositoryError>;
+}
+
+pub mod payload {
+    use serde::{Deserialize, Serialize};
+    use validator::Validate;
+
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    pub struct PasswordResetRequestPayload {
+        #[validate(email)]
+        pub email: String,
+    }
+
+    /// `password` follows the same rules as in `NewUserPayload`.
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    pub struct PasswordResetConfirmPayload {
+        #[validate(length(min = 1))]
+        pub token: String,
+        pub password: String,
+    }
+}
+
+pub mod mocks {
+    use super::*;
+
+    factori::factori!(PasswordResetToken, {
+        default {
+            id = Uuid::new_v4(),
+            user_id = Uuid::new_v4(),
+            expires_at = Utc::now() + chrono::Duration::hours(1),
+            used_at = None,
+        }
+    });
+}
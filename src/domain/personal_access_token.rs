@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+use uuid::Uuid;
+
+use crate::{
+    repositories::error::RepositoryError,
+    utils::{serialize_dt, serialize_dt_option},
+};
+
+/// Marks a bearer token as a personal access token rather than a JWT.
+pub const TOKEN_MARKER: &str = "uat_";
+/// Length of the leading part of a token kept in clear so users can tell tokens apart.
+pub const TOKEN_PREFIX_LENGTH: usize = 12;
+
+#[derive(
+    sqlx::Type,
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    strum_macros::Display,
+    strum_macros::EnumString,
+)]
+#[sqlx(type_name = "token_scope")]
+pub enum Scope {
+    #[sqlx(rename = "users:read")]
+    #[serde(rename = "users:read")]
+    #[strum(serialize = "users:read")]
+    UsersRead,
+    #[sqlx(rename = "users:write")]
+    #[serde(rename = "users:write")]
+    #[strum(serialize = "users:write")]
+    UsersWrite,
+    /// Scraping `/metrics`. Only admins can put it on their personal access tokens.
+    #[sqlx(rename = "metrics:read")]
+    #[serde(rename = "metrics:read")]
+    #[strum(serialize = "metrics:read")]
+    MetricsRead,
+}
+
+impl PgHasArrayType for Scope {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_token_scope")
+    }
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PersonalAccessToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: Vec<Scope>,
+    #[serde(serialize_with = "serialize_dt_option")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "serialize_dt_option")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "serialize_dt")]
+    pub creation_time: DateTime<Utc>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait PersonalAccessTokenRepository {
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        payload: payload::NewPersonalAccessTokenPayload,
+        token_prefix: String,
+        token_hash: String,
+    ) -> Result<PersonalAccessToken, RepositoryError>;
+    async fn get_tokens_by_user(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<PersonalAccessToken>, RepositoryError>;
+    async fn get_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<PersonalAccessToken>, RepositoryError>;
+    async fn update_last_used(&self, id: Uuid) -> Result<(), RepositoryError>;
+    /// Returns `false` if the user has no token with this id.
+    async fn delete_token(&self, user_id: Uuid, id: Uuid) -> Result<bool, RepositoryError>;
+    async fn delete_user_tokens(&self, user_id: Uuid) -> Result<(), RepositoryError>;
+}
+
+pub mod payload {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Serialize};
+    use validator::{Validate, ValidationError};
+
+    use super::Scope;
+
+    #[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct NewPersonalAccessTokenPayload {
+        #[validate(length(min = 1, max = 100))]
+        pub name: String,
+        #[validate(length(min = 1))]
+        pub scopes: Vec<Scope>,
+        #[validate(custom = "validate_in_future")]
+        pub expires_at: Option<DateTime<Utc>>,
+    }
+
+    fn validate_in_future(date: &DateTime<Utc>) -> Result<(), ValidationError> {
+        if *date <= Utc::now() {
+            return Err(ValidationError::new("expires_at must be in the future"));
+        }
+        Ok(())
+    }
+}
+
+pub mod mocks {
+    use super::*;
+
+    factori::factori!(PersonalAccessToken, {
+        default {
+            id = Uuid::new_v4(),
+            user_id = Uuid::new_v4(),
+            name = "ci".to_string(),
+            token_prefix = "uat_abcdefgh".to_string(),
+            scopes = vec![Scope::UsersRead],
+            expires_at = None,
+            last_used_at = None,
+            creation_time = Utc::now(),
+        }
+    });
+}
@@ -0,0 +1,62 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::repositories::error::RepositoryError;
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub family_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait RefreshTokenRepository {
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        family_id: Uuid,
+        token_hash: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError>;
+    async fn get_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<RefreshToken>, RepositoryError>;
+    /// Marks the token as rotated. Returns `false` if it had already been used,
+    /// which means another request won the race for the same token.
+    async fn mark_token_used(&self, id: Uuid) -> Result<bool, RepositoryError>;
+    async fn revoke_family(&self, family_id: Uuid) -> Result<(), RepositoryError>;
+    async fn revoke_user_tokens(&self, user_id: Uuid) -> Result<(), RepositoryError>;
+}
+
+pub mod payload {
+    use serde::{Deserialize, Serialize};
+    use validator::Validate;
+
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct RefreshTokenPayload {
+        #[validate(length(min = 1))]
+        pub refresh_token: String,
+    }
+}
+
+pub mod mocks {
+    use super::*;
+
+    factori::factori!(RefreshToken, {
+        default {
+            id = Uuid::new_v4(),
+            user_id = Uuid::new_v4(),
+            family_id = Uuid::new_v4(),
+            expires_at = Utc::now() + chrono::Duration::days(1),
+            used_at = None,
+            revoked_at = None,
+        }
+    });
+}
@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{repositories::error::RepositoryError, utils::serialize_dt};
+
+/// A device or browser the user signed in from. Every refresh token issued for the
+/// session belongs to the token family sharing its id, and every access token carries
+/// that id in its `sid` claim, so ending the session signs the device out for good.
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    #[serde(serialize_with = "serialize_dt")]
+    pub creation_time: DateTime<Utc>,
+    /// Updated whenever the session refreshes its access token.
+    #[serde(serialize_with = "serialize_dt")]
+    pub last_used_at: DateTime<Utc>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait SessionRepository {
+    async fn create_session(
+        &self,
+        user_id: Uuid,
+        user_agent: Option<String>,
+        ip: Option<String>,
+    ) -> Result<Session, RepositoryError>;
+    /// Sessions of the user not ended and used since `active_since`, most recent first.
+    async fn get_sessions_by_user(
+        &self,
+        user_id: Uuid,
+        active_since: DateTime<Utc>,
+    ) -> Result<Vec<Session>, RepositoryError>;
+    async fn update_last_used(&self, id: Uuid) -> Result<(), RepositoryError>;
+    /// Ends the session along with its refresh tokens. Returns `false` if the user has
+    /// no session with this id still running.
+    async fn revoke_session(&self, user_id: Uuid, id: Uuid) -> Result<bool, RepositoryError>;
+    /// Ends every session of the user but `kept_id`, along with their refresh tokens.
+    async fn revoke_user_sessions(
+        &self,
+        user_id: Uuid,
+        kept_id: Option<Uuid>,
+    ) -> Result<(), RepositoryError>;
+}
+
+pub mod mocks {
+    use super::*;
+
+    factori::factori!(Session, {
+        default {
+            id = Uuid::new_v4(),
+            user_id = Uuid::new_v4(),
+            user_agent = Some("Mozilla/5.0".to_string()),
+            ip = Some("127.0.0.1".to_string()),
+            creation_time = Utc::now(),
+            last_used_at = Utc::now(),
+        }
+    });
+}
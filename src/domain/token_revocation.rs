@@ -0,0 +1,26 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::repositories::error::RepositoryError;
+
+/// A revoked access token when `jti` is set, every access token of a signed out session
+/// when `session_id` is, otherwise every access token of `user_id` issued up to
+/// `revocation_time` but `kept_jti`. Rows are only relevant until `expires_at`, after
+/// which the tokens they cover are expired anyway.
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct TokenRevocation {
+    pub jti: Option<Uuid>,
+    pub session_id: Option<Uuid>,
+    pub kept_jti: Option<Uuid>,
+    pub user_id: Uuid,
+    pub revocation_time: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait TokenRevocationRepository {
+    async fn create_revocation(&self, revocation: TokenRevocation) -> Result<(), RepositoryError>;
+    async fn get_active_revocations(&self) -> Result<Vec<TokenRevocation>, RepositoryError>;
+    async fn delete_expired_revocations(&self) -> Result<(), RepositoryError>;
+}
@@ -0,0 +1,109 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::repositories::error::RepositoryError;
+
+/// TOTP settings of a user. `enabled_at` stays empty until the enrollment is confirmed
+/// with a first code, and `secret` is encrypted with [`crate::auth::cipher::SecretCipher`].
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct TwoFactor {
+    pub secret: String,
+    pub enabled_at: Option<DateTime<Utc>>,
+    pub last_used_step: Option<i64>,
+}
+
+/// Proof that a user got the password right, exchanged for tokens along with a code.
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct TwoFactorChallenge {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub failed_attempts: i32,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait TwoFactorRepository {
+    async fn get_two_factor(&self, user_id: Uuid) -> Result<Option<TwoFactor>, RepositoryError>;
+    /// Replaces any pending enrollment and its recovery codes.
+    async fn save_pending_two_factor(
+        &self,
+        user_id: Uuid,
+        secret: String,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), RepositoryError>;
+    async fn enable_two_factor(&self, user_id: Uuid, step: i64) -> Result<(), RepositoryError>;
+    /// Records the step of an accepted code. Returns `false` if a code from this step or a
+    /// later one was accepted in the meantime.
+    async fn update_last_used_step(
+        &self,
+        user_id: Uuid,
+        step: i64,
+    ) -> Result<bool, RepositoryError>;
+    async fn delete_two_factor(&self, user_id: Uuid) -> Result<(), RepositoryError>;
+    /// Returns `false` if the code does not exist or was already used.
+    async fn use_recovery_code(
+        &self,
+        user_id: Uuid,
+        code_hash: String,
+    ) -> Result<bool, RepositoryError>;
+    async fn create_challenge(
+        &self,
+        user_id: Uuid,
+        token_hash: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError>;
+    async fn get_challenge_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<TwoFactorChallenge>, RepositoryError>;
+    async fn increment_challenge_failures(&self, id: Uuid) -> Result<(), RepositoryError>;
+    /// Returns `false` if the challenge was already used.
+    async fn delete_challenge(&self, id: Uuid) -> Result<bool, RepositoryError>;
+}
+
+pub mod payload {
+    use serde::{Deserialize, Serialize};
+    use validator::Validate;
+
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ConfirmTwoFactorPayload {
+        #[validate(length(min = 1, max = 32))]
+        pub code: String,
+    }
+
+    /// Users turning 2FA off for themselves confirm with either their password or a code
+    /// like the one [`TwoFactorLoginPayload`] takes.
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct DisableTwoFactorPayload {
+        #[validate(length(min = 1))]
+        pub password: Option<String>,
+        #[validate(length(min = 1, max = 32))]
+        pub code: Option<String>,
+    }
+
+    /// `code` is either the current TOTP code or one of the recovery codes.
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct TwoFactorLoginPayload {
+        #[validate(length(min = 1))]
+        pub challenge_token: String,
+        #[validate(length(min = 1, max = 32))]
+        pub code: String,
+    }
+}
+
+pub mod mocks {
+    use super::*;
+
+    factori::factori!(TwoFactorChallenge, {
+        default {
+            id = Uuid::new_v4(),
+            user_id = Uuid::new_v4(),
+            expires_at = Utc::now() + chrono::Duration::minutes(5),
+            failed_attempts = 0,
+        }
+    });
+}
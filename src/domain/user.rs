@@ -1,7 +1,3 @@
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
-};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -11,7 +7,21 @@ use crate::{
     utils::{serialize_dt, serialize_dt_option},
 };
 
-use self::payload::{LoginUserPayload, NewUserPayload, UpdateUserPayload};
+use self::payload::{NewUserPayload, UpdateUserPayload};
+
+/// Roles are ordered by privilege, so a role check passes for the required role
+/// and everything above it.
+#[derive(
+    sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default,
+)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Moderator,
+    Admin,
+}
 
 #[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -24,6 +34,9 @@ pub struct User {
     pub password: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bio: Option<String>,
+    pub role: Role,
+    #[serde(serialize_with = "serialize_dt_option")]
+    pub email_verified_at: Option<DateTime<Utc>>,
     #[serde(serialize_with = "serialize_dt")]
     pub creation_time: DateTime<Utc>,
     #[serde(serialize_with = "serialize_dt_option")]
@@ -40,15 +53,34 @@ pub struct PublicUser {
     pub email: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bio: Option<String>,
+    pub role: Role,
+    #[serde(serialize_with = "serialize_dt_option")]
+    pub email_verified_at: Option<DateTime<Utc>>,
     #[serde(serialize_with = "serialize_dt")]
     pub creation_time: DateTime<Utc>,
 }
 
+impl From<User> for PublicUser {
+    fn from(user: User) -> Self {
+        PublicUser {
+            id: user.id,
+            name: user.name,
+            nickname: user.nickname,
+            email: user.email,
+            bio: user.bio,
+            role: user.role,
+            email_verified_at: user.email_verified_at,
+            creation_time: user.creation_time,
+        }
+    }
+}
+
 #[cfg_attr(test, mockall::automock)]
 #[async_trait::async_trait]
 pub trait UserRepository {
     async fn create_user(&self, user: NewUserPayload) -> Result<PublicUser, RepositoryError>;
     async fn update_user(&self, id: Uuid, user: UpdateUserPayload) -> Result<(), RepositoryError>;
+    async fn update_user_role(&self, id: Uuid, role: Role) -> Result<(), RepositoryError>;
     async fn get_user_by_nickname(
         &self,
         nickname: String,
@@ -56,11 +88,24 @@ pub trait UserRepository {
     async fn get_user_by_id(&self, id: Uuid) -> Result<Option<PublicUser>, RepositoryError>;
     async fn get_user_by_email(&self, email: String)
         -> Result<Option<PublicUser>, RepositoryError>;
-    async fn get_user_by_login(
+    /// The only lookup returning the password hash, for the login to check it.
+    async fn get_user_with_password_by_email(
         &self,
-        login_payload: LoginUserPayload,
-    ) -> Result<PublicUser, RepositoryError>;
+        email: String,
+    ) -> Result<Option<User>, RepositoryError>;
+    /// Replaces `current_hash` with a hash of `password` made with the current parameters,
+    /// unless the password was changed in the meantime.
+    async fn upgrade_password(
+        &self,
+        id: Uuid,
+        current_hash: String,
+        password: String,
+    ) -> Result<(), RepositoryError>;
     async fn delete_user(&self, id: Uuid) -> Result<(), RepositoryError>;
+    /// Hashes and stores a new password.
+    async fn update_password(&self, id: Uuid, password: String) -> Result<(), RepositoryError>;
+    async fn mark_email_verified(&self, id: Uuid) -> Result<(), RepositoryError>;
+    async fn get_password_hash(&self, id: Uuid) -> Result<Option<String>, RepositoryError>;
 }
 
 pub mod payload {
@@ -69,6 +114,8 @@ pub mod payload {
     use serde::{Deserialize, Serialize};
     use validator::Validate;
 
+    use super::Role;
+
     lazy_static! {
         static ref NICKNAME_REGEX: Regex = Regex::new(r"^[a-zA-Z0-9_]+$").unwrap();
     }
@@ -81,7 +128,8 @@ pub mod payload {
         pub nickname: String,
         #[validate(email)]
         pub email: String,
-        #[validate(length(min = 8))]
+        /// Checked against the [`PasswordPolicy`](crate::domain::password_policy::PasswordPolicy)
+        /// by the handler, which knows the rules in force.
         pub password: String,
         #[validate(length(max = 250))]
         pub bio: Option<String>,
@@ -97,11 +145,26 @@ pub mod payload {
         pub bio: Option<String>,
     }
 
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct UpdateUserRolePayload {
+        pub role: Role,
+    }
+
+    /// `new_password` follows the same rules as in [`NewUserPayload`].
+    #[derive(Serialize, Deserialize, Validate, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ChangePasswordPayload {
+        #[validate(length(min = 1))]
+        pub current_password: String,
+        pub new_password: String,
+    }
+
+    /// Passwords set under an older policy must still work, so only emptiness is checked.
     #[derive(Serialize, Deserialize, Validate, Debug)]
     pub struct LoginUserPayload {
         #[validate(email)]
         pub email: String,
-        #[validate(length(min = 8))]
+        #[validate(length(min = 1))]
         pub password: String,
     }
 }
@@ -125,25 +188,423 @@ pub mod validation {
     }
 }
 
+/// Passwords are hashed with Argon2id using the parameters and optional pepper set with
+/// [`password::configure`] at startup, [`argon2::Params::default`] otherwise.
+///
+/// Accounts imported from the previous system may still hold a bcrypt (`$2a$`, `$2b$`,
+/// `$2y$`), PHC PBKDF2-SHA256 (`$pbkdf2-sha256$`) or PHC scrypt (`$scrypt$`) hash. Those
+/// are verified without the pepper and always reported by [`password::needs_rehash`].
+///
+/// Hashing is slow on purpose, so it runs on the blocking thread pool rather than on the
+/// async workers, at most `max_concurrency` at a time. Calls waiting longer than
+/// `queue_timeout` for their turn fail with [`RepositoryError::Overloaded`].
 pub mod password {
-    use argon2::{password_hash::Error, PasswordHash, PasswordVerifier};
+    use std::{
+        env,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            OnceLock,
+        },
+        thread,
+        time::Duration,
+    };
 
-    use super::*;
-    pub fn hash_password(original: String) -> Result<String, Error> {
-        let argon2 = Argon2::default();
+    use argon2::{
+        password_hash::{
+            rand_core::{OsRng, RngCore},
+            Error, PasswordHasher, SaltString,
+        },
+        Algorithm, Argon2, KeyId, Params, ParamsBuilder, PasswordHash, PasswordVerifier, Version,
+    };
+
+    use pbkdf2::Pbkdf2;
+    use scrypt::Scrypt;
+    use sha2::{Digest, Sha256};
+    use tokio::sync::Semaphore;
+
+    use super::RepositoryError;
+    use crate::utils::env_var_or;
+
+    const BCRYPT_PREFIXES: [&str; 3] = ["$2a$", "$2b$", "$2y$"];
+
+    static CONFIG: OnceLock<PasswordConfig> = OnceLock::new();
+    static POOL: OnceLock<HashingPool> = OnceLock::new();
+    static DUMMY_HASH: tokio::sync::OnceCell<String> = tokio::sync::OnceCell::const_new();
+
+    pub struct PasswordConfig {
+        pub params: Params,
+        /// Secret mixed into every hash, kept out of the database so a leaked dump alone
+        /// cannot be brute-forced. Hashes name the pepper they were made with in their
+        /// `keyid`, so those made before it was set still verify and get rehashed with it.
+        /// Changing it invalidates the passwords hashed with the previous one.
+        pub pepper: Option<Vec<u8>>,
+        pub max_concurrency: usize,
+        pub queue_timeout: Duration,
+    }
+
+    impl Default for PasswordConfig {
+        fn default() -> Self {
+            PasswordConfig {
+                params: Params::default(),
+                pepper: None,
+                max_concurrency: thread::available_parallelism().map_or(1, |n| n.get()),
+                queue_timeout: Duration::from_secs(5),
+            }
+        }
+    }
+
+    impl PasswordConfig {
+        pub fn from_env() -> Result<PasswordConfig, String> {
+            let params = Params::new(
+                env_var_or("PASSWORD_HASH_MEMORY_KIB", Params::DEFAULT_M_COST),
+                env_var_or("PASSWORD_HASH_ITERATIONS", Params::DEFAULT_T_COST),
+                env_var_or("PASSWORD_HASH_PARALLELISM", Params::DEFAULT_P_COST),
+                None,
+            )
+            .map_err(|e| format!("Invalid password hashing parameters: {e}"))?;
+            let pepper = env::var("PASSWORD_PEPPER")
+                .ok()
+                .filter(|pepper| !pepper.is_empty())
+                .map(String::into_bytes);
+
+            let max_concurrency = env_var_or(
+                "PASSWORD_HASH_MAX_CONCURRENCY",
+                PasswordConfig::default().max_concurrency,
+            );
+            if max_concurrency == 0 {
+                return Err("PASSWORD_HASH_MAX_CONCURRENCY must be at least 1".to_string());
+            }
+            let queue_timeout =
+                Duration::from_millis(env_var_or("PASSWORD_HASH_QUEUE_TIMEOUT_MS", 5000));
+
+            let config = PasswordConfig {
+                params,
+                pepper,
+                max_concurrency,
+                queue_timeout,
+            };
+            config
+                .argon2()
+                .map_err(|e| format!("Invalid PASSWORD_PEPPER: {e}"))?;
+            Ok(config)
+        }
+
+        fn argon2(&self) -> Result<Argon2<'_>, Error> {
+            match &self.pepper {
+                Some(pepper) => {
+                    let params = ParamsBuilder::new()
+                        .m_cost(self.params.m_cost())
+                        .t_cost(self.params.t_cost())
+                        .p_cost(self.params.p_cost())
+                        .keyid(KeyId::new(&pepper_id(pepper))?)
+                        .build()?;
+                    Argon2::new_with_secret(pepper, Algorithm::Argon2id, Version::V0x13, params)
+                        .map_err(Into::into)
+                }
+                None => Ok(Argon2::new(
+                    Algorithm::Argon2id,
+                    Version::V0x13,
+                    self.params.clone(),
+                )),
+            }
+        }
+
+        /// The hasher able to verify `hash`: unpeppered if it has no `keyid`, failing if
+        /// it was made with another pepper than the configured one.
+        fn argon2_for(&self, hash: &PasswordHash) -> Result<Argon2<'_>, Error> {
+            let keyid = Params::try_from(hash)?.keyid().to_vec();
+            if keyid.is_empty() {
+                return Ok(Argon2::default());
+            }
+            match &self.pepper {
+                Some(pepper) if keyid == pepper_id(pepper) => self.argon2(),
+                _ => Err(Error::Crypto),
+            }
+        }
+
+        fn is_current(&self, hash: &PasswordHash) -> bool {
+            let Ok(params) = Params::try_from(hash) else {
+                return false;
+            };
+            let keyid = self.pepper.as_deref().map(pepper_id).unwrap_or_default();
+            hash.algorithm == Algorithm::Argon2id.ident()
+                && hash.version == Some(Version::V0x13.into())
+                && params.m_cost() == self.params.m_cost()
+                && params.t_cost() == self.params.t_cost()
+                && params.p_cost() == self.params.p_cost()
+                && params.keyid() == keyid
+        }
+    }
+
+    /// Names a pepper in the hashes made with it, without giving it away.
+    fn pepper_id(pepper: &[u8]) -> Vec<u8> {
+        Sha256::digest(pepper)[..Params::MAX_KEYID_LEN].to_vec()
+    }
+
+    /// Must be called before any password is hashed, once.
+    pub fn configure(config: PasswordConfig) {
+        if CONFIG.set(config).is_err() {
+            panic!("Password hashing is already configured");
+        }
+    }
+
+    fn config() -> &'static PasswordConfig {
+        CONFIG.get_or_init(PasswordConfig::default)
+    }
+
+    fn pool() -> &'static HashingPool {
+        POOL.get_or_init(|| HashingPool {
+            permits: Semaphore::new(config().max_concurrency),
+            queue_timeout: config().queue_timeout,
+            queued: AtomicUsize::new(0),
+        })
+    }
+
+    struct HashingPool {
+        permits: Semaphore,
+        queue_timeout: Duration,
+        queued: AtomicUsize,
+    }
+
+    /// Keeps [`HashingPool::queued`] right when a waiting request is dropped.
+    struct QueuedGuard<'a>(&'a AtomicUsize);
+
+    impl Drop for QueuedGuard<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    impl HashingPool {
+        async fn run<T, F>(&'static self, task: F) -> Result<T, RepositoryError>
+        where
+            T: Send + 'static,
+            F: FnOnce() -> Result<T, Error> + Send + 'static,
+        {
+            let permit = {
+                self.queued.fetch_add(1, Ordering::Relaxed);
+                let _queued = QueuedGuard(&self.queued);
+                tokio::time::timeout(self.queue_timeout, self.permits.acquire())
+                    .await
+                    .map_err(|_| RepositoryError::Overloaded)?
+                    .expect("The hashing semaphore is never closed")
+            };
+
+            let result = tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                task()
+            })
+            .await
+            .expect("Password hashing panicked")?;
+            Ok(result)
+        }
+    }
+
+    /// Number of hashing calls waiting for a free slot.
+    pub fn queue_depth() -> usize {
+        pool().queued.load(Ordering::Relaxed)
+    }
+
+    /// Number of hashing calls currently running.
+    pub fn in_flight() -> usize {
+        config().max_concurrency - pool().permits.available_permits()
+    }
+
+    pub async fn hash_password(original: String) -> Result<String, RepositoryError> {
+        pool().run(move || hash_password_blocking(original)).await
+    }
+
+    /// Fails with [`RepositoryError::HashingError`] holding [`Error::Password`] on a
+    /// mismatch. A stored hash that cannot be parsed fails with another error rather than
+    /// panicking.
+    pub async fn verify_passwords(
+        input_password: String,
+        db_password: String,
+    ) -> Result<(), RepositoryError> {
+        pool()
+            .run(move || verify_passwords_blocking(input_password, db_password))
+            .await
+    }
+
+    /// A hash of a random password made with the current parameters, for logins with an
+    /// unknown email to spend as long verifying as those with a known one.
+    pub async fn dummy_hash() -> Result<String, RepositoryError> {
+        DUMMY_HASH
+            .get_or_try_init(|| {
+                let mut password = [0u8; 32];
+                OsRng.fill_bytes(&mut password);
+                hash_password(format!("{password:x?}"))
+            })
+            .await
+            .cloned()
+    }
+
+    fn hash_password_blocking(original: String) -> Result<String, Error> {
         let salt = SaltString::generate(&mut OsRng);
-        let password_hash = argon2
+        let password_hash = config()
+            .argon2()?
             .hash_password(original.as_bytes(), &salt)?
             .to_string();
         Ok(password_hash)
     }
 
-    pub fn verify_passwords(input_password: String, db_password: String) -> Result<(), Error> {
-        let argon2 = Argon2::default();
-        let parsed_hash = PasswordHash::new(&db_password).unwrap();
-        argon2.verify_password(input_password.as_bytes(), &parsed_hash)?;
+    fn verify_passwords_blocking(input_password: String, db_password: String) -> Result<(), Error> {
+        if is_bcrypt(&db_password) {
+            return match bcrypt::verify(input_password.as_bytes(), &db_password) {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(Error::Password),
+                Err(_) => Err(Error::Crypto),
+            };
+        }
+
+        let parsed_hash = PasswordHash::new(&db_password)?;
+        if parsed_hash.algorithm == pbkdf2::Algorithm::Pbkdf2Sha256.ident() {
+            Pbkdf2.verify_password(input_password.as_bytes(), &parsed_hash)?;
+        } else if parsed_hash.algorithm == scrypt::ALG_ID {
+            Scrypt.verify_password(input_password.as_bytes(), &parsed_hash)?;
+        } else {
+            config()
+                .argon2_for(&parsed_hash)?
+                .verify_password(input_password.as_bytes(), &parsed_hash)?;
+        }
         Ok(())
     }
+
+    fn is_bcrypt(db_password: &str) -> bool {
+        BCRYPT_PREFIXES
+            .iter()
+            .any(|prefix| db_password.starts_with(prefix))
+    }
+
+    /// Whether a hash was made with other parameters than the configured ones, and should
+    /// be replaced next time the password is known.
+    pub fn needs_rehash(db_password: &str) -> bool {
+        is_bcrypt(db_password)
+            || PasswordHash::new(db_password).is_ok_and(|hash| !config().is_current(&hash))
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn does_not_panic_on_corrupted_hash() {
+            let result =
+                verify_passwords_blocking("password".to_string(), "not a hash".to_string());
+
+            assert!(matches!(result, Err(error) if error != Error::Password));
+        }
+
+        #[test]
+        fn detects_outdated_hash() {
+            let outdated = Argon2::new(
+                Algorithm::Argon2id,
+                Version::V0x13,
+                Params::new(8192, 1, 1, None).unwrap(),
+            )
+            .hash_password(b"password", &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string();
+            let current = hash_password_blocking("password".to_string()).unwrap();
+
+            assert!(needs_rehash(&outdated));
+            assert!(!needs_rehash(&current));
+            assert!(verify_passwords_blocking("password".to_string(), outdated).is_ok());
+        }
+
+        #[test]
+        fn verifies_hashes_made_before_pepper_was_set() {
+            let unpeppered = PasswordConfig::default();
+            let peppered = PasswordConfig {
+                pepper: Some(b"pepper".to_vec()),
+                ..PasswordConfig::default()
+            };
+            let verify = |config: &PasswordConfig, password: &str, hash: &str| {
+                let hash = PasswordHash::new(hash).unwrap();
+                config
+                    .argon2_for(&hash)
+                    .and_then(|argon2| argon2.verify_password(password.as_bytes(), &hash))
+            };
+            let salt = SaltString::generate(&mut OsRng);
+            let old_hash = unpeppered
+                .argon2()
+                .unwrap()
+                .hash_password(b"password", &salt)
+                .unwrap()
+                .to_string();
+            let new_hash = peppered
+                .argon2()
+                .unwrap()
+                .hash_password(b"password", &salt)
+                .unwrap()
+                .to_string();
+
+            assert!(verify(&peppered, "password", &old_hash).is_ok());
+            assert!(!peppered.is_current(&PasswordHash::new(&old_hash).unwrap()));
+            assert!(verify(&peppered, "password", &new_hash).is_ok());
+            assert!(peppered.is_current(&PasswordHash::new(&new_hash).unwrap()));
+            assert_eq!(
+                verify(&peppered, "wrong password", &new_hash),
+                Err(Error::Password)
+            );
+            assert!(verify(&unpeppered, "password", &new_hash).is_err());
+        }
+
+        #[test]
+        fn verifies_legacy_hashes() {
+            let bcrypt = bcrypt::hash("password", 4).unwrap();
+            let pbkdf2 = Pbkdf2
+                .hash_password_customized(
+                    b"password",
+                    Some(pbkdf2::Algorithm::Pbkdf2Sha256.ident()),
+                    None,
+                    pbkdf2::Params {
+                        rounds: 1000,
+                        output_length: 32,
+                    },
+                    &SaltString::generate(&mut OsRng),
+                )
+                .unwrap()
+                .to_string();
+            let scrypt = Scrypt
+                .hash_password_customized(
+                    b"password",
+                    None,
+                    None,
+                    scrypt::Params::new(4, 8, 1, 32).unwrap(),
+                    &SaltString::generate(&mut OsRng),
+                )
+                .unwrap()
+                .to_string();
+
+            for legacy in [bcrypt, pbkdf2, scrypt] {
+                assert!(verify_passwords_blocking("password".to_string(), legacy.clone()).is_ok());
+                assert_eq!(
+                    verify_passwords_blocking("wrong password".to_string(), legacy.clone()),
+                    Err(Error::Password)
+                );
+                assert!(needs_rehash(&legacy));
+            }
+        }
+
+        #[tokio::test]
+        async fn sheds_load_when_saturated() {
+            let pool: &'static HashingPool = Box::leak(Box::new(HashingPool {
+                permits: Semaphore::new(1),
+                queue_timeout: Duration::from_millis(10),
+                queued: AtomicUsize::new(0),
+            }));
+            let busy = pool.permits.acquire().await.unwrap();
+
+            let result = pool.run(|| Ok(())).await;
+
+            assert!(matches!(result, Err(RepositoryError::Overloaded)));
+            assert_eq!(pool.queued.load(Ordering::Relaxed), 0);
+
+            drop(busy);
+            assert!(pool.run(|| Ok(())).await.is_ok());
+        }
+    }
 }
 
 pub mod mocks {
@@ -157,6 +618,8 @@ pub mod mocks {
             email = "johndoe@gmail.com".to_string(),
             password = "password".to_string(),
             bio = Some("I am a cool guy".to_string()),
+            role = Role::User,
+            email_verified_at = Some(Utc::now()),
             creation_time = Utc::now(),
             update_time = None,
         }
@@ -169,6 +632,8 @@ pub mod mocks {
             nickname = "johndoe".to_string(),
             email = "johndoe@gmail.com".to_string(),
             bio = Some("I am a cool guy".to_string()),
+            role = Role::User,
+            email_verified_at = Some(Utc::now()),
             creation_time = Utc::now(),
         }
     });
@@ -178,7 +643,7 @@ pub mod mocks {
                 name = Some("John Doe".to_string()),
                 nickname = "johndoe".to_string(),
                 email = "johndoe@gmail.com".to_string(),
-                password = "password".to_string(),
+                password = "correct horse battery staple".to_string(),
                 bio = Some("I am a cool guy".to_string()),
         }
     });
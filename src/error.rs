@@ -1,10 +1,20 @@
 use std::fmt::Display;
 
-use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use actix_web::{
+    error::{JsonPayloadError, PathError, QueryPayloadError},
+    http::{header, StatusCode},
+    web::{self, ServiceConfig},
+    HttpRequest, HttpResponse, ResponseError,
+};
 use argon2::password_hash::Error;
 use strum::EnumMessage;
+use validator::ValidationErrors;
 
-use crate::{repositories::error::RepositoryError, response::GenericResponse};
+use crate::{
+    domain::{oauth::OAuthErrorCode, user::validation::format_error_msg},
+    repositories::error::{format_violations, RepositoryError},
+    response::GenericResponse,
+};
 
 #[derive(Debug)]
 pub enum ErrorType {
@@ -12,6 +22,13 @@ pub enum ErrorType {
     Conflict,
     InternalError,
     BadRequest,
+    Unauthorized,
+    Forbidden,
+    UnprocessableEntity,
+    /// Carries the number of seconds sent back in `Retry-After`.
+    TooManyRequests(u64),
+    ServiceUnavailable,
+    BadGateway,
 }
 
 #[derive(Debug)]
@@ -31,20 +48,67 @@ impl From<RepositoryError> for AppError {
                 message: message.get_message().unwrap().to_string(),
                 r#type: ErrorType::Conflict,
             },
+            RepositoryError::InvalidToken(message) => AppError {
+                message: message.get_message().unwrap().to_string(),
+                r#type: ErrorType::Unauthorized,
+            },
+            RepositoryError::WeakPassword(violations) => AppError {
+                message: format_violations(&violations),
+                r#type: ErrorType::UnprocessableEntity,
+            },
+            RepositoryError::InvalidCredentials => AppError {
+                message: "Invalid credentials".to_string(),
+                r#type: ErrorType::Unauthorized,
+            },
+            RepositoryError::TooManyAttempts(retry_after) => AppError {
+                message: "Too many attempts, try again later".to_string(),
+                r#type: ErrorType::TooManyRequests(retry_after),
+            },
+            RepositoryError::SqlxError(sqlx::Error::PoolTimedOut) | RepositoryError::Overloaded => {
+                AppError {
+                    message: "Service temporarily unavailable".to_string(),
+                    r#type: ErrorType::ServiceUnavailable,
+                }
+            }
+            RepositoryError::IdentityProvider(error) => AppError {
+                message: format!("Identity provider error: {}", error),
+                r#type: ErrorType::BadGateway,
+            },
+            RepositoryError::OAuth(OAuthErrorCode::InvalidClient, description) => AppError {
+                message: description,
+                r#type: ErrorType::Unauthorized,
+            },
+            RepositoryError::OAuth(_, description) => AppError {
+                message: description,
+                r#type: ErrorType::BadRequest,
+            },
             RepositoryError::SqlxError(error) => AppError {
                 message: format!("Internal error: {}", error),
                 r#type: ErrorType::InternalError,
             },
+            RepositoryError::EncryptionError(error) => AppError {
+                message: format!("Internal error: {}", error),
+                r#type: ErrorType::InternalError,
+            },
             RepositoryError::HashingError(error) => match error {
                 Error::Password => AppError {
-                    message: format!("Invalid password"),
-                    r#type: ErrorType::BadRequest,
+                    message: "Invalid password".to_string(),
+                    r#type: ErrorType::Unauthorized,
                 },
                 _ => AppError {
                     message: format!("Internal error: {}", error),
                     r#type: ErrorType::InternalError,
                 },
-            }
+            },
+        }
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        AppError {
+            message: format_error_msg(errors.field_errors()),
+            r#type: ErrorType::UnprocessableEntity,
         }
     }
 }
@@ -56,6 +120,34 @@ impl AppError {
             r#type: ErrorType::BadRequest,
         }
     }
+
+    pub fn unauthorized(message: String) -> AppError {
+        AppError {
+            message,
+            r#type: ErrorType::Unauthorized,
+        }
+    }
+
+    pub fn forbidden(message: String) -> AppError {
+        AppError {
+            message,
+            r#type: ErrorType::Forbidden,
+        }
+    }
+
+    pub fn not_found(message: String) -> AppError {
+        AppError {
+            message,
+            r#type: ErrorType::NotFound,
+        }
+    }
+
+    pub fn internal(message: String) -> AppError {
+        AppError {
+            message,
+            r#type: ErrorType::InternalError,
+        }
+    }
 }
 
 impl Display for AppError {
@@ -71,13 +163,59 @@ impl ResponseError for AppError {
             ErrorType::Conflict => StatusCode::CONFLICT,
             ErrorType::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
             ErrorType::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorType::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorType::Forbidden => StatusCode::FORBIDDEN,
+            ErrorType::UnprocessableEntity => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorType::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ErrorType::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorType::BadGateway => StatusCode::BAD_GATEWAY,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(GenericResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        match self.r#type {
+            ErrorType::Unauthorized => {
+                response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+            }
+            ErrorType::TooManyRequests(retry_after) => {
+                response.insert_header((header::RETRY_AFTER, retry_after));
+            }
+            _ => {}
+        }
+        response.json(GenericResponse {
             status: self.status_code().as_u16(),
             message: self.message.to_string(),
         })
     }
 }
+
+/// Makes actix's own extractor failures and unmatched routes answer with the same
+/// JSON body as every other error instead of plain text.
+pub(crate) fn error_handlers(cfg: &mut ServiceConfig) {
+    cfg.app_data(web::JsonConfig::default().error_handler(json_error_handler))
+        .app_data(web::PathConfig::default().error_handler(path_error_handler))
+        .app_data(web::QueryConfig::default().error_handler(query_error_handler))
+        .default_service(web::to(|| async {
+            Err::<HttpResponse, _>(AppError::not_found("Not found".to_string()))
+        }));
+}
+
+fn json_error_handler(error: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
+    match error {
+        JsonPayloadError::Deserialize(error) if error.is_data() => AppError {
+            message: error.to_string(),
+            r#type: ErrorType::UnprocessableEntity,
+        },
+        error => AppError::bad_request(error.to_string()),
+    }
+    .into()
+}
+
+fn path_error_handler(_: PathError, _: &HttpRequest) -> actix_web::Error {
+    AppError::not_found("Not found".to_string()).into()
+}
+
+fn query_error_handler(error: QueryPayloadError, _: &HttpRequest) -> actix_web::Error {
+    AppError::bad_request(error.to_string()).into()
+}
@@ -1 +1,13 @@
 pub mod user;
+pub mod refresh_token;
+pub mod token_revocation;
+pub mod personal_access_token;
+pub mod login_attempt;
+pub mod two_factor;
+pub mod password_reset;
+pub mod email_verification;
+pub mod email_change;
+pub mod magic_link;
+pub mod session;
+pub mod oidc;
+pub mod oauth;
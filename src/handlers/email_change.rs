@@ -0,0 +1,335 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        email_change::{payload::EmailChangePayload, EmailChangeRepository},
+        user::{password::verify_passwords, UserRepository},
+    },
+    mail::{send_in_background, DynMailTransport, Mail},
+    repositories::error::{
+        ErrorMessage::{ExistingEmail, InvalidEmailChangeToken},
+        RepositoryError,
+    },
+    utils::{generate_token, hash_token, token_link},
+};
+
+pub type DynEmailChangeHandler = dyn EmailChangeHandler + Send + Sync;
+
+pub struct EmailChangeHandlerImpl {
+    pub email_change_repository: Box<dyn EmailChangeRepository + Send + Sync>,
+    pub user_repository: Box<dyn UserRepository + Send + Sync>,
+    pub mail_transport: Arc<DynMailTransport>,
+    pub token_lifetime: Duration,
+    pub confirm_url: String,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait EmailChangeHandler {
+    /// Checks the password, then mails a confirmation link to the new address and lets
+    /// the current one know about the request. The email stays the same until the link
+    /// is followed.
+    async fn request_change(
+        &self,
+        id: Uuid,
+        payload: EmailChangePayload,
+    ) -> Result<(), RepositoryError>;
+
+    /// Consumes the token and sets the new email, returning the user it belonged to.
+    async fn confirm_change(&self, token: String) -> Result<Uuid, RepositoryError>;
+}
+
+impl EmailChangeHandlerImpl {
+    async fn ensure_email_available(&self, email: String) -> Result<(), RepositoryError> {
+        let user_with_email = self.user_repository.get_user_by_email(email).await?;
+        if user_with_email.is_some() {
+            return Err(RepositoryError::Conflict(ExistingEmail));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailChangeHandler for EmailChangeHandlerImpl {
+    #[tracing::instrument(skip(self, payload))]
+    async fn request_change(
+        &self,
+        id: Uuid,
+        payload: EmailChangePayload,
+    ) -> Result<(), RepositoryError> {
+        let user = self
+            .user_repository
+            .get_user_by_id(id)
+            .await?
+            .ok_or(RepositoryError::NotFound)?;
+        let password_hash = self
+            .user_repository
+            .get_password_hash(id)
+            .await?
+            .ok_or(RepositoryError::NotFound)?;
+
+        verify_passwords(payload.password, password_hash).await?;
+        self.ensure_email_available(payload.new_email.clone())
+            .await?;
+
+        let token = generate_token();
+        self.email_change_repository
+            .create_request(
+                id,
+                payload.new_email.clone(),
+                hash_token(&token),
+                Utc::now() + self.token_lifetime,
+            )
+            .await?;
+
+        let confirmation = Mail {
+            to: payload.new_email.clone(),
+            subject: "Confirm your new email".to_string(),
+            body: format!(
+                "The account {} asked to use this address from now on.\n\n\
+                Follow this link to confirm it: {}\n\n\
+                The link expires in {} hours. If you did not ask for it, ignore this mail.",
+                user.nickname,
+                token_link(&self.confirm_url, &token),
+                self.token_lifetime.num_hours()
+            ),
+        };
+        send_in_background(self.mail_transport.clone(), confirmation);
+
+        let notification = Mail {
+            to: user.email,
+            subject: "Your email is about to change".to_string(),
+            body: format!(
+                "Someone asked to change the email of your account {} to {}.\n\n\
+                It will only change once the link sent to the new address is followed. \
+                If you did not ask for it, change your password.",
+                user.nickname, payload.new_email
+            ),
+        };
+        send_in_background(self.mail_transport.clone(), notification);
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self, token))]
+    async fn confirm_change(&self, token: String) -> Result<Uuid, RepositoryError> {
+        let invalid_token = || RepositoryError::InvalidToken(InvalidEmailChangeToken);
+
+        let request = self
+            .email_change_repository
+            .get_request_by_hash(hash_token(&token))
+            .await?
+            .ok_or_else(invalid_token)?;
+
+        if request.expires_at <= Utc::now() {
+            return Err(invalid_token());
+        }
+        let user_id = request.user_id;
+        if !self.email_change_repository.apply_request(request).await? {
+            return Err(invalid_token());
+        }
+        Ok(user_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        domain::{
+            email_change::{mocks::*, MockEmailChangeRepository},
+            user::{mocks::*, password::hash_password, MockUserRepository},
+        },
+        mail::{InMemoryMailTransport, MockMailTransport},
+    };
+
+    fn payload(password: &str) -> EmailChangePayload {
+        EmailChangePayload {
+            password: password.to_string(),
+            new_email: "new_johndoe@gmail.com".to_string(),
+        }
+    }
+
+    async fn user_repository_with_password(password: &str) -> MockUserRepository {
+        let password_hash = hash_password(password.to_string()).await.unwrap();
+        let mut user_repository = MockUserRepository::new();
+        user_repository
+            .expect_get_user_by_id()
+            .return_once(|_| Ok(Some(factori::create!(PublicUser))));
+        user_repository
+            .expect_get_password_hash()
+            .return_once(|_| Ok(Some(password_hash)));
+        user_repository
+    }
+
+    #[tokio::test]
+    async fn mails_link_to_new_email_and_notifies_current_one() {
+        let mut email_change_repository = MockEmailChangeRepository::new();
+        let mut user_repository = user_repository_with_password("password").await;
+        let mail_transport = InMemoryMailTransport::default();
+
+        user_repository
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(None));
+        email_change_repository
+            .expect_create_request()
+            .withf(|_, new_email, _, _| new_email == "new_johndoe@gmail.com")
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+
+        EmailChangeHandlerImpl {
+            email_change_repository: Box::new(email_change_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(mail_transport.clone()),
+            token_lifetime: Duration::hours(24),
+            confirm_url: "http://localhost/email-change".to_string(),
+        }
+        .request_change(Uuid::new_v4(), payload("password"))
+        .await
+        .expect("Failed to request email change");
+        // Lets the spawned deliveries run.
+        tokio::task::yield_now().await;
+
+        let sent = mail_transport.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].to, "new_johndoe@gmail.com");
+        assert!(sent[0]
+            .body
+            .contains("http://localhost/email-change?token="));
+        assert_eq!(sent[1].to, "johndoe@gmail.com");
+        assert!(!sent[1].body.contains("token="));
+    }
+
+    #[tokio::test]
+    async fn does_not_request_change_if_password_is_wrong() {
+        let mut email_change_repository = MockEmailChangeRepository::new();
+        let user_repository = user_repository_with_password("password").await;
+
+        email_change_repository.expect_create_request().never();
+
+        let result = EmailChangeHandlerImpl {
+            email_change_repository: Box::new(email_change_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(MockMailTransport::new()),
+            token_lifetime: Duration::hours(24),
+            confirm_url: "http://localhost/email-change".to_string(),
+        }
+        .request_change(Uuid::new_v4(), payload("wrong password"))
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::HashingError(_))));
+    }
+
+    #[tokio::test]
+    async fn does_not_request_change_if_existing_email() {
+        let mut email_change_repository = MockEmailChangeRepository::new();
+        let mut user_repository = user_repository_with_password("password").await;
+
+        user_repository
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(Some(factori::create!(PublicUser))));
+        email_change_repository.expect_create_request().never();
+
+        let result = EmailChangeHandlerImpl {
+            email_change_repository: Box::new(email_change_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(MockMailTransport::new()),
+            token_lifetime: Duration::hours(24),
+            confirm_url: "http://localhost/email-change".to_string(),
+        }
+        .request_change(Uuid::new_v4(), payload("password"))
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(RepositoryError::Conflict(ExistingEmail))
+        ));
+    }
+
+    #[tokio::test]
+    async fn does_not_change_email_with_expired_token() {
+        let request = factori::create!(
+            EmailChangeRequest,
+            expires_at: Utc::now() - Duration::minutes(1)
+        );
+        let mut email_change_repository = MockEmailChangeRepository::new();
+        let user_repository = MockUserRepository::new();
+
+        email_change_repository
+            .expect_get_request_by_hash()
+            .return_once(|_| Ok(Some(request)));
+        email_change_repository.expect_apply_request().never();
+
+        let result = EmailChangeHandlerImpl {
+            email_change_repository: Box::new(email_change_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(MockMailTransport::new()),
+            token_lifetime: Duration::hours(24),
+            confirm_url: "http://localhost/email-change".to_string(),
+        }
+        .confirm_change("token".to_string())
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::InvalidToken(_))));
+    }
+
+    #[tokio::test]
+    async fn changes_email_once() {
+        let request = factori::create!(EmailChangeRequest);
+        let user_id = request.user_id;
+        let mut email_change_repository = MockEmailChangeRepository::new();
+        let user_repository = MockUserRepository::new();
+
+        email_change_repository
+            .expect_get_request_by_hash()
+            .return_once(|_| Ok(Some(request)));
+        email_change_repository
+            .expect_apply_request()
+            .withf(move |request| request.user_id == user_id)
+            .times(1)
+            .returning(|_| Ok(true));
+
+        let result = EmailChangeHandlerImpl {
+            email_change_repository: Box::new(email_change_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(MockMailTransport::new()),
+            token_lifetime: Duration::hours(24),
+            confirm_url: "http://localhost/email-change".to_string(),
+        }
+        .confirm_change("token".to_string())
+        .await;
+
+        assert_eq!(result.unwrap(), user_id);
+    }
+
+    #[tokio::test]
+    async fn does_not_change_email_taken_meanwhile() {
+        let request = factori::create!(EmailChangeRequest);
+        let mut email_change_repository = MockEmailChangeRepository::new();
+
+        email_change_repository
+            .expect_get_request_by_hash()
+            .return_once(|_| Ok(Some(request)));
+        email_change_repository
+            .expect_apply_request()
+            .return_once(|_| Err(RepositoryError::Conflict(ExistingEmail)));
+
+        let result = EmailChangeHandlerImpl {
+            email_change_repository: Box::new(email_change_repository),
+            user_repository: Box::new(MockUserRepository::new()),
+            mail_transport: Arc::new(MockMailTransport::new()),
+            token_lifetime: Duration::hours(24),
+            confirm_url: "http://localhost/email-change".to_string(),
+        }
+        .confirm_change("token".to_string())
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(RepositoryError::Conflict(ExistingEmail))
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        email_verification::EmailVerificationRepository, user::PublicUser, user::UserRepository,
+    },
+    mail::{send_in_background, DynMailTransport, Mail},
+    repositories::error::{ErrorMessage::InvalidEmailVerificationToken, RepositoryError},
+    utils::{generate_token, hash_token, token_link},
+};
+
+pub type DynEmailVerificationHandler = dyn EmailVerificationHandler + Send + Sync;
+
+pub struct EmailVerificationHandlerImpl {
+    pub email_verification_repository: Box<dyn EmailVerificationRepository + Send + Sync>,
+    pub user_repository: Box<dyn UserRepository + Send + Sync>,
+    pub mail_transport: Arc<DynMailTransport>,
+    pub token_lifetime: Duration,
+    /// Minimum time between two verification mails to the same account.
+    pub resend_interval: Duration,
+    pub verify_url: String,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait EmailVerificationHandler {
+    /// Mails a verification token to a new user, without waiting on delivery.
+    async fn send_verification(&self, user: PublicUser) -> Result<(), RepositoryError>;
+
+    /// Sends a new token unless the account is unknown, already verified, or got one less
+    /// than `resend_interval` ago. Succeeds silently in all those cases so the route does
+    /// not reveal which emails are registered.
+    async fn resend_verification(&self, email: String) -> Result<(), RepositoryError>;
+
+    /// Consumes the token and marks the email of its user as verified.
+    async fn verify_email(&self, token: String) -> Result<Uuid, RepositoryError>;
+}
+
+#[async_trait::async_trait]
+impl EmailVerificationHandler for EmailVerificationHandlerImpl {
+    #[tracing::instrument(skip(self))]
+    async fn send_verification(&self, user: PublicUser) -> Result<(), RepositoryError> {
+        let token = generate_token();
+        self.email_verification_repository
+            .create_token(
+                user.id,
+                hash_token(&token),
+                Utc::now() + self.token_lifetime,
+            )
+            .await?;
+
+        let mail = Mail {
+            to: user.email,
+            subject: "Verify your email address".to_string(),
+            body: format!(
+                "Welcome {}!\n\n\
+                Follow this link to confirm this is your email address: {}\n\n\
+                The link expires in {} hours.",
+                user.nickname,
+                token_link(&self.verify_url, &token),
+                self.token_lifetime.num_hours()
+            ),
+        };
+        send_in_background(self.mail_transport.clone(), mail);
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn resend_verification(&self, email: String) -> Result<(), RepositoryError> {
+        let Some(user) = self.user_repository.get_user_by_email(email).await? else {
+            return Ok(());
+        };
+        if user.email_verified_at.is_some() {
+            return Ok(());
+        }
+
+        let last_token = self
+            .email_verification_repository
+            .get_token_by_user(user.id)
+            .await?;
+        if last_token.is_some_and(|token| token.creation_time + self.resend_interval > Utc::now()) {
+            tracing::info!(user_id = %user.id, "verification mail throttled");
+            return Ok(());
+        }
+
+        self.send_verification(user).await
+    }
+
+    #[tracing::instrument(skip(self, token))]
+    async fn verify_email(&self, token: String) -> Result<Uuid, RepositoryError> {
+        let invalid_token = || RepositoryError::InvalidToken(InvalidEmailVerificationToken);
+
+        let stored_token = self
+            .email_verification_repository
+            .get_token_by_hash(hash_token(&token))
+            .await?
+            .ok_or_else(invalid_token)?;
+
+        if stored_token.expires_at <= Utc::now()
+            || !self
+                .email_verification_repository
+                .delete_token(stored_token.id)
+                .await?
+        {
+            return Err(invalid_token());
+        }
+
+        self.user_repository
+            .mark_email_verified(stored_token.user_id)
+            .await?;
+        Ok(stored_token.user_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        domain::{
+            email_verification::{mocks::*, MockEmailVerificationRepository},
+            user::{mocks::*, MockUserRepository},
+        },
+        mail::{InMemoryMailTransport, MockMailTransport},
+    };
+
+    #[tokio::test]
+    async fn resends_to_unverified_user() {
+        let mut email_verification_repository = MockEmailVerificationRepository::new();
+        let mut user_repository = MockUserRepository::new();
+        let mail_transport = InMemoryMailTransport::default();
+
+        user_repository
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(Some(factori::create!(PublicUser, email_verified_at: None))));
+        email_verification_repository
+            .expect_get_token_by_user()
+            .return_once(|_| {
+                Ok(Some(factori::create!(
+                    EmailVerificationToken,
+                    creation_time: Utc::now() - Duration::minutes(5)
+                )))
+            });
+        email_verification_repository
+            .expect_create_token()
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        EmailVerificationHandlerImpl {
+            email_verification_repository: Box::new(email_verification_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(mail_transport.clone()),
+            token_lifetime: Duration::days(1),
+            resend_interval: Duration::minutes(1),
+            verify_url: "http://localhost/verify".to_string(),
+        }
+        .resend_verification("johndoe@gmail.com".to_string())
+        .await
+        .expect("Failed to resend verification");
+        // Lets the spawned delivery run.
+        tokio::task::yield_now().await;
+
+        assert!(mail_transport.sent()[0]
+            .body
+            .contains("http://localhost/verify?token="));
+    }
+
+    #[tokio::test]
+    async fn throttles_resend() {
+        let mut email_verification_repository = MockEmailVerificationRepository::new();
+        let mut user_repository = MockUserRepository::new();
+        let mut mail_transport = MockMailTransport::new();
+
+        user_repository
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(Some(factori::create!(PublicUser, email_verified_at: None))));
+        email_verification_repository
+            .expect_get_token_by_user()
+            .return_once(|_| Ok(Some(factori::create!(EmailVerificationToken))));
+        email_verification_repository.expect_create_token().never();
+        mail_transport.expect_send().never();
+
+        let result = EmailVerificationHandlerImpl {
+            email_verification_repository: Box::new(email_verification_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(mail_transport),
+            token_lifetime: Duration::days(1),
+            resend_interval: Duration::minutes(1),
+            verify_url: "http://localhost/verify".to_string(),
+        }
+        .resend_verification("johndoe@gmail.com".to_string())
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn does_not_verify_with_expired_token() {
+        let mut email_verification_repository = MockEmailVerificationRepository::new();
+        let mut user_repository = MockUserRepository::new();
+
+        email_verification_repository
+            .expect_get_token_by_hash()
+            .return_once(|_| {
+                Ok(Some(factori::create!(
+                    EmailVerificationToken,
+                    expires_at: Utc::now() - Duration::minutes(1)
+                )))
+            });
+        user_repository.expect_mark_email_verified().never();
+
+        let result = EmailVerificationHandlerImpl {
+            email_verification_repository: Box::new(email_verification_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(MockMailTransport::new()),
+            token_lifetime: Duration::days(1),
+            resend_interval: Duration::minutes(1),
+            verify_url: "http://localhost/verify".to_string(),
+        }
+        .verify_email("token".to_string())
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::InvalidToken(_))));
+    }
+}
@@ -0,0 +1,443 @@
+use std::{future::Future, net::IpAddr};
+
+use argon2::password_hash::Error;
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    domain::login_attempt::{LoginAttempt, LoginAttemptRepository},
+    repositories::error::{ErrorMessage::InvalidTwoFactorCode, RepositoryError},
+};
+
+pub type DynLoginAttemptHandler = dyn LoginAttemptHandler + Send + Sync;
+
+/// Longest an attempt can take to be checked, see [`LoginAttemptHandlerImpl::start_key_attempt`].
+const ATTEMPT_HOLD_SECONDS: i64 = 30;
+
+/// Thresholds for [`LoginAttemptHandlerImpl`]. Below its threshold a key is held back
+/// for `backoff_base * 2^(failures - 1)`, capped at `max_backoff`. Once the threshold is
+/// reached it is locked out for `lockout * 2^(failures - threshold)`, capped at
+/// `max_lockout`. Counters start over once `reset_after` has passed since the last
+/// failure.
+#[derive(Debug, Clone)]
+pub struct LoginAttemptPolicy {
+    pub max_attempts_per_account: i32,
+    pub max_attempts_per_ip: i32,
+    pub backoff_base: Duration,
+    pub max_backoff: Duration,
+    pub lockout: Duration,
+    pub max_lockout: Duration,
+    pub reset_after: Duration,
+}
+
+impl Default for LoginAttemptPolicy {
+    fn default() -> Self {
+        LoginAttemptPolicy {
+            max_attempts_per_account: 5,
+            max_attempts_per_ip: 20,
+            backoff_base: Duration::seconds(1),
+            max_backoff: Duration::minutes(1),
+            lockout: Duration::minutes(15),
+            max_lockout: Duration::hours(24),
+            reset_after: Duration::hours(24),
+        }
+    }
+}
+
+impl LoginAttemptPolicy {
+    fn delay(&self, failed_attempts: i32, threshold: i32) -> Duration {
+        if failed_attempts >= threshold {
+            exponential(self.lockout, failed_attempts - threshold, self.max_lockout)
+        } else {
+            exponential(self.backoff_base, failed_attempts - 1, self.max_backoff)
+        }
+    }
+}
+
+fn exponential(base: Duration, exponent: i32, max: Duration) -> Duration {
+    let factor = 1i64 << exponent.clamp(0, 32);
+    let milliseconds = base.num_milliseconds().saturating_mul(factor);
+    Duration::milliseconds(milliseconds.min(max.num_milliseconds()))
+}
+
+pub struct LoginAttemptHandlerImpl {
+    pub login_attempt_repository: Box<dyn LoginAttemptRepository + Send + Sync>,
+    pub policy: LoginAttemptPolicy,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait LoginAttemptHandler {
+    /// Counts an attempt against the account and the client address before the password
+    /// is checked, so that concurrent requests cannot get more guesses in than the policy
+    /// allows. Fails with [`RepositoryError::TooManyAttempts`] while either is held back.
+    async fn start_attempt(&self, email: String, ip: Option<IpAddr>)
+        -> Result<(), RepositoryError>;
+
+    /// Holds the account and the address back after the attempt failed.
+    async fn record_failure(
+        &self,
+        email: String,
+        ip: Option<IpAddr>,
+    ) -> Result<(), RepositoryError>;
+
+    /// Takes back an attempt that neither failed nor succeeded, leaving earlier failures
+    /// counted.
+    async fn release_attempt(
+        &self,
+        email: String,
+        ip: Option<IpAddr>,
+    ) -> Result<(), RepositoryError>;
+
+    /// Clears the account counter once the user is signed in. The address counter is left
+    /// alone so one valid account does not let a client keep guessing others.
+    async fn record_success(&self, email: String) -> Result<(), RepositoryError>;
+
+    async fn unlock_account(&self, email: String) -> Result<(), RepositoryError>;
+}
+
+/// Runs `check` of a password or a second factor as an attempt of the account, see
+/// [`LoginAttemptHandler::start_attempt`]. Wrong secrets count as failures, anything else
+/// is taken back so that earlier failures are only cleared by a completed sign-in.
+pub async fn throttled<T>(
+    login_attempt_handler: &DynLoginAttemptHandler,
+    email: String,
+    ip: Option<IpAddr>,
+    check: impl Future<Output = Result<T, RepositoryError>>,
+) -> Result<T, RepositoryError> {
+    login_attempt_handler
+        .start_attempt(email.clone(), ip)
+        .await?;
+
+    let result = check.await;
+    match &result {
+        Err(
+            RepositoryError::InvalidCredentials
+            | RepositoryError::HashingError(Error::Password)
+            | RepositoryError::InvalidToken(InvalidTwoFactorCode),
+        ) => login_attempt_handler.record_failure(email, ip).await?,
+        _ => login_attempt_handler.release_attempt(email, ip).await?,
+    }
+    result
+}
+
+fn account_key(email: &str) -> String {
+    format!("account:{}", email.trim().to_lowercase())
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{ip}")
+}
+
+#[async_trait::async_trait]
+impl LoginAttemptHandler for LoginAttemptHandlerImpl {
+    #[tracing::instrument(skip(self))]
+    async fn start_attempt(
+        &self,
+        email: String,
+        ip: Option<IpAddr>,
+    ) -> Result<(), RepositoryError> {
+        let account_key = account_key(&email);
+        self.start_key_attempt(account_key.clone(), self.policy.max_attempts_per_account)
+            .await?;
+        if let Some(ip) = ip {
+            let result = self
+                .start_key_attempt(ip_key(ip), self.policy.max_attempts_per_ip)
+                .await;
+            if result.is_err() {
+                self.login_attempt_repository
+                    .take_back_attempt(account_key)
+                    .await?;
+            }
+            result?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn record_failure(
+        &self,
+        email: String,
+        ip: Option<IpAddr>,
+    ) -> Result<(), RepositoryError> {
+        let now = Utc::now();
+        let thresholds: Vec<(String, i32)> =
+            std::iter::once((account_key(&email), self.policy.max_attempts_per_account))
+                .chain(ip.map(|ip| (ip_key(ip), self.policy.max_attempts_per_ip)))
+                .collect();
+        let keys = thresholds.iter().map(|(key, _)| key.clone()).collect();
+
+        for LoginAttempt {
+            key,
+            failed_attempts,
+            ..
+        } in self.login_attempt_repository.get_attempts(keys).await?
+        {
+            let Some(&(_, threshold)) = thresholds.iter().find(|(known, _)| *known == key) else {
+                continue;
+            };
+            if failed_attempts >= threshold {
+                tracing::warn!(
+                    key,
+                    failed_attempts,
+                    "Locking out after repeated login failures"
+                );
+            }
+            self.login_attempt_repository
+                .set_blocked_until(key, now + self.policy.delay(failed_attempts, threshold))
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn release_attempt(
+        &self,
+        email: String,
+        ip: Option<IpAddr>,
+    ) -> Result<(), RepositoryError> {
+        self.login_attempt_repository
+            .take_back_attempt(account_key(&email))
+            .await?;
+        if let Some(ip) = ip {
+            self.login_attempt_repository
+                .take_back_attempt(ip_key(ip))
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn record_success(&self, email: String) -> Result<(), RepositoryError> {
+        self.login_attempt_repository
+            .delete_attempts(account_key(&email))
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn unlock_account(&self, email: String) -> Result<(), RepositoryError> {
+        self.login_attempt_repository
+            .delete_attempts(account_key(&email))
+            .await
+    }
+}
+
+impl LoginAttemptHandlerImpl {
+    /// A key reaching its threshold is held while its attempt is checked, so that only
+    /// one attempt at a time gets through once it is locked out. The hold is lifted or
+    /// replaced by a lockout when the attempt is over, and expires on its own should the
+    /// request never finish.
+    async fn start_key_attempt(&self, key: String, threshold: i32) -> Result<(), RepositoryError> {
+        let now = Utc::now();
+        let attempt = self
+            .login_attempt_repository
+            .start_attempt(
+                key.clone(),
+                threshold,
+                now - self.policy.reset_after,
+                now + Duration::seconds(ATTEMPT_HOLD_SECONDS),
+            )
+            .await?;
+        if attempt.is_some() {
+            return Ok(());
+        }
+
+        let blocked_until = self
+            .login_attempt_repository
+            .get_attempts(vec![key])
+            .await?
+            .into_iter()
+            .find_map(|attempt| attempt.blocked_until)
+            .unwrap_or(now);
+        Err(RepositoryError::TooManyAttempts(retry_after_seconds(
+            blocked_until,
+            now,
+        )))
+    }
+}
+
+fn retry_after_seconds(blocked_until: DateTime<Utc>, now: DateTime<Utc>) -> u64 {
+    // Round up so clients never retry a moment too early.
+    let remaining = blocked_until - now;
+    let seconds = remaining.num_seconds() + i64::from(remaining.num_milliseconds() % 1000 > 0);
+    seconds.max(1) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::login_attempt::MockLoginAttemptRepository;
+
+    fn attempt(key: &str, failed_attempts: i32) -> LoginAttempt {
+        LoginAttempt {
+            key: key.to_string(),
+            failed_attempts,
+            blocked_until: None,
+        }
+    }
+
+    #[test]
+    fn backs_off_exponentially_then_locks_out() {
+        let policy = LoginAttemptPolicy::default();
+
+        assert_eq!(policy.delay(1, 5), Duration::seconds(1));
+        assert_eq!(policy.delay(4, 5), Duration::seconds(8));
+        assert_eq!(policy.delay(5, 5), Duration::minutes(15));
+        assert_eq!(policy.delay(6, 5), Duration::minutes(30));
+        assert_eq!(policy.delay(100, 5), Duration::hours(24));
+        assert_eq!(policy.delay(19, 20), Duration::minutes(1));
+    }
+
+    #[tokio::test]
+    async fn rejects_blocked_account_with_retry_after() {
+        let mut repo = MockLoginAttemptRepository::new();
+
+        repo.expect_start_attempt()
+            .withf(|key, _, _, _| key == "account:user@example.com")
+            .return_once(|_, _, _, _| Ok(None));
+        repo.expect_get_attempts()
+            .withf(|keys| keys == &["account:user@example.com"])
+            .return_once(|_| {
+                Ok(vec![LoginAttempt {
+                    blocked_until: Some(Utc::now() + Duration::seconds(90)),
+                    ..attempt("account:user@example.com", 5)
+                }])
+            });
+
+        let result = LoginAttemptHandlerImpl {
+            login_attempt_repository: Box::new(repo),
+            policy: LoginAttemptPolicy::default(),
+        }
+        .start_attempt(
+            " User@Example.com".to_string(),
+            Some("127.0.0.1".parse().unwrap()),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(RepositoryError::TooManyAttempts(89..=90))
+        ));
+    }
+
+    #[tokio::test]
+    async fn passes_hold_for_key_reaching_threshold() {
+        let mut repo = MockLoginAttemptRepository::new();
+
+        repo.expect_start_attempt()
+            .withf(|key, threshold, _, hold_until| {
+                key == "account:user@example.com"
+                    && *threshold == 5
+                    && *hold_until > Utc::now() + Duration::seconds(20)
+            })
+            .times(1)
+            .return_once(|key, _, _, _| Ok(Some(attempt(&key, 1))));
+
+        let result = LoginAttemptHandlerImpl {
+            login_attempt_repository: Box::new(repo),
+            policy: LoginAttemptPolicy::default(),
+        }
+        .start_attempt("user@example.com".to_string(), None)
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn takes_account_attempt_back_when_address_is_held_back() {
+        let mut repo = MockLoginAttemptRepository::new();
+
+        repo.expect_start_attempt()
+            .withf(|key, _, _, _| key == "account:user@example.com")
+            .return_once(|key, _, _, _| Ok(Some(attempt(&key, 1))));
+        repo.expect_start_attempt()
+            .withf(|key, threshold, _, _| key == "ip:127.0.0.1" && *threshold == 20)
+            .return_once(|_, _, _, _| Ok(None));
+        repo.expect_get_attempts().return_once(|_| {
+            Ok(vec![LoginAttempt {
+                blocked_until: Some(Utc::now() + Duration::seconds(30)),
+                ..attempt("ip:127.0.0.1", 20)
+            }])
+        });
+        repo.expect_take_back_attempt()
+            .withf(|key| key == "account:user@example.com")
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let result = LoginAttemptHandlerImpl {
+            login_attempt_repository: Box::new(repo),
+            policy: LoginAttemptPolicy::default(),
+        }
+        .start_attempt(
+            "user@example.com".to_string(),
+            Some("127.0.0.1".parse().unwrap()),
+        )
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::TooManyAttempts(_))));
+    }
+
+    #[tokio::test]
+    async fn locks_out_account_at_threshold() {
+        let mut repo = MockLoginAttemptRepository::new();
+
+        repo.expect_get_attempts()
+            .return_once(|_| Ok(vec![attempt("account:user@example.com", 5)]));
+        repo.expect_set_blocked_until()
+            .withf(|key, until| {
+                key == "account:user@example.com" && *until > Utc::now() + Duration::minutes(14)
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let result = LoginAttemptHandlerImpl {
+            login_attempt_repository: Box::new(repo),
+            policy: LoginAttemptPolicy::default(),
+        }
+        .record_failure("user@example.com".to_string(), None)
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn counts_wrong_secret_as_failure() {
+        let mut login_attempt_handler = MockLoginAttemptHandler::new();
+        login_attempt_handler
+            .expect_start_attempt()
+            .times(1)
+            .returning(|_, _| Ok(()));
+        login_attempt_handler
+            .expect_record_failure()
+            .times(1)
+            .returning(|_, _| Ok(()));
+        login_attempt_handler.expect_release_attempt().never();
+
+        let result: Result<(), _> = throttled(
+            &login_attempt_handler,
+            "user@example.com".to_string(),
+            None,
+            async { Err(RepositoryError::InvalidToken(InvalidTwoFactorCode)) },
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn does_not_check_secret_while_held_back() {
+        let mut login_attempt_handler = MockLoginAttemptHandler::new();
+        login_attempt_handler
+            .expect_start_attempt()
+            .returning(|_, _| Err(RepositoryError::TooManyAttempts(60)));
+
+        let result: Result<(), _> = throttled(
+            &login_attempt_handler,
+            "user@example.com".to_string(),
+            None,
+            async { panic!("checked while held back") },
+        )
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::TooManyAttempts(60))));
+    }
+}
@@ -0,0 +1,238 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    domain::{magic_link::MagicLinkRepository, user::UserRepository},
+    mail::{send_in_background, DynMailTransport, Mail},
+    repositories::error::{ErrorMessage::InvalidMagicLinkToken, RepositoryError},
+    utils::{generate_token, hash_token, token_link},
+};
+
+pub type DynMagicLinkHandler = dyn MagicLinkHandler + Send + Sync;
+
+/// Passwordless sign-in: the mailed link carries a random token only its hash is stored
+/// of, so the link cannot be forged or replayed, and expires after `token_lifetime`.
+pub struct MagicLinkHandlerImpl {
+    pub magic_link_repository: Box<dyn MagicLinkRepository + Send + Sync>,
+    pub user_repository: Box<dyn UserRepository + Send + Sync>,
+    pub mail_transport: Arc<DynMailTransport>,
+    pub token_lifetime: Duration,
+    /// Minimum time between two links mailed to the same account.
+    pub resend_interval: Duration,
+    pub login_url: String,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait MagicLinkHandler {
+    /// Mails a sign-in link unless the account is unknown or got one less than
+    /// `resend_interval` ago. Succeeds silently in those cases without waiting on delivery
+    /// otherwise, so callers cannot tell whether the email is registered.
+    async fn request_link(&self, email: String) -> Result<(), RepositoryError>;
+
+    /// Consumes the token, returning the user it belonged to. Following the link proves
+    /// the user owns the address, so the email is marked as verified too.
+    async fn consume_link(&self, token: String) -> Result<Uuid, RepositoryError>;
+}
+
+#[async_trait::async_trait]
+impl MagicLinkHandler for MagicLinkHandlerImpl {
+    #[tracing::instrument(skip(self))]
+    async fn request_link(&self, email: String) -> Result<(), RepositoryError> {
+        let Some(user) = self.user_repository.get_user_by_email(email).await? else {
+            return Ok(());
+        };
+
+        let last_token = self
+            .magic_link_repository
+            .get_token_by_user(user.id)
+            .await?;
+        if last_token.is_some_and(|token| token.creation_time + self.resend_interval > Utc::now()) {
+            tracing::info!(user_id = %user.id, "magic link mail throttled");
+            return Ok(());
+        }
+
+        let token = generate_token();
+        self.magic_link_repository
+            .create_token(
+                user.id,
+                hash_token(&token),
+                Utc::now() + self.token_lifetime,
+            )
+            .await?;
+
+        let mail = Mail {
+            to: user.email,
+            subject: "Your sign-in link".to_string(),
+            body: format!(
+                "Follow this link to sign in as {}: {}\n\n\
+                The link expires in {} minutes and works once. If you did not ask for it, \
+                ignore this mail.",
+                user.nickname,
+                token_link(&self.login_url, &token),
+                self.token_lifetime.num_minutes()
+            ),
+        };
+        send_in_background(self.mail_transport.clone(), mail);
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self, token))]
+    async fn consume_link(&self, token: String) -> Result<Uuid, RepositoryError> {
+        let invalid_token = || RepositoryError::InvalidToken(InvalidMagicLinkToken);
+
+        let stored_token = self
+            .magic_link_repository
+            .get_token_by_hash(hash_token(&token))
+            .await?
+            .ok_or_else(invalid_token)?;
+
+        if stored_token.expires_at <= Utc::now()
+            || !self
+                .magic_link_repository
+                .delete_token(stored_token.id)
+                .await?
+        {
+            return Err(invalid_token());
+        }
+
+        self.user_repository
+            .mark_email_verified(stored_token.user_id)
+            .await?;
+        Ok(stored_token.user_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        domain::{
+            magic_link::{mocks::*, MockMagicLinkRepository},
+            user::{mocks::*, MockUserRepository},
+        },
+        mail::{InMemoryMailTransport, MockMailTransport},
+    };
+
+    #[tokio::test]
+    async fn mails_link_to_known_email() {
+        let mut magic_link_repository = MockMagicLinkRepository::new();
+        let mut user_repository = MockUserRepository::new();
+        let mail_transport = InMemoryMailTransport::default();
+
+        user_repository
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(Some(factori::create!(PublicUser))));
+        magic_link_repository
+            .expect_get_token_by_user()
+            .return_once(|_| Ok(None));
+        magic_link_repository
+            .expect_create_token()
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        MagicLinkHandlerImpl {
+            magic_link_repository: Box::new(magic_link_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(mail_transport.clone()),
+            token_lifetime: Duration::minutes(15),
+            resend_interval: Duration::seconds(60),
+            login_url: "http://localhost/magic-link".to_string(),
+        }
+        .request_link("johndoe@gmail.com".to_string())
+        .await
+        .expect("Failed to request a magic link");
+        // Lets the spawned delivery run.
+        tokio::task::yield_now().await;
+
+        let sent = mail_transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "johndoe@gmail.com");
+        assert!(sent[0].body.contains("http://localhost/magic-link?token="));
+    }
+
+    #[tokio::test]
+    async fn succeeds_silently_for_unknown_email() {
+        let mut magic_link_repository = MockMagicLinkRepository::new();
+        let mut user_repository = MockUserRepository::new();
+        let mut mail_transport = MockMailTransport::new();
+
+        user_repository
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(None));
+        magic_link_repository.expect_create_token().never();
+        mail_transport.expect_send().never();
+
+        let result = MagicLinkHandlerImpl {
+            magic_link_repository: Box::new(magic_link_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(mail_transport),
+            token_lifetime: Duration::minutes(15),
+            resend_interval: Duration::seconds(60),
+            login_url: "http://localhost/magic-link".to_string(),
+        }
+        .request_link("nobody@gmail.com".to_string())
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn throttles_links_to_same_account() {
+        let mut magic_link_repository = MockMagicLinkRepository::new();
+        let mut user_repository = MockUserRepository::new();
+        let mut mail_transport = MockMailTransport::new();
+
+        user_repository
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(Some(factori::create!(PublicUser))));
+        magic_link_repository
+            .expect_get_token_by_user()
+            .return_once(|_| Ok(Some(factori::create!(MagicLinkToken))));
+        magic_link_repository.expect_create_token().never();
+        mail_transport.expect_send().never();
+
+        let result = MagicLinkHandlerImpl {
+            magic_link_repository: Box::new(magic_link_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(mail_transport),
+            token_lifetime: Duration::minutes(15),
+            resend_interval: Duration::seconds(60),
+            login_url: "http://localhost/magic-link".to_string(),
+        }
+        .request_link("johndoe@gmail.com".to_string())
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn does_not_reuse_token() {
+        let stored_token = factori::create!(MagicLinkToken);
+        let mut magic_link_repository = MockMagicLinkRepository::new();
+        let mut user_repository = MockUserRepository::new();
+
+        magic_link_repository
+            .expect_get_token_by_hash()
+            .return_once(|_| Ok(Some(stored_token)));
+        magic_link_repository
+            .expect_delete_token()
+            .return_once(|_| Ok(false));
+        user_repository.expect_mark_email_verified().never();
+
+        let result = MagicLinkHandlerImpl {
+            magic_link_repository: Box::new(magic_link_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(MockMailTransport::new()),
+            token_lifetime: Duration::minutes(15),
+            resend_interval: Duration::seconds(60),
+            login_url: "http://localhost/magic-link".to_string(),
+        }
+        .consume_link("token".to_string())
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::InvalidToken(_))));
+    }
+}
@@ -0,0 +1,716 @@
+use chrono::{Duration, Utc};
+use reqwest::Url;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        oauth::{
+            payload::{AuthorizationRequest, NewOAuthClientPayload},
+            OAuthAuthorizationCode, OAuthClient, OAuthErrorCode, OAuthGrant, OAuthRepository,
+        },
+        personal_access_token::Scope,
+    },
+    repositories::error::RepositoryError,
+    utils::{code_challenge, generate_token, hash_token},
+};
+
+pub type DynOAuthHandler = dyn OAuthHandler + Send + Sync;
+
+/// Length of an S256 PKCE challenge, a base64url encoded SHA-256 digest.
+const CODE_CHALLENGE_LENGTH: usize = 43;
+
+/// The authorization server third-party apps get access tokens from, either on behalf
+/// of a user who consented through the authorization code grant, or for themselves
+/// through the client credentials grant. PKCE is required of every client.
+pub struct OAuthHandlerImpl {
+    pub oauth_repository: Box<dyn OAuthRepository + Send + Sync>,
+    /// How long the client has to redeem an authorization code.
+    pub code_lifetime: Duration,
+}
+
+/// How a client authenticates at the token endpoint. Public clients only send their id.
+#[derive(Debug, Clone)]
+pub struct ClientCredentials {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+/// What an access token is to be issued for, signed by the route with
+/// [`crate::auth::create_oauth_jwt`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenGrant {
+    /// The user who consented, or the client itself.
+    pub subject: Uuid,
+    pub client_id: Uuid,
+    pub scopes: Vec<Scope>,
+    /// The consent the token is issued under, none for the client credentials grant.
+    pub grant_id: Option<Uuid>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait OAuthHandler {
+    /// Registers a client for the user, returning it along with its secret if it is
+    /// confidential. The secret is not stored and cannot be shown again.
+    async fn register_client(
+        &self,
+        owner_id: Uuid,
+        payload: NewOAuthClientPayload,
+    ) -> Result<(OAuthClient, Option<String>), RepositoryError>;
+
+    async fn get_user_clients(&self, owner_id: Uuid) -> Result<Vec<OAuthClient>, RepositoryError>;
+
+    async fn delete_client(&self, owner_id: Uuid, id: Uuid) -> Result<(), RepositoryError>;
+
+    /// Checks an authorization request before asking the user for consent, returning the
+    /// client and the scopes it asks for.
+    async fn check_authorization(
+        &self,
+        request: AuthorizationRequest,
+    ) -> Result<(OAuthClient, Vec<Scope>), RepositoryError>;
+
+    /// Records the decision of the user, returning where to send them back to: the
+    /// redirect URI with an authorization code, or with `access_denied`.
+    async fn authorize(
+        &self,
+        user_id: Uuid,
+        request: AuthorizationRequest,
+        approved: bool,
+    ) -> Result<String, RepositoryError>;
+
+    /// The authorization code grant. The code can only be redeemed once, by the client
+    /// it was issued to, with the verifier of its PKCE challenge, and not after the user
+    /// revoked their consent.
+    async fn exchange_code(
+        &self,
+        client: ClientCredentials,
+        code: String,
+        redirect_uri: String,
+        code_verifier: String,
+    ) -> Result<TokenGrant, RepositoryError>;
+
+    /// The client credentials grant, for confidential clients only. `scope` narrows the
+    /// scopes of the client.
+    async fn client_credentials(
+        &self,
+        client: ClientCredentials,
+        scope: Option<String>,
+    ) -> Result<TokenGrant, RepositoryError>;
+
+    /// The clients the user consented to.
+    async fn get_user_grants(&self, user_id: Uuid) -> Result<Vec<OAuthGrant>, RepositoryError>;
+
+    /// Withdraws the consent of the user to the client, returning the id of the grant
+    /// for its tokens to be revoked.
+    async fn revoke_grant(&self, user_id: Uuid, client_id: Uuid) -> Result<Uuid, RepositoryError>;
+}
+
+#[async_trait::async_trait]
+impl OAuthHandler for OAuthHandlerImpl {
+    #[tracing::instrument(skip(self))]
+    async fn register_client(
+        &self,
+        owner_id: Uuid,
+        payload: NewOAuthClientPayload,
+    ) -> Result<(OAuthClient, Option<String>), RepositoryError> {
+        let client_secret = payload.confidential.then(generate_token);
+
+        let client = self
+            .oauth_repository
+            .create_client(owner_id, payload, client_secret.as_deref().map(hash_token))
+            .await?;
+        Ok((client, client_secret))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_user_clients(&self, owner_id: Uuid) -> Result<Vec<OAuthClient>, RepositoryError> {
+        self.oauth_repository.get_clients_by_owner(owner_id).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_client(&self, owner_id: Uuid, id: Uuid) -> Result<(), RepositoryError> {
+        let deleted = self.oauth_repository.delete_client(owner_id, id).await?;
+        if !deleted {
+            return Err(RepositoryError::NotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn check_authorization(
+        &self,
+        request: AuthorizationRequest,
+    ) -> Result<(OAuthClient, Vec<Scope>), RepositoryError> {
+        let client = self
+            .oauth_repository
+            .get_client(request.client_id)
+            .await?
+            .ok_or_else(|| oauth_error(OAuthErrorCode::InvalidClient, "Unknown client"))?;
+
+        if !client.redirect_uris.contains(&request.redirect_uri) {
+            return Err(oauth_error(
+                OAuthErrorCode::InvalidRequest,
+                "The redirect URI is not registered for this client",
+            ));
+        }
+        if request.response_type != "code" {
+            return Err(oauth_error(
+                OAuthErrorCode::UnsupportedResponseType,
+                "Only the code response type is supported",
+            ));
+        }
+        if request.code_challenge_method != "S256"
+            || request.code_challenge.len() != CODE_CHALLENGE_LENGTH
+        {
+            return Err(oauth_error(
+                OAuthErrorCode::InvalidRequest,
+                "A PKCE code challenge using S256 is required",
+            ));
+        }
+
+        let scopes = requested_scopes(request.scope.as_deref(), &client.scopes)?;
+        Ok((client, scopes))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn authorize(
+        &self,
+        user_id: Uuid,
+        request: AuthorizationRequest,
+        approved: bool,
+    ) -> Result<String, RepositoryError> {
+        let (client, scopes) = self.check_authorization(request.clone()).await?;
+
+        let mut redirect_url = Url::parse(&request.redirect_uri)
+            .map_err(|_| oauth_error(OAuthErrorCode::InvalidRequest, "Invalid redirect URI"))?;
+        let outcome = if approved {
+            self.oauth_repository
+                .save_grant(user_id, client.id, scopes.clone())
+                .await?;
+            let code = generate_token();
+            self.oauth_repository
+                .create_authorization_code(
+                    hash_token(&code),
+                    OAuthAuthorizationCode {
+                        client_id: client.id,
+                        user_id,
+                        redirect_uri: request.redirect_uri,
+                        scopes,
+                        code_challenge: request.code_challenge,
+                        expires_at: Utc::now() + self.code_lifetime,
+                    },
+                )
+                .await?;
+            ("code", code)
+        } else {
+            ("error", OAuthErrorCode::AccessDenied.to_string())
+        };
+
+        let mut query = redirect_url.query_pairs_mut();
+        query.append_pair(outcome.0, &outcome.1);
+        if let Some(state) = &request.state {
+            query.append_pair("state", state);
+        }
+        drop(query);
+
+        Ok(redirect_url.into())
+    }
+
+    #[tracing::instrument(skip(self, client, code, code_verifier))]
+    async fn exchange_code(
+        &self,
+        client: ClientCredentials,
+        code: String,
+        redirect_uri: String,
+        code_verifier: String,
+    ) -> Result<TokenGrant, RepositoryError> {
+        let client = self.authenticate_client(client).await?;
+        let invalid_grant = || oauth_error(OAuthErrorCode::InvalidGrant, "Invalid or expired code");
+
+        let authorization_code = self
+            .oauth_repository
+            .take_authorization_code(hash_token(&code))
+            .await?
+            .filter(|authorization_code| authorization_code.expires_at > Utc::now())
+            .ok_or_else(invalid_grant)?;
+
+        let verifier_matches: bool = code_challenge(&code_verifier)
+            .as_bytes()
+            .ct_eq(authorization_code.code_challenge.as_bytes())
+            .into();
+        if authorization_code.client_id != client.id
+            || authorization_code.redirect_uri != redirect_uri
+            || !verifier_matches
+        {
+            return Err(invalid_grant());
+        }
+
+        let grant = self
+            .oauth_repository
+            .get_grant(authorization_code.user_id, client.id)
+            .await?
+            .ok_or_else(invalid_grant)?;
+
+        Ok(TokenGrant {
+            subject: authorization_code.user_id,
+            client_id: client.id,
+            scopes: authorization_code.scopes,
+            grant_id: Some(grant.id),
+        })
+    }
+
+    #[tracing::instrument(skip(self, client))]
+    async fn client_credentials(
+        &self,
+        client: ClientCredentials,
+        scope: Option<String>,
+    ) -> Result<TokenGrant, RepositoryError> {
+        let client = self.authenticate_client(client).await?;
+        if !client.confidential {
+            return Err(oauth_error(
+                OAuthErrorCode::UnauthorizedClient,
+                "Only confidential clients can use the client credentials grant",
+            ));
+        }
+
+        Ok(TokenGrant {
+            subject: client.id,
+            client_id: client.id,
+            scopes: requested_scopes(scope.as_deref(), &client.scopes)?,
+            grant_id: None,
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_user_grants(&self, user_id: Uuid) -> Result<Vec<OAuthGrant>, RepositoryError> {
+        self.oauth_repository.get_grants_by_user(user_id).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_grant(&self, user_id: Uuid, client_id: Uuid) -> Result<Uuid, RepositoryError> {
+        self.oauth_repository
+            .delete_grant(user_id, client_id)
+            .await?
+            .ok_or(RepositoryError::NotFound)
+    }
+}
+
+impl OAuthHandlerImpl {
+    /// Confidential clients must send their secret, public ones must not send any.
+    async fn authenticate_client(
+        &self,
+        credentials: ClientCredentials,
+    ) -> Result<OAuthClient, RepositoryError> {
+        let invalid_client = || oauth_error(OAuthErrorCode::InvalidClient, "Invalid client");
+
+        let client_id = credentials
+            .client_id
+            .parse()
+            .map_err(|_| invalid_client())?;
+        let client = self
+            .oauth_repository
+            .get_client(client_id)
+            .await?
+            .ok_or_else(invalid_client)?;
+
+        let secret_matches = match (&client.secret_hash, &credentials.client_secret) {
+            (Some(secret_hash), Some(client_secret)) => hash_token(client_secret)
+                .as_bytes()
+                .ct_eq(secret_hash.as_bytes())
+                .into(),
+            (None, None) => true,
+            _ => false,
+        };
+        if !secret_matches {
+            return Err(invalid_client());
+        }
+        Ok(client)
+    }
+}
+
+/// Parses the space separated `scope` of a request, defaulting to every scope of the
+/// client. Asking for one the client was not registered with is an error.
+fn requested_scopes(scope: Option<&str>, allowed: &[Scope]) -> Result<Vec<Scope>, RepositoryError> {
+    let Some(scope) = scope.filter(|scope| !scope.trim().is_empty()) else {
+        return Ok(allowed.to_vec());
+    };
+
+    let mut scopes = Vec::new();
+    for name in scope.split_whitespace() {
+        let scope: Scope = name
+            .parse()
+            .ok()
+            .filter(|scope| allowed.contains(scope))
+            .ok_or_else(|| {
+                oauth_error(
+                    OAuthErrorCode::InvalidScope,
+                    &format!("The {name} scope is not available to this client"),
+                )
+            })?;
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    Ok(scopes)
+}
+
+fn oauth_error(code: OAuthErrorCode, description: &str) -> RepositoryError {
+    RepositoryError::OAuth(code, description.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::oauth::{mocks::*, MockOAuthRepository};
+
+    const REDIRECT_URI: &str = "https://partner.example.com/callback";
+
+    fn authorization_request(client_id: Uuid, code_verifier: &str) -> AuthorizationRequest {
+        AuthorizationRequest {
+            response_type: "code".to_string(),
+            client_id,
+            redirect_uri: REDIRECT_URI.to_string(),
+            scope: Some("users:read".to_string()),
+            state: Some("xyz".to_string()),
+            code_challenge: code_challenge(code_verifier),
+            code_challenge_method: "S256".to_string(),
+        }
+    }
+
+    fn public_client(id: Uuid) -> ClientCredentials {
+        ClientCredentials {
+            client_id: id.to_string(),
+            client_secret: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn registers_confidential_client_storing_only_secret_hash() {
+        let mut repo = MockOAuthRepository::new();
+        repo.expect_create_client()
+            .withf(|_, _, secret_hash| secret_hash.as_ref().is_some_and(|hash| hash.len() == 64))
+            .return_once(|_, _, _| Ok(factori::create!(OAuthClient, confidential: true)));
+
+        let (_, secret) = OAuthHandlerImpl {
+            oauth_repository: Box::new(repo),
+            code_lifetime: Duration::minutes(1),
+        }
+        .register_client(
+            Uuid::new_v4(),
+            NewOAuthClientPayload {
+                name: "Partner app".to_string(),
+                redirect_uris: vec![REDIRECT_URI.to_string()],
+                scopes: vec![Scope::UsersRead],
+                confidential: true,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(secret.is_some());
+    }
+
+    #[tokio::test]
+    async fn rejects_unregistered_redirect_uri() {
+        let client = factori::create!(OAuthClient);
+        let mut request = authorization_request(client.id, "verifier");
+        request.redirect_uri = "https://attacker.example.com/callback".to_string();
+        let mut repo = MockOAuthRepository::new();
+        repo.expect_get_client().return_once(|_| Ok(Some(client)));
+
+        let result = OAuthHandlerImpl {
+            oauth_repository: Box::new(repo),
+            code_lifetime: Duration::minutes(1),
+        }
+        .check_authorization(request)
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(RepositoryError::OAuth(OAuthErrorCode::InvalidRequest, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_scope_the_client_was_not_registered_with() {
+        let client = factori::create!(OAuthClient, scopes: vec![Scope::UsersRead]);
+        let mut request = authorization_request(client.id, "verifier");
+        request.scope = Some("users:read users:write".to_string());
+        let mut repo = MockOAuthRepository::new();
+        repo.expect_get_client().return_once(|_| Ok(Some(client)));
+
+        let result = OAuthHandlerImpl {
+            oauth_repository: Box::new(repo),
+            code_lifetime: Duration::minutes(1),
+        }
+        .check_authorization(request)
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(RepositoryError::OAuth(OAuthErrorCode::InvalidScope, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn redirects_with_access_denied_without_issuing_code() {
+        let client = factori::create!(OAuthClient);
+        let request = authorization_request(client.id, "verifier");
+        let mut repo = MockOAuthRepository::new();
+        repo.expect_get_client().return_once(|_| Ok(Some(client)));
+        repo.expect_create_authorization_code().never();
+
+        let redirect_url = OAuthHandlerImpl {
+            oauth_repository: Box::new(repo),
+            code_lifetime: Duration::minutes(1),
+        }
+        .authorize(Uuid::new_v4(), request, false)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            redirect_url,
+            format!("{REDIRECT_URI}?error=access_denied&state=xyz")
+        );
+    }
+
+    #[tokio::test]
+    async fn issues_code_redeemable_once_with_its_verifier() {
+        let client = factori::create!(OAuthClient);
+        let client_id = client.id;
+        let user_id = Uuid::new_v4();
+        let code_verifier = generate_token();
+        let request = authorization_request(client_id, &code_verifier);
+        let mut repo = MockOAuthRepository::new();
+        repo.expect_get_client()
+            .return_once(move |_| Ok(Some(client)));
+        repo.expect_save_grant()
+            .withf(move |user, client, scopes| {
+                *user == user_id && *client == client_id && scopes == &[Scope::UsersRead]
+            })
+            .times(1)
+            .return_once(|_, _, _| Ok(Uuid::new_v4()));
+        repo.expect_create_authorization_code()
+            .times(1)
+            .return_once(|_, _| Ok(()));
+
+        let redirect_url = OAuthHandlerImpl {
+            oauth_repository: Box::new(repo),
+            code_lifetime: Duration::minutes(1),
+        }
+        .authorize(user_id, request.clone(), true)
+        .await
+        .unwrap();
+        let code = Url::parse(&redirect_url)
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == "code")
+            .map(|(_, value)| value.to_string())
+            .unwrap();
+
+        let mut repo = MockOAuthRepository::new();
+        let client = factori::create!(OAuthClient, id: client_id);
+        repo.expect_get_client()
+            .return_once(move |_| Ok(Some(client)));
+        let challenge = request.code_challenge.clone();
+        let code_hash = hash_token(&code);
+        repo.expect_take_authorization_code()
+            .withf(move |hash| *hash == code_hash)
+            .return_once(move |_| {
+                Ok(Some(factori::create!(
+                    OAuthAuthorizationCode,
+                    client_id: client_id,
+                    user_id: user_id,
+                    code_challenge: challenge
+                )))
+            });
+        let grant_id = Uuid::new_v4();
+        repo.expect_get_grant()
+            .return_once(move |_, _| Ok(Some(factori::create!(OAuthGrant, id: grant_id))));
+
+        let grant = OAuthHandlerImpl {
+            oauth_repository: Box::new(repo),
+            code_lifetime: Duration::minutes(1),
+        }
+        .exchange_code(
+            public_client(client_id),
+            code,
+            REDIRECT_URI.to_string(),
+            code_verifier,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            grant,
+            TokenGrant {
+                subject: user_id,
+                client_id,
+                scopes: vec![Scope::UsersRead],
+                grant_id: Some(grant_id),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_code_once_consent_was_revoked() {
+        let client = factori::create!(OAuthClient);
+        let client_id = client.id;
+        let code_verifier = generate_token();
+        let mut repo = MockOAuthRepository::new();
+        repo.expect_get_client()
+            .return_once(move |_| Ok(Some(client)));
+        let challenge = code_challenge(&code_verifier);
+        repo.expect_take_authorization_code().return_once(move |_| {
+            Ok(Some(factori::create!(
+                OAuthAuthorizationCode,
+                client_id: client_id,
+                code_challenge: challenge
+            )))
+        });
+        repo.expect_get_grant().return_once(|_, _| Ok(None));
+
+        let result = OAuthHandlerImpl {
+            oauth_repository: Box::new(repo),
+            code_lifetime: Duration::minutes(1),
+        }
+        .exchange_code(
+            public_client(client_id),
+            "code".to_string(),
+            REDIRECT_URI.to_string(),
+            code_verifier,
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(RepositoryError::OAuth(OAuthErrorCode::InvalidGrant, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn revoking_unknown_grant_is_not_found() {
+        let mut repo = MockOAuthRepository::new();
+        repo.expect_delete_grant().return_once(|_, _| Ok(None));
+
+        let result = OAuthHandlerImpl {
+            oauth_repository: Box::new(repo),
+            code_lifetime: Duration::minutes(1),
+        }
+        .revoke_grant(Uuid::new_v4(), Uuid::new_v4())
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn rejects_code_with_wrong_verifier_or_another_client() {
+        let client = factori::create!(OAuthClient);
+        let client_id = client.id;
+        let code_verifier = generate_token();
+
+        for (code_client_id, verifier) in [
+            (client_id, generate_token()),
+            (Uuid::new_v4(), code_verifier.clone()),
+        ] {
+            let mut repo = MockOAuthRepository::new();
+            let client = client.clone();
+            repo.expect_get_client()
+                .return_once(move |_| Ok(Some(client)));
+            let challenge = code_challenge(&code_verifier);
+            repo.expect_take_authorization_code().return_once(move |_| {
+                Ok(Some(factori::create!(
+                    OAuthAuthorizationCode,
+                    client_id: code_client_id,
+                    code_challenge: challenge
+                )))
+            });
+
+            let result = OAuthHandlerImpl {
+                oauth_repository: Box::new(repo),
+                code_lifetime: Duration::minutes(1),
+            }
+            .exchange_code(
+                public_client(client_id),
+                "code".to_string(),
+                REDIRECT_URI.to_string(),
+                verifier,
+            )
+            .await;
+
+            assert!(matches!(
+                result,
+                Err(RepositoryError::OAuth(OAuthErrorCode::InvalidGrant, _))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn grants_narrowed_scopes_to_confidential_client() {
+        let secret = generate_token();
+        let client = factori::create!(
+            OAuthClient,
+            confidential: true,
+            secret_hash: Some(hash_token(&secret))
+        );
+        let client_id = client.id;
+        let mut repo = MockOAuthRepository::new();
+        repo.expect_get_client()
+            .return_once(move |_| Ok(Some(client)));
+
+        let grant = OAuthHandlerImpl {
+            oauth_repository: Box::new(repo),
+            code_lifetime: Duration::minutes(1),
+        }
+        .client_credentials(
+            ClientCredentials {
+                client_id: client_id.to_string(),
+                client_secret: Some(secret),
+            },
+            Some("users:read".to_string()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(grant.subject, client_id);
+        assert_eq!(grant.scopes, vec![Scope::UsersRead]);
+    }
+
+    #[tokio::test]
+    async fn refuses_client_credentials_to_public_client_or_wrong_secret() {
+        let public = factori::create!(OAuthClient);
+        let confidential = factori::create!(
+            OAuthClient,
+            confidential: true,
+            secret_hash: Some(hash_token("secret"))
+        );
+
+        let cases = [
+            (public.clone(), None, OAuthErrorCode::UnauthorizedClient),
+            (public, Some("secret"), OAuthErrorCode::InvalidClient),
+            (confidential, Some("other"), OAuthErrorCode::InvalidClient),
+        ];
+        for (client, secret, expected) in cases {
+            let client_id = client.id;
+            let mut repo = MockOAuthRepository::new();
+            repo.expect_get_client()
+                .return_once(move |_| Ok(Some(client)));
+
+            let result = OAuthHandlerImpl {
+                oauth_repository: Box::new(repo),
+                code_lifetime: Duration::minutes(1),
+            }
+            .client_credentials(
+                ClientCredentials {
+                    client_id: client_id.to_string(),
+                    client_secret: secret.map(str::to_string),
+                },
+                None,
+            )
+            .await;
+
+            assert!(matches!(result, Err(RepositoryError::OAuth(code, _)) if code == expected));
+        }
+    }
+}
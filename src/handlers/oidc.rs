@@ -0,0 +1,616 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    auth::oidc::{IdTokenClaims, OidcProvider},
+    domain::{
+        oidc::OidcRepository,
+        user::{payload::NewUserPayload, UserRepository},
+    },
+    repositories::error::{
+        ErrorMessage::{ExistingEmail, InvalidOidcState, MissingOidcEmail},
+        RepositoryError,
+    },
+    utils::{code_challenge, generate_token, hash_token},
+};
+
+pub type DynOidcHandler = dyn OidcHandler + Send + Sync;
+
+/// Nicknames are at most 50 characters long, leaving room for a suffix.
+const MAX_NICKNAME_BASE_LENGTH: usize = 40;
+const NICKNAME_ATTEMPTS: usize = 5;
+
+/// Sign-in with an external OpenID Connect provider, using the authorization code flow
+/// with PKCE. Accounts are matched on the `iss` and `sub` of the ID token, and created on
+/// first sign-in.
+pub struct OidcHandlerImpl {
+    pub oidc_repository: Box<dyn OidcRepository + Send + Sync>,
+    pub user_repository: Box<dyn UserRepository + Send + Sync>,
+    pub provider: OidcProvider,
+    /// How long the user has to sign in at the provider and come back.
+    pub login_lifetime: Duration,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait OidcHandler {
+    /// Starts a sign-in, returning the URL of the provider to send the user to.
+    async fn start_login(&self) -> Result<String, RepositoryError>;
+
+    /// Finishes the sign-in the `state` belongs to, returning the local user.
+    ///
+    /// A provider account not seen before is linked to the user with the same email when
+    /// both the provider and this service have verified it, and gets a new user
+    /// otherwise. An unverified match is refused, since whoever registered it may not own
+    /// the address.
+    async fn complete_login(&self, code: String, state: String) -> Result<Uuid, RepositoryError>;
+}
+
+#[async_trait::async_trait]
+impl OidcHandler for OidcHandlerImpl {
+    #[tracing::instrument(skip(self))]
+    async fn start_login(&self) -> Result<String, RepositoryError> {
+        let state = generate_token();
+        let nonce = generate_token();
+        let code_verifier = generate_token();
+
+        let authorization_url = self
+            .provider
+            .authorization_url(&state, &nonce, &code_challenge(&code_verifier))
+            .await?;
+
+        self.oidc_repository
+            .create_login_request(
+                hash_token(&state),
+                nonce,
+                code_verifier,
+                Utc::now() + self.login_lifetime,
+            )
+            .await?;
+        Ok(authorization_url)
+    }
+
+    #[tracing::instrument(skip(self, code, state))]
+    async fn complete_login(&self, code: String, state: String) -> Result<Uuid, RepositoryError> {
+        let login_request = self
+            .oidc_repository
+            .take_login_request(hash_token(&state))
+            .await?
+            .filter(|login_request| login_request.expires_at > Utc::now())
+            .ok_or(RepositoryError::InvalidToken(InvalidOidcState))?;
+
+        let id_token = self
+            .provider
+            .exchange_code(&code, &login_request.code_verifier)
+            .await?;
+        let claims = self
+            .provider
+            .validate_id_token(&id_token, &login_request.nonce)
+            .await?;
+
+        if let Some(identity) = self
+            .oidc_repository
+            .get_identity(claims.iss.clone(), claims.sub.clone())
+            .await?
+        {
+            return Ok(identity.user_id);
+        }
+
+        let user_id = self.find_or_create_user(&claims).await?;
+        self.oidc_repository
+            .create_identity(user_id, claims.iss.clone(), claims.sub.clone())
+            .await?;
+        tracing::info!(%user_id, issuer = %claims.iss, "linked external identity");
+        Ok(user_id)
+    }
+}
+
+impl OidcHandlerImpl {
+    async fn find_or_create_user(&self, claims: &IdTokenClaims) -> Result<Uuid, RepositoryError> {
+        let email = claims
+            .email
+            .clone()
+            .ok_or(RepositoryError::InvalidToken(MissingOidcEmail))?;
+
+        if let Some(user) = self
+            .user_repository
+            .get_user_by_email(email.clone())
+            .await?
+        {
+            if claims.email_verified && user.email_verified_at.is_some() {
+                return Ok(user.id);
+            }
+            return Err(RepositoryError::Conflict(ExistingEmail));
+        }
+
+        let user = self
+            .user_repository
+            .create_user(NewUserPayload {
+                // Sign up only takes 3 to 15 characters, other names are left out.
+                name: claims
+                    .name
+                    .clone()
+                    .filter(|name| (3..=15).contains(&name.chars().count())),
+                nickname: self.available_nickname(claims, &email).await?,
+                email,
+                // Never handed out, the user can pick a password with a reset.
+                password: generate_token(),
+                bio: None,
+            })
+            .await?;
+        if claims.email_verified {
+            self.user_repository.mark_email_verified(user.id).await?;
+        }
+        Ok(user.id)
+    }
+
+    /// Derived from the preferred username or the email, with a random suffix when taken.
+    async fn available_nickname(
+        &self,
+        claims: &IdTokenClaims,
+        email: &str,
+    ) -> Result<String, RepositoryError> {
+        let base = nickname_base(claims.preferred_username.as_deref(), email);
+
+        for attempt in 0..NICKNAME_ATTEMPTS {
+            let nickname = if attempt == 0 {
+                base.clone()
+            } else {
+                format!("{base}_{}", &Uuid::new_v4().simple().to_string()[..6])
+            };
+            if self
+                .user_repository
+                .get_user_by_nickname(nickname.clone())
+                .await?
+                .is_none()
+            {
+                return Ok(nickname);
+            }
+        }
+        Ok(format!("user_{}", Uuid::new_v4().simple()))
+    }
+}
+
+/// Keeps the characters nicknames allow. Too short a result falls back to `user`.
+fn nickname_base(preferred_username: Option<&str>, email: &str) -> String {
+    let source = preferred_username
+        .map(|username| username.split('@').next().unwrap_or_default())
+        .unwrap_or_else(|| email.split('@').next().unwrap_or_default());
+    let base: String = source
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .take(MAX_NICKNAME_BASE_LENGTH)
+        .collect();
+    if base.len() < 3 {
+        return "user".to_string();
+    }
+    base
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use ed25519_dalek::{pkcs8::EncodePrivateKey, SigningKey};
+    use jsonwebtoken::{Algorithm, Header};
+    use reqwest::Url;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{
+        auth::{
+            keys::{JwtKey, JwtKeys},
+            oidc::OidcSettings,
+        },
+        domain::{
+            oidc::{mocks::*, MockOidcRepository, OidcLoginRequest},
+            user::{mocks::*, MockUserRepository},
+        },
+    };
+
+    const CLIENT_ID: &str = "users-actix";
+
+    /// What the provider knows of a code it handed out.
+    #[derive(Clone)]
+    struct Grant {
+        code_challenge: String,
+        nonce: String,
+        subject: String,
+        email: String,
+        name: String,
+    }
+
+    /// A minimal OpenID Connect provider serving discovery, JWKS and token endpoints,
+    /// signing ID tokens with an Ed25519 key.
+    struct MockProvider {
+        issuer: String,
+        grants: Arc<Mutex<HashMap<String, Grant>>>,
+    }
+
+    #[derive(Deserialize)]
+    struct TokenForm {
+        code: String,
+        code_verifier: String,
+        client_id: String,
+    }
+
+    impl MockProvider {
+        fn start() -> MockProvider {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let issuer = format!("http://{}", listener.local_addr().unwrap());
+            let grants: Arc<Mutex<HashMap<String, Grant>>> = Arc::default();
+
+            let private_pem = SigningKey::from_bytes(&[7; 32])
+                .to_pkcs8_pem(Default::default())
+                .unwrap();
+            let keys = web::Data::new(
+                JwtKeys::new(
+                    vec![JwtKey::from_pem("idp", Algorithm::EdDSA, &private_pem).unwrap()],
+                    "idp",
+                )
+                .unwrap(),
+            );
+
+            let server_issuer = issuer.clone();
+            let server_grants = grants.clone();
+            let server = HttpServer::new(move || {
+                let issuer = server_issuer.clone();
+                let grants = server_grants.clone();
+                App::new()
+                    .app_data(keys.clone())
+                    .route(
+                        "/.well-known/openid-configuration",
+                        web::get().to({
+                            let issuer = issuer.clone();
+                            move || {
+                                let issuer = issuer.clone();
+                                async move {
+                                    HttpResponse::Ok().json(serde_json::json!({
+                                        "issuer": issuer,
+                                        "authorization_endpoint": format!("{issuer}/authorize"),
+                                        "token_endpoint": format!("{issuer}/token"),
+                                        "jwks_uri": format!("{issuer}/jwks"),
+                                    }))
+                                }
+                            }
+                        }),
+                    )
+                    .route(
+                        "/jwks",
+                        web::get().to(|keys: web::Data<JwtKeys>| async move {
+                            HttpResponse::Ok().json(keys.jwks())
+                        }),
+                    )
+                    .route(
+                        "/token",
+                        web::post().to(
+                            move |form: web::Form<TokenForm>, keys: web::Data<JwtKeys>| {
+                                let issuer = issuer.clone();
+                                let grant = grants.lock().unwrap().remove(&form.code);
+                                async move {
+                                    let Some(grant) = grant.filter(|grant| {
+                                        grant.code_challenge == code_challenge(&form.code_verifier)
+                                            && form.client_id == CLIENT_ID
+                                    }) else {
+                                        return HttpResponse::BadRequest()
+                                            .json(serde_json::json!({ "error": "invalid_grant" }));
+                                    };
+                                    let now = Utc::now().timestamp();
+                                    let mut header = Header::new(Algorithm::EdDSA);
+                                    header.kid = Some("idp".to_string());
+                                    let id_token = jsonwebtoken::encode(
+                                        &header,
+                                        &serde_json::json!({
+                                            "iss": issuer,
+                                            "aud": CLIENT_ID,
+                                            "sub": grant.subject,
+                                            "exp": now + 300,
+                                            "iat": now,
+                                            "nonce": grant.nonce,
+                                            "email": grant.email,
+                                            "email_verified": true,
+                                            "name": grant.name,
+                                            "preferred_username": "Jane.Doe",
+                                        }),
+                                        keys.signing_key().encoding_key().unwrap(),
+                                    )
+                                    .unwrap();
+                                    HttpResponse::Ok().json(serde_json::json!({
+                                        "id_token": id_token,
+                                        "token_type": "Bearer",
+                                    }))
+                                }
+                            },
+                        ),
+                    )
+            })
+            .listen(listener)
+            .unwrap()
+            .run();
+            actix_web::rt::spawn(server);
+
+            MockProvider { issuer, grants }
+        }
+
+        fn provider(&self) -> OidcProvider {
+            OidcProvider::new(OidcSettings {
+                issuer_url: self.issuer.clone(),
+                client_id: CLIENT_ID.to_string(),
+                client_secret: None,
+                redirect_url: "http://localhost:3000/oidc/callback".to_string(),
+                scopes: "openid email profile".to_string(),
+            })
+        }
+
+        /// Plays the part of the user signing in at the provider, returning the code it
+        /// redirects back with.
+        fn authorize(&self, authorization_url: &str, nonce: Option<&str>) -> String {
+            let url = Url::parse(authorization_url).unwrap();
+            let param = |name: &str| {
+                url.query_pairs()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.to_string())
+                    .unwrap()
+            };
+            let code = generate_token();
+            self.grants.lock().unwrap().insert(
+                code.clone(),
+                Grant {
+                    code_challenge: param("code_challenge"),
+                    nonce: nonce.map(str::to_string).unwrap_or_else(|| param("nonce")),
+                    subject: "jane".to_string(),
+                    email: "jane@example.com".to_string(),
+                    name: "Jane Doe".to_string(),
+                },
+            );
+            code
+        }
+    }
+
+    /// Starts a sign-in, keeping the stored request for `take_login_request` to return.
+    async fn start_login(
+        provider: &MockProvider,
+        oidc_repo: &mut MockOidcRepository,
+    ) -> (String, Arc<Mutex<Option<OidcLoginRequest>>>) {
+        let stored: Arc<Mutex<Option<OidcLoginRequest>>> = Arc::default();
+        let created = stored.clone();
+        let mut setup_repo = MockOidcRepository::new();
+        setup_repo.expect_create_login_request().return_once(
+            move |_, nonce, code_verifier, expires_at| {
+                *created.lock().unwrap() = Some(OidcLoginRequest {
+                    nonce,
+                    code_verifier,
+                    expires_at,
+                });
+                Ok(())
+            },
+        );
+        let url = OidcHandlerImpl {
+            oidc_repository: Box::new(setup_repo),
+            user_repository: Box::new(MockUserRepository::new()),
+            provider: provider.provider(),
+            login_lifetime: Duration::minutes(10),
+        }
+        .start_login()
+        .await
+        .expect("Failed to start login");
+
+        let taken = stored.clone();
+        oidc_repo
+            .expect_take_login_request()
+            .return_once(move |_| Ok(taken.lock().unwrap().take()));
+        (url, stored)
+    }
+
+    fn state_of(authorization_url: &str) -> String {
+        Url::parse(authorization_url)
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == "state")
+            .map(|(_, value)| value.to_string())
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn creates_user_with_generated_nickname_on_first_login() {
+        let provider = MockProvider::start();
+        let mut oidc_repo = MockOidcRepository::new();
+        let mut user_repo = MockUserRepository::new();
+        let (url, _) = start_login(&provider, &mut oidc_repo).await;
+        let code = provider.authorize(&url, None);
+        let user_id = Uuid::new_v4();
+
+        oidc_repo.expect_get_identity().return_once(|_, _| Ok(None));
+        oidc_repo
+            .expect_create_identity()
+            .withf(move |user, _, subject| *user == user_id && subject == "jane")
+            .times(1)
+            .return_once(|_, _, _| Ok(()));
+
+        user_repo
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(None));
+        user_repo
+            .expect_get_user_by_nickname()
+            .withf(|nickname| nickname == "JaneDoe")
+            .return_once(|_| Ok(Some(factori::create!(PublicUser))));
+        user_repo
+            .expect_get_user_by_nickname()
+            .return_once(|_| Ok(None));
+        user_repo
+            .expect_create_user()
+            .withf(|user| {
+                user.nickname.starts_with("JaneDoe_")
+                    && user.email == "jane@example.com"
+                    && user.name.as_deref() == Some("Jane Doe")
+            })
+            .return_once(move |_| Ok(factori::create!(PublicUser, id: user_id)));
+        user_repo
+            .expect_mark_email_verified()
+            .times(1)
+            .return_once(|_| Ok(()));
+
+        let result = OidcHandlerImpl {
+            oidc_repository: Box::new(oidc_repo),
+            user_repository: Box::new(user_repo),
+            provider: provider.provider(),
+            login_lifetime: Duration::minutes(10),
+        }
+        .complete_login(code, state_of(&url))
+        .await;
+
+        assert_eq!(result.unwrap(), user_id);
+    }
+
+    #[actix_web::test]
+    async fn leaves_out_name_too_long_for_sign_up() {
+        let provider = MockProvider::start();
+        let mut oidc_repo = MockOidcRepository::new();
+        let mut user_repo = MockUserRepository::new();
+        let (url, _) = start_login(&provider, &mut oidc_repo).await;
+        let code = provider.authorize(&url, None);
+        provider.grants.lock().unwrap().get_mut(&code).unwrap().name =
+            "Jane Alexandra Doe-Smith".to_string();
+
+        oidc_repo.expect_get_identity().return_once(|_, _| Ok(None));
+        oidc_repo
+            .expect_create_identity()
+            .return_once(|_, _, _| Ok(()));
+        user_repo
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(None));
+        user_repo
+            .expect_get_user_by_nickname()
+            .return_once(|_| Ok(None));
+        user_repo
+            .expect_create_user()
+            .withf(|user| user.name.is_none())
+            .times(1)
+            .return_once(|_| Ok(factori::create!(PublicUser)));
+        user_repo
+            .expect_mark_email_verified()
+            .return_once(|_| Ok(()));
+
+        let result = OidcHandlerImpl {
+            oidc_repository: Box::new(oidc_repo),
+            user_repository: Box::new(user_repo),
+            provider: provider.provider(),
+            login_lifetime: Duration::minutes(10),
+        }
+        .complete_login(code, state_of(&url))
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn signs_in_linked_identity() {
+        let provider = MockProvider::start();
+        let mut oidc_repo = MockOidcRepository::new();
+        let (url, _) = start_login(&provider, &mut oidc_repo).await;
+        let code = provider.authorize(&url, None);
+        let identity = factori::create!(ExternalIdentity);
+        let user_id = identity.user_id;
+
+        oidc_repo
+            .expect_get_identity()
+            .withf(|_, subject| subject == "jane")
+            .return_once(|_, _| Ok(Some(identity)));
+        oidc_repo.expect_create_identity().never();
+
+        let result = OidcHandlerImpl {
+            oidc_repository: Box::new(oidc_repo),
+            user_repository: Box::new(MockUserRepository::new()),
+            provider: provider.provider(),
+            login_lifetime: Duration::minutes(10),
+        }
+        .complete_login(code, state_of(&url))
+        .await;
+
+        assert_eq!(result.unwrap(), user_id);
+    }
+
+    #[actix_web::test]
+    async fn does_not_link_unverified_account_with_same_email() {
+        let provider = MockProvider::start();
+        let mut oidc_repo = MockOidcRepository::new();
+        let mut user_repo = MockUserRepository::new();
+        let (url, _) = start_login(&provider, &mut oidc_repo).await;
+        let code = provider.authorize(&url, None);
+
+        oidc_repo.expect_get_identity().return_once(|_, _| Ok(None));
+        oidc_repo.expect_create_identity().never();
+        user_repo
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(Some(factori::create!(PublicUser, email_verified_at: None))));
+
+        let result = OidcHandlerImpl {
+            oidc_repository: Box::new(oidc_repo),
+            user_repository: Box::new(user_repo),
+            provider: provider.provider(),
+            login_lifetime: Duration::minutes(10),
+        }
+        .complete_login(code, state_of(&url))
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(RepositoryError::Conflict(ExistingEmail))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn rejects_id_token_with_another_nonce() {
+        let provider = MockProvider::start();
+        let mut oidc_repo = MockOidcRepository::new();
+        let (url, _) = start_login(&provider, &mut oidc_repo).await;
+        let code = provider.authorize(&url, Some("replayed"));
+
+        oidc_repo.expect_get_identity().never();
+
+        let result = OidcHandlerImpl {
+            oidc_repository: Box::new(oidc_repo),
+            user_repository: Box::new(MockUserRepository::new()),
+            provider: provider.provider(),
+            login_lifetime: Duration::minutes(10),
+        }
+        .complete_login(code, state_of(&url))
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::InvalidToken(_))));
+    }
+
+    #[actix_web::test]
+    async fn rejects_code_redeemed_with_another_verifier() {
+        let provider = MockProvider::start();
+        let mut oidc_repo = MockOidcRepository::new();
+        let (url, stored) = start_login(&provider, &mut oidc_repo).await;
+        let code = provider.authorize(&url, None);
+        stored.lock().unwrap().as_mut().unwrap().code_verifier = generate_token();
+
+        oidc_repo.expect_get_identity().never();
+
+        let result = OidcHandlerImpl {
+            oidc_repository: Box::new(oidc_repo),
+            user_repository: Box::new(MockUserRepository::new()),
+            provider: provider.provider(),
+            login_lifetime: Duration::minutes(10),
+        }
+        .complete_login(code, state_of(&url))
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::InvalidToken(_))));
+    }
+
+    #[test]
+    fn derives_nickname_from_username_or_email() {
+        assert_eq!(nickname_base(Some("jane.doe@corp"), "x@y.z"), "janedoe");
+        assert_eq!(nickname_base(None, "j-d@example.com"), "user");
+        assert_eq!(nickname_base(None, "jdoe@example.com"), "jdoe");
+    }
+}
@@ -0,0 +1,240 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        password_policy::PasswordPolicy, password_reset::PasswordResetRepository,
+        user::UserRepository,
+    },
+    mail::{send_in_background, DynMailTransport, Mail},
+    repositories::error::{ErrorMessage::InvalidPasswordResetToken, RepositoryError},
+    utils::{generate_token, hash_token, token_link},
+};
+
+pub type DynPasswordResetHandler = dyn PasswordResetHandler + Send + Sync;
+
+pub struct PasswordResetHandlerImpl {
+    pub password_reset_repository: Box<dyn PasswordResetRepository + Send + Sync>,
+    pub user_repository: Box<dyn UserRepository + Send + Sync>,
+    pub mail_transport: Arc<DynMailTransport>,
+    pub password_policy: Arc<PasswordPolicy>,
+    pub token_lifetime: Duration,
+    pub reset_url: String,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait PasswordResetHandler {
+    /// Mails a reset token if an account uses this email. Succeeds either way without
+    /// waiting on delivery, so callers cannot tell whether it exists.
+    async fn request_reset(&self, email: String) -> Result<(), RepositoryError>;
+
+    /// Consumes the token and sets the new password, returning the user it belonged to.
+    /// A password breaking the policy, or failing to be set, leaves the token usable for
+    /// another try. Other reset links mailed to the user stop working.
+    async fn confirm_reset(&self, token: String, password: String)
+        -> Result<Uuid, RepositoryError>;
+}
+
+#[async_trait::async_trait]
+impl PasswordResetHandler for PasswordResetHandlerImpl {
+    #[tracing::instrument(skip(self))]
+    async fn request_reset(&self, email: String) -> Result<(), RepositoryError> {
+        let Some(user) = self.user_repository.get_user_by_email(email).await? else {
+            return Ok(());
+        };
+
+        let token = generate_token();
+        self.password_reset_repository
+            .create_token(
+                user.id,
+                hash_token(&token),
+                Utc::now() + self.token_lifetime,
+            )
+            .await?;
+
+        let mail = Mail {
+            to: user.email,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Someone asked to reset the password of your account {}.\n\n\
+                Follow this link to choose a new one: {}\n\n\
+                The link expires in {} minutes. If you did not ask for it, ignore this mail.",
+                user.nickname,
+                token_link(&self.reset_url, &token),
+                self.token_lifetime.num_minutes()
+            ),
+        };
+        send_in_background(self.mail_transport.clone(), mail);
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self, token, password))]
+    async fn confirm_reset(
+        &self,
+        token: String,
+        password: String,
+    ) -> Result<Uuid, RepositoryError> {
+        let invalid_token = || RepositoryError::InvalidToken(InvalidPasswordResetToken);
+
+        let stored_token = self
+            .password_reset_repository
+            .get_token_by_hash(hash_token(&token))
+            .await?
+            .ok_or_else(invalid_token)?;
+
+        if stored_token.used_at.is_some() || stored_token.expires_at <= Utc::now() {
+            return Err(invalid_token());
+        }
+
+        let user = self
+            .user_repository
+            .get_user_by_id(stored_token.user_id)
+            .await?
+            .ok_or_else(invalid_token)?;
+        self.password_policy
+            .check(&password, &user.nickname, &user.email)
+            .map_err(RepositoryError::WeakPassword)?;
+        let user_id = stored_token.user_id;
+        if !self
+            .password_reset_repository
+            .reset_password(stored_token, password)
+            .await?
+        {
+            return Err(invalid_token());
+        }
+        Ok(user_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        domain::{
+            password_reset::{mocks::*, MockPasswordResetRepository},
+            user::{mocks::*, MockUserRepository},
+        },
+        mail::{InMemoryMailTransport, MockMailTransport},
+    };
+
+    #[tokio::test]
+    async fn mails_token_whose_hash_is_stored() {
+        let mut password_reset_repository = MockPasswordResetRepository::new();
+        let mut user_repository = MockUserRepository::new();
+        let mail_transport = InMemoryMailTransport::default();
+
+        user_repository
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(Some(factori::create!(PublicUser))));
+        password_reset_repository
+            .expect_create_token()
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        PasswordResetHandlerImpl {
+            password_reset_repository: Box::new(password_reset_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(mail_transport.clone()),
+            password_policy: Arc::new(PasswordPolicy::default()),
+            token_lifetime: Duration::hours(1),
+            reset_url: "http://localhost/reset".to_string(),
+        }
+        .request_reset("johndoe@gmail.com".to_string())
+        .await
+        .expect("Failed to request password reset");
+        // Lets the spawned delivery run.
+        tokio::task::yield_now().await;
+
+        let sent = mail_transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "johndoe@gmail.com");
+        assert!(sent[0].body.contains("http://localhost/reset?token="));
+    }
+
+    #[tokio::test]
+    async fn succeeds_silently_for_unknown_email() {
+        let mut password_reset_repository = MockPasswordResetRepository::new();
+        let mut user_repository = MockUserRepository::new();
+        let mut mail_transport = MockMailTransport::new();
+
+        user_repository
+            .expect_get_user_by_email()
+            .return_once(|_| Ok(None));
+        password_reset_repository.expect_create_token().never();
+        mail_transport.expect_send().never();
+
+        let result = PasswordResetHandlerImpl {
+            password_reset_repository: Box::new(password_reset_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(mail_transport),
+            password_policy: Arc::new(PasswordPolicy::default()),
+            token_lifetime: Duration::hours(1),
+            reset_url: "http://localhost/reset".to_string(),
+        }
+        .request_reset("nobody@gmail.com".to_string())
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn does_not_reuse_token() {
+        let stored_token = factori::create!(PasswordResetToken, used_at: Some(Utc::now()));
+        let mut password_reset_repository = MockPasswordResetRepository::new();
+        let user_repository = MockUserRepository::new();
+
+        password_reset_repository
+            .expect_get_token_by_hash()
+            .return_once(|_| Ok(Some(stored_token)));
+        password_reset_repository.expect_reset_password().never();
+
+        let result = PasswordResetHandlerImpl {
+            password_reset_repository: Box::new(password_reset_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(MockMailTransport::new()),
+            password_policy: Arc::new(PasswordPolicy::default()),
+            token_lifetime: Duration::hours(1),
+            reset_url: "http://localhost/reset".to_string(),
+        }
+        .confirm_reset("token".to_string(), "new password".to_string())
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::InvalidToken(_))));
+    }
+
+    #[tokio::test]
+    async fn sets_new_password_once() {
+        let stored_token = factori::create!(PasswordResetToken);
+        let user_id = stored_token.user_id;
+        let mut password_reset_repository = MockPasswordResetRepository::new();
+        let mut user_repository = MockUserRepository::new();
+
+        password_reset_repository
+            .expect_get_token_by_hash()
+            .return_once(|_| Ok(Some(stored_token)));
+        user_repository
+            .expect_get_user_by_id()
+            .return_once(|_| Ok(Some(factori::create!(PublicUser))));
+        password_reset_repository
+            .expect_reset_password()
+            .withf(move |token, password| token.user_id == user_id && password == "new password")
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        let result = PasswordResetHandlerImpl {
+            password_reset_repository: Box::new(password_reset_repository),
+            user_repository: Box::new(user_repository),
+            mail_transport: Arc::new(MockMailTransport::new()),
+            password_policy: Arc::new(PasswordPolicy::default()),
+            token_lifetime: Duration::hours(1),
+            reset_url: "http://localhost/reset".to_string(),
+        }
+        .confirm_reset("token".to_string(), "new password".to_string())
+        .await;
+
+        assert_eq!(result.unwrap(), user_id);
+    }
+}
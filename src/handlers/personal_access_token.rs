@@ -0,0 +1,186 @@
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    domain::personal_access_token::{
+        payload::NewPersonalAccessTokenPayload, PersonalAccessToken, PersonalAccessTokenRepository,
+        TOKEN_MARKER, TOKEN_PREFIX_LENGTH,
+    },
+    repositories::error::{ErrorMessage::InvalidAccessToken, RepositoryError},
+    utils::{generate_token, hash_token},
+};
+
+pub type DynPersonalAccessTokenHandler = dyn PersonalAccessTokenHandler + Send + Sync;
+
+pub struct PersonalAccessTokenHandlerImpl {
+    pub personal_access_token_repository: Box<dyn PersonalAccessTokenRepository + Send + Sync>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait PersonalAccessTokenHandler {
+    /// Creates a token for the user, returning its metadata and the raw token, which is
+    /// not stored and cannot be shown again.
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        payload: NewPersonalAccessTokenPayload,
+    ) -> Result<(PersonalAccessToken, String), RepositoryError>;
+
+    async fn get_user_tokens(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<PersonalAccessToken>, RepositoryError>;
+
+    async fn delete_token(&self, user_id: Uuid, id: Uuid) -> Result<(), RepositoryError>;
+
+    /// Deletes every token of the user, for when their password may have leaked.
+    async fn revoke_user_tokens(&self, user_id: Uuid) -> Result<(), RepositoryError>;
+
+    /// Resolves a raw token presented as a bearer credential.
+    async fn authenticate(&self, token: String) -> Result<PersonalAccessToken, RepositoryError>;
+}
+
+#[async_trait::async_trait]
+impl PersonalAccessTokenHandler for PersonalAccessTokenHandlerImpl {
+    #[tracing::instrument(skip(self))]
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        payload: NewPersonalAccessTokenPayload,
+    ) -> Result<(PersonalAccessToken, String), RepositoryError> {
+        let token = format!("{TOKEN_MARKER}{}", generate_token());
+        let token_prefix = token[..TOKEN_PREFIX_LENGTH].to_string();
+
+        let personal_access_token = self
+            .personal_access_token_repository
+            .create_token(user_id, payload, token_prefix, hash_token(&token))
+            .await?;
+        Ok((personal_access_token, token))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_user_tokens(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<PersonalAccessToken>, RepositoryError> {
+        self.personal_access_token_repository
+            .get_tokens_by_user(user_id)
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_token(&self, user_id: Uuid, id: Uuid) -> Result<(), RepositoryError> {
+        let deleted = self
+            .personal_access_token_repository
+            .delete_token(user_id, id)
+            .await?;
+        if !deleted {
+            return Err(RepositoryError::NotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_user_tokens(&self, user_id: Uuid) -> Result<(), RepositoryError> {
+        self.personal_access_token_repository
+            .delete_user_tokens(user_id)
+            .await
+    }
+
+    #[tracing::instrument(skip(self, token))]
+    async fn authenticate(&self, token: String) -> Result<PersonalAccessToken, RepositoryError> {
+        let personal_access_token = self
+            .personal_access_token_repository
+            .get_token_by_hash(hash_token(&token))
+            .await?
+            .ok_or(RepositoryError::InvalidToken(InvalidAccessToken))?;
+
+        if personal_access_token
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+        {
+            return Err(RepositoryError::InvalidToken(InvalidAccessToken));
+        }
+
+        self.personal_access_token_repository
+            .update_last_used(personal_access_token.id)
+            .await?;
+        Ok(personal_access_token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::domain::personal_access_token::{
+        mocks::*, MockPersonalAccessTokenRepository, Scope,
+    };
+
+    #[tokio::test]
+    async fn creates_prefixed_token_and_stores_only_its_hash() {
+        let user_id = Uuid::new_v4();
+        let payload = NewPersonalAccessTokenPayload {
+            name: "ci".to_string(),
+            scopes: vec![Scope::UsersRead],
+            expires_at: None,
+        };
+
+        let mut repo = MockPersonalAccessTokenRepository::new();
+
+        repo.expect_create_token()
+            .withf(|_, _, prefix, hash| prefix.starts_with(TOKEN_MARKER) && !hash.contains(prefix))
+            .return_once(|_, _, token_prefix, _| {
+                Ok(factori::create!(PersonalAccessToken, token_prefix: token_prefix))
+            });
+
+        let (stored_token, token) = PersonalAccessTokenHandlerImpl {
+            personal_access_token_repository: Box::new(repo),
+        }
+        .create_token(user_id, payload)
+        .await
+        .expect("Failed to create personal access token");
+
+        assert!(token.starts_with(&stored_token.token_prefix));
+    }
+
+    #[tokio::test]
+    async fn does_not_authenticate_expired_token() {
+        let stored_token = factori::create!(
+            PersonalAccessToken,
+            expires_at: Some(Utc::now() - Duration::minutes(1))
+        );
+
+        let mut repo = MockPersonalAccessTokenRepository::new();
+
+        repo.expect_get_token_by_hash()
+            .return_once(|_| Ok(Some(stored_token)));
+
+        repo.expect_update_last_used().never();
+
+        let result = PersonalAccessTokenHandlerImpl {
+            personal_access_token_repository: Box::new(repo),
+        }
+        .authenticate("uat_token".to_string())
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::InvalidToken(_))));
+    }
+
+    #[tokio::test]
+    async fn does_not_delete_token_of_another_user() {
+        let mut repo = MockPersonalAccessTokenRepository::new();
+
+        repo.expect_delete_token().return_once(|_, _| Ok(false));
+
+        let result = PersonalAccessTokenHandlerImpl {
+            personal_access_token_repository: Box::new(repo),
+        }
+        .delete_token(Uuid::new_v4(), Uuid::new_v4())
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::NotFound)));
+    }
+}
@@ -0,0 +1,213 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    domain::refresh_token::{RefreshToken, RefreshTokenRepository},
+    repositories::error::{
+        ErrorMessage::{InvalidRefreshToken, RefreshTokenReuse},
+        RepositoryError,
+    },
+    utils::{generate_token, hash_token},
+};
+
+pub type DynRefreshTokenHandler = dyn RefreshTokenHandler + Send + Sync;
+
+pub struct RefreshTokenHandlerImpl {
+    pub refresh_token_repository: Box<dyn RefreshTokenRepository + Send + Sync>,
+    pub token_lifetime: Duration,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait RefreshTokenHandler {
+    /// Issues a refresh token in the family of the session, returning the raw token.
+    /// Each sign-in session has its own family, identified by the session id.
+    async fn issue_token(&self, user_id: Uuid, session_id: Uuid)
+        -> Result<String, RepositoryError>;
+
+    /// Exchanges a refresh token for a new one in the same family, returning the rotated
+    /// token and the raw replacement token. Presenting an already rotated token revokes
+    /// the whole family, since it means the token has leaked.
+    async fn rotate_token(&self, token: String) -> Result<(RefreshToken, String), RepositoryError>;
+
+    /// Revokes the family of the given refresh token if it belongs to the user.
+    async fn revoke_token(&self, user_id: Uuid, token: String) -> Result<(), RepositoryError>;
+
+    /// Revokes every refresh token of the user, signing them out of all devices.
+    async fn revoke_user_tokens(&self, user_id: Uuid) -> Result<(), RepositoryError>;
+}
+
+#[async_trait::async_trait]
+impl RefreshTokenHandler for RefreshTokenHandlerImpl {
+    #[tracing::instrument(skip(self))]
+    async fn issue_token(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<String, RepositoryError> {
+        self.create_token(user_id, session_id).await
+    }
+
+    #[tracing::instrument(skip(self, token))]
+    async fn rotate_token(&self, token: String) -> Result<(RefreshToken, String), RepositoryError> {
+        let stored_token = self
+            .refresh_token_repository
+            .get_token_by_hash(hash_token(&token))
+            .await?
+            .ok_or(RepositoryError::InvalidToken(InvalidRefreshToken))?;
+
+        if stored_token.revoked_at.is_some() || stored_token.expires_at <= Utc::now() {
+            return Err(RepositoryError::InvalidToken(InvalidRefreshToken));
+        }
+
+        let is_first_use = stored_token.used_at.is_none()
+            && self
+                .refresh_token_repository
+                .mark_token_used(stored_token.id)
+                .await?;
+
+        if !is_first_use {
+            tracing::warn!(
+                family_id = %stored_token.family_id,
+                "refresh token reuse detected, revoking token family"
+            );
+            self.refresh_token_repository
+                .revoke_family(stored_token.family_id)
+                .await?;
+            return Err(RepositoryError::InvalidToken(RefreshTokenReuse));
+        }
+
+        let new_token = self
+            .create_token(stored_token.user_id, stored_token.family_id)
+            .await?;
+        Ok((stored_token, new_token))
+    }
+
+    #[tracing::instrument(skip(self, token))]
+    async fn revoke_token(&self, user_id: Uuid, token: String) -> Result<(), RepositoryError> {
+        let stored_token = self
+            .refresh_token_repository
+            .get_token_by_hash(hash_token(&token))
+            .await?;
+
+        if let Some(stored_token) = stored_token.filter(|token| token.user_id == user_id) {
+            self.refresh_token_repository
+                .revoke_family(stored_token.family_id)
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_user_tokens(&self, user_id: Uuid) -> Result<(), RepositoryError> {
+        self.refresh_token_repository
+            .revoke_user_tokens(user_id)
+            .await
+    }
+}
+
+impl RefreshTokenHandlerImpl {
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        family_id: Uuid,
+    ) -> Result<String, RepositoryError> {
+        let token = generate_token();
+        let expires_at = Utc::now() + self.token_lifetime;
+        self.refresh_token_repository
+            .create_token(user_id, family_id, hash_token(&token), expires_at)
+            .await?;
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::refresh_token::{mocks::*, MockRefreshTokenRepository};
+
+    #[tokio::test]
+    async fn rotates_token_within_the_same_family() {
+        let stored_token = factori::create!(RefreshToken);
+        let family_id = stored_token.family_id;
+        let user_id = stored_token.user_id;
+
+        let mut repo = MockRefreshTokenRepository::new();
+
+        repo.expect_get_token_by_hash()
+            .return_once(|_| Ok(Some(stored_token)));
+
+        repo.expect_mark_token_used().return_once(|_| Ok(true));
+
+        repo.expect_create_token()
+            .withf(move |user, family, _, _| *user == user_id && *family == family_id)
+            .return_once(|_, _, _, _| Ok(()));
+
+        repo.expect_revoke_family().never();
+
+        let (rotated_token, new_token) = RefreshTokenHandlerImpl {
+            refresh_token_repository: Box::new(repo),
+            token_lifetime: Duration::days(30),
+        }
+        .rotate_token("token".to_string())
+        .await
+        .expect("Failed to rotate refresh token");
+
+        assert_eq!(rotated_token.user_id, user_id);
+        assert_ne!(new_token, "token");
+    }
+
+    #[tokio::test]
+    async fn revokes_family_when_used_token_is_reused() {
+        let stored_token = factori::create!(RefreshToken, used_at: Some(Utc::now()));
+        let family_id = stored_token.family_id;
+
+        let mut repo = MockRefreshTokenRepository::new();
+
+        repo.expect_get_token_by_hash()
+            .return_once(|_| Ok(Some(stored_token)));
+
+        repo.expect_revoke_family()
+            .withf(move |family| *family == family_id)
+            .times(1)
+            .return_once(|_| Ok(()));
+
+        repo.expect_create_token().never();
+
+        let result = RefreshTokenHandlerImpl {
+            refresh_token_repository: Box::new(repo),
+            token_lifetime: Duration::days(30),
+        }
+        .rotate_token("token".to_string())
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(RepositoryError::InvalidToken(RefreshTokenReuse))
+        ));
+    }
+
+    #[tokio::test]
+    async fn does_not_rotate_expired_token() {
+        let stored_token = factori::create!(
+            RefreshToken,
+            expires_at: Utc::now() - Duration::minutes(1)
+        );
+
+        let mut repo = MockRefreshTokenRepository::new();
+
+        repo.expect_get_token_by_hash()
+            .return_once(|_| Ok(Some(stored_token)));
+
+        repo.expect_create_token().never();
+
+        let result = RefreshTokenHandlerImpl {
+            refresh_token_repository: Box::new(repo),
+            token_lifetime: Duration::days(30),
+        }
+        .rotate_token("token".to_string())
+        .await;
+
+        assert!(result.is_err());
+    }
+}
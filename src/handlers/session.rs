@@ -0,0 +1,161 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    domain::session::{Session, SessionRepository},
+    repositories::error::RepositoryError,
+};
+
+pub type DynSessionHandler = dyn SessionHandler + Send + Sync;
+
+/// Longest user agent kept, longer ones are cut rather than refused.
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+pub struct SessionHandlerImpl {
+    pub session_repository: Box<dyn SessionRepository + Send + Sync>,
+    /// How long a session lasts without being used, the lifetime of its refresh tokens.
+    pub session_lifetime: Duration,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait SessionHandler {
+    async fn create_session(
+        &self,
+        user_id: Uuid,
+        user_agent: Option<String>,
+        ip: Option<String>,
+    ) -> Result<Session, RepositoryError>;
+
+    /// Sessions of the user that can still refresh their tokens.
+    async fn get_user_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, RepositoryError>;
+
+    async fn touch_session(&self, id: Uuid) -> Result<(), RepositoryError>;
+
+    /// Ends the session and revokes its refresh tokens. Access tokens already issued in
+    /// it are revoked separately, see
+    /// [`TokenRevocationHandler::revoke_session_tokens`](crate::handlers::token_revocation::TokenRevocationHandler::revoke_session_tokens).
+    async fn revoke_session(&self, user_id: Uuid, id: Uuid) -> Result<(), RepositoryError>;
+
+    /// Ends every session of the user but `kept_id`, like [`SessionHandler::revoke_session`].
+    async fn revoke_other_sessions(
+        &self,
+        user_id: Uuid,
+        kept_id: Option<Uuid>,
+    ) -> Result<(), RepositoryError>;
+}
+
+#[async_trait::async_trait]
+impl SessionHandler for SessionHandlerImpl {
+    #[tracing::instrument(skip(self))]
+    async fn create_session(
+        &self,
+        user_id: Uuid,
+        user_agent: Option<String>,
+        ip: Option<String>,
+    ) -> Result<Session, RepositoryError> {
+        let user_agent =
+            user_agent.map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect());
+        self.session_repository
+            .create_session(user_id, user_agent, ip)
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_user_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, RepositoryError> {
+        self.session_repository
+            .get_sessions_by_user(user_id, Utc::now() - self.session_lifetime)
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn touch_session(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.session_repository.update_last_used(id).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_session(&self, user_id: Uuid, id: Uuid) -> Result<(), RepositoryError> {
+        let revoked = self.session_repository.revoke_session(user_id, id).await?;
+        if !revoked {
+            return Err(RepositoryError::NotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_other_sessions(
+        &self,
+        user_id: Uuid,
+        kept_id: Option<Uuid>,
+    ) -> Result<(), RepositoryError> {
+        self.session_repository
+            .revoke_user_sessions(user_id, kept_id)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::session::{mocks::*, MockSessionRepository};
+
+    #[tokio::test]
+    async fn truncates_long_user_agent() {
+        let mut repo = MockSessionRepository::new();
+
+        repo.expect_create_session()
+            .withf(|_, user_agent, _| {
+                user_agent
+                    .as_ref()
+                    .is_some_and(|user_agent| user_agent.len() == MAX_USER_AGENT_LENGTH)
+            })
+            .return_once(|user_id, user_agent, ip| {
+                Ok(factori::create!(Session, user_id: user_id, user_agent: user_agent, ip: ip))
+            });
+
+        SessionHandlerImpl {
+            session_repository: Box::new(repo),
+            session_lifetime: Duration::days(30),
+        }
+        .create_session(Uuid::new_v4(), Some("a".repeat(2000)), None)
+        .await
+        .expect("Failed to create session");
+    }
+
+    #[tokio::test]
+    async fn lists_sessions_used_within_their_lifetime() {
+        let mut repo = MockSessionRepository::new();
+
+        repo.expect_get_sessions_by_user()
+            .withf(|_, active_since| {
+                (*active_since - (Utc::now() - Duration::days(30))).num_seconds() == 0
+            })
+            .return_once(|_, _| Ok(vec![factori::create!(Session)]));
+
+        let sessions = SessionHandlerImpl {
+            session_repository: Box::new(repo),
+            session_lifetime: Duration::days(30),
+        }
+        .get_user_sessions(Uuid::new_v4())
+        .await
+        .unwrap();
+
+        assert_eq!(sessions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_revoke_session_of_another_user() {
+        let mut repo = MockSessionRepository::new();
+
+        repo.expect_revoke_session().return_once(|_, _| Ok(false));
+
+        let result = SessionHandlerImpl {
+            session_repository: Box::new(repo),
+            session_lifetime: Duration::days(30),
+        }
+        .revoke_session(Uuid::new_v4(), Uuid::new_v4())
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::NotFound)));
+    }
+}
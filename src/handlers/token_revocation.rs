@@ -0,0 +1,432 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+use chrono::{DateTime, Duration, SubsecRound, Utc};
+use uuid::Uuid;
+
+use crate::{
+    domain::token_revocation::{TokenRevocation, TokenRevocationRepository},
+    repositories::error::RepositoryError,
+};
+
+pub type DynTokenRevocationHandler = dyn TokenRevocationHandler + Send + Sync;
+
+/// Revocations are persisted in Postgres so every instance sees them, and mirrored in
+/// memory so checking a token does not cost a query per request. The cache is reloaded
+/// from the database once it is older than `sync_interval`.
+pub struct TokenRevocationHandlerImpl {
+    token_revocation_repository: Box<dyn TokenRevocationRepository + Send + Sync>,
+    access_token_lifetime: Duration,
+    sync_interval: Duration,
+    cache: RwLock<RevocationCache>,
+}
+
+#[derive(Default)]
+struct RevocationCache {
+    revoked_tokens: HashMap<Uuid, DateTime<Utc>>,
+    revoked_sessions: HashSet<Uuid>,
+    revoked_users: HashMap<Uuid, UserRevocation>,
+    last_sync: Option<DateTime<Utc>>,
+}
+
+/// The latest revocation of all tokens of a user.
+struct UserRevocation {
+    revocation_time: DateTime<Utc>,
+    kept_jti: Option<Uuid>,
+}
+
+impl RevocationCache {
+    fn insert(&mut self, revocation: TokenRevocation) {
+        match (revocation.jti, revocation.session_id) {
+            (Some(jti), _) => {
+                self.revoked_tokens.insert(jti, revocation.expires_at);
+            }
+            (None, Some(session_id)) => {
+                self.revoked_sessions.insert(session_id);
+            }
+            (None, None) => {
+                let is_latest = self
+                    .revoked_users
+                    .get(&revocation.user_id)
+                    .is_none_or(|latest| latest.revocation_time <= revocation.revocation_time);
+                if is_latest {
+                    self.revoked_users.insert(
+                        revocation.user_id,
+                        UserRevocation {
+                            revocation_time: revocation.revocation_time,
+                            kept_jti: revocation.kept_jti,
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait TokenRevocationHandler {
+    async fn revoke_token(
+        &self,
+        jti: Uuid,
+        user_id: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError>;
+
+    /// Revokes every access token issued to the user so far.
+    async fn revoke_user_tokens(&self, user_id: Uuid) -> Result<(), RepositoryError>;
+
+    /// Like [`TokenRevocationHandler::revoke_user_tokens`], sparing the token making the
+    /// request.
+    async fn revoke_other_tokens(
+        &self,
+        user_id: Uuid,
+        kept_jti: Uuid,
+    ) -> Result<(), RepositoryError>;
+
+    /// Revokes every access token issued in the session. Sessions are never resumed, so
+    /// tokens issued later cannot exist.
+    async fn revoke_session_tokens(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<(), RepositoryError>;
+
+    async fn is_token_revoked(
+        &self,
+        jti: Uuid,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+        issued_at: DateTime<Utc>,
+    ) -> Result<bool, RepositoryError>;
+}
+
+impl TokenRevocationHandlerImpl {
+    pub fn new(
+        token_revocation_repository: Box<dyn TokenRevocationRepository + Send + Sync>,
+        access_token_lifetime: Duration,
+        sync_interval: Duration,
+    ) -> Self {
+        TokenRevocationHandlerImpl {
+            token_revocation_repository,
+            access_token_lifetime,
+            sync_interval,
+            cache: RwLock::new(RevocationCache::default()),
+        }
+    }
+
+    async fn store_revocation(&self, revocation: TokenRevocation) -> Result<(), RepositoryError> {
+        self.token_revocation_repository
+            .create_revocation(revocation.clone())
+            .await?;
+        self.cache.write().unwrap().insert(revocation);
+        Ok(())
+    }
+
+    async fn store_user_revocation(
+        &self,
+        user_id: Uuid,
+        kept_jti: Option<Uuid>,
+    ) -> Result<(), RepositoryError> {
+        let now = Utc::now();
+        self.store_revocation(TokenRevocation {
+            jti: None,
+            session_id: None,
+            kept_jti,
+            user_id,
+            revocation_time: now,
+            expires_at: now + self.access_token_lifetime,
+        })
+        .await
+    }
+
+    async fn sync_cache_if_stale(&self) -> Result<(), RepositoryError> {
+        let last_sync = self.cache.read().unwrap().last_sync;
+        if last_sync.is_some_and(|last_sync| last_sync + self.sync_interval > Utc::now()) {
+            return Ok(());
+        }
+
+        self.token_revocation_repository
+            .delete_expired_revocations()
+            .await?;
+        let revocations = self
+            .token_revocation_repository
+            .get_active_revocations()
+            .await?;
+
+        let mut cache = RevocationCache {
+            last_sync: Some(Utc::now()),
+            ..Default::default()
+        };
+        for revocation in revocations {
+            cache.insert(revocation);
+        }
+        *self.cache.write().unwrap() = cache;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenRevocationHandler for TokenRevocationHandlerImpl {
+    #[tracing::instrument(skip(self))]
+    async fn revoke_token(
+        &self,
+        jti: Uuid,
+        user_id: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        self.store_revocation(TokenRevocation {
+            jti: Some(jti),
+            session_id: None,
+            kept_jti: None,
+            user_id,
+            revocation_time: Utc::now(),
+            expires_at,
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_user_tokens(&self, user_id: Uuid) -> Result<(), RepositoryError> {
+        self.store_user_revocation(user_id, None).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_other_tokens(
+        &self,
+        user_id: Uuid,
+        kept_jti: Uuid,
+    ) -> Result<(), RepositoryError> {
+        self.store_user_revocation(user_id, Some(kept_jti)).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_session_tokens(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<(), RepositoryError> {
+        let now = Utc::now();
+        self.store_revocation(TokenRevocation {
+            jti: None,
+            session_id: Some(session_id),
+            kept_jti: None,
+            user_id,
+            revocation_time: now,
+            expires_at: now + self.access_token_lifetime,
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn is_token_revoked(
+        &self,
+        jti: Uuid,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+        issued_at: DateTime<Utc>,
+    ) -> Result<bool, RepositoryError> {
+        self.sync_cache_if_stale().await?;
+
+        let cache = self.cache.read().unwrap();
+        let is_token_revoked = cache.revoked_tokens.contains_key(&jti)
+            || session_id.is_some_and(|session_id| cache.revoked_sessions.contains(&session_id));
+        // `iat` only has whole seconds, so the token reissued right after revoking counts
+        // as issued in the same second and must be spared.
+        let is_user_revoked = cache.revoked_users.get(&user_id).is_some_and(|revocation| {
+            issued_at < revocation.revocation_time.trunc_subsecs(0)
+                && revocation.kept_jti != Some(jti)
+        });
+
+        Ok(is_token_revoked || is_user_revoked)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::token_revocation::MockTokenRevocationRepository;
+
+    #[tokio::test]
+    async fn detects_token_revoked_by_jti() {
+        let jti = Uuid::new_v4();
+        let user_id = Uuid::new_v4();
+
+        let mut repo = MockTokenRevocationRepository::new();
+
+        repo.expect_delete_expired_revocations()
+            .returning(|| Ok(()));
+
+        repo.expect_get_active_revocations().return_once(move || {
+            Ok(vec![TokenRevocation {
+                jti: Some(jti),
+                session_id: None,
+                kept_jti: None,
+                user_id,
+                revocation_time: Utc::now(),
+                expires_at: Utc::now() + Duration::minutes(5),
+            }])
+        });
+
+        let handler = TokenRevocationHandlerImpl::new(
+            Box::new(repo),
+            Duration::minutes(5),
+            Duration::minutes(1),
+        );
+
+        assert!(handler
+            .is_token_revoked(jti, user_id, None, Utc::now())
+            .await
+            .unwrap());
+        assert!(!handler
+            .is_token_revoked(Uuid::new_v4(), user_id, None, Utc::now())
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn revokes_tokens_issued_before_user_revocation() {
+        let user_id = Uuid::new_v4();
+
+        let mut repo = MockTokenRevocationRepository::new();
+
+        repo.expect_create_revocation().return_once(|_| Ok(()));
+
+        repo.expect_delete_expired_revocations()
+            .returning(|| Ok(()));
+
+        repo.expect_get_active_revocations()
+            .return_once(|| Ok(vec![]));
+
+        let handler = TokenRevocationHandlerImpl::new(
+            Box::new(repo),
+            Duration::minutes(5),
+            Duration::minutes(1),
+        );
+        handler.sync_cache_if_stale().await.unwrap();
+
+        let issued_at = Utc::now() - Duration::minutes(1);
+        handler.revoke_user_tokens(user_id).await.unwrap();
+
+        assert!(handler
+            .is_token_revoked(Uuid::new_v4(), user_id, None, issued_at)
+            .await
+            .unwrap());
+        assert!(!handler
+            .is_token_revoked(
+                Uuid::new_v4(),
+                user_id,
+                None,
+                Utc::now() + Duration::seconds(1)
+            )
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn spares_token_issued_in_the_second_of_user_revocation() {
+        let user_id = Uuid::new_v4();
+
+        let mut repo = MockTokenRevocationRepository::new();
+
+        repo.expect_create_revocation().return_once(|_| Ok(()));
+
+        repo.expect_delete_expired_revocations()
+            .returning(|| Ok(()));
+
+        repo.expect_get_active_revocations()
+            .return_once(|| Ok(vec![]));
+
+        let handler = TokenRevocationHandlerImpl::new(
+            Box::new(repo),
+            Duration::minutes(5),
+            Duration::minutes(1),
+        );
+        handler.sync_cache_if_stale().await.unwrap();
+
+        handler.revoke_user_tokens(user_id).await.unwrap();
+        let issued_at = Utc::now().trunc_subsecs(0);
+
+        assert!(!handler
+            .is_token_revoked(Uuid::new_v4(), user_id, None, issued_at)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn spares_kept_token_when_revoking_other_tokens() {
+        let user_id = Uuid::new_v4();
+        let kept_jti = Uuid::new_v4();
+
+        let mut repo = MockTokenRevocationRepository::new();
+
+        repo.expect_create_revocation().return_once(|_| Ok(()));
+
+        repo.expect_delete_expired_revocations()
+            .returning(|| Ok(()));
+
+        repo.expect_get_active_revocations()
+            .return_once(|| Ok(vec![]));
+
+        let handler = TokenRevocationHandlerImpl::new(
+            Box::new(repo),
+            Duration::minutes(5),
+            Duration::minutes(1),
+        );
+        handler.sync_cache_if_stale().await.unwrap();
+
+        let issued_at = Utc::now() - Duration::minutes(1);
+        handler
+            .revoke_other_tokens(user_id, kept_jti)
+            .await
+            .unwrap();
+
+        assert!(!handler
+            .is_token_revoked(kept_jti, user_id, None, issued_at)
+            .await
+            .unwrap());
+        assert!(handler
+            .is_token_revoked(Uuid::new_v4(), user_id, None, issued_at)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn revokes_tokens_of_signed_out_session() {
+        let user_id = Uuid::new_v4();
+        let session_id = Uuid::new_v4();
+
+        let mut repo = MockTokenRevocationRepository::new();
+
+        repo.expect_create_revocation().return_once(|_| Ok(()));
+
+        repo.expect_delete_expired_revocations()
+            .returning(|| Ok(()));
+
+        repo.expect_get_active_revocations()
+            .return_once(|| Ok(vec![]));
+
+        let handler = TokenRevocationHandlerImpl::new(
+            Box::new(repo),
+            Duration::minutes(5),
+            Duration::minutes(1),
+        );
+        handler.sync_cache_if_stale().await.unwrap();
+
+        handler
+            .revoke_session_tokens(user_id, session_id)
+            .await
+            .unwrap();
+
+        assert!(handler
+            .is_token_revoked(Uuid::new_v4(), user_id, Some(session_id), Utc::now())
+            .await
+            .unwrap());
+        assert!(!handler
+            .is_token_revoked(Uuid::new_v4(), user_id, Some(Uuid::new_v4()), Utc::now())
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,453 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Duration, Utc};
+use data_encoding::BASE32_NOPAD;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::{cipher::SecretCipher, totp},
+    domain::two_factor::{TwoFactor, TwoFactorChallenge, TwoFactorRepository},
+    repositories::error::{
+        ErrorMessage::{InvalidTwoFactorChallenge, InvalidTwoFactorCode, TwoFactorAlreadyEnabled},
+        RepositoryError,
+    },
+    utils::{generate_token, hash_token},
+};
+
+pub type DynTwoFactorHandler = dyn TwoFactorHandler + Send + Sync;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Wrong codes allowed per challenge before the password has to be entered again.
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+/// Returned once at enrollment. Neither the secret nor the recovery codes can be shown
+/// again afterwards.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TwoFactorEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+    pub recovery_codes: Vec<String>,
+}
+
+pub struct TwoFactorHandlerImpl {
+    pub two_factor_repository: Box<dyn TwoFactorRepository + Send + Sync>,
+    pub cipher: SecretCipher,
+    pub issuer: String,
+    pub challenge_lifetime: Duration,
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait TwoFactorHandler {
+    /// Starts over any pending enrollment. 2FA is only enforced once confirmed.
+    async fn enroll(
+        &self,
+        user_id: Uuid,
+        account_name: String,
+    ) -> Result<TwoFactorEnrollment, RepositoryError>;
+
+    async fn confirm(&self, user_id: Uuid, code: String) -> Result<(), RepositoryError>;
+
+    async fn disable(&self, user_id: Uuid) -> Result<(), RepositoryError>;
+
+    /// Checks a TOTP or recovery code of a user with 2FA enabled, using it up.
+    async fn verify_code(&self, user_id: Uuid, code: String) -> Result<(), RepositoryError>;
+
+    async fn is_enabled(&self, user_id: Uuid) -> Result<bool, RepositoryError>;
+
+    /// Issues the token handed out instead of the JWT when the password was right.
+    async fn create_challenge(&self, user_id: Uuid) -> Result<String, RepositoryError>;
+
+    /// Returns the user of a pending challenge, so that the code can be throttled like
+    /// the password was.
+    async fn challenge_user(&self, challenge_token: String) -> Result<Uuid, RepositoryError>;
+
+    /// Consumes a challenge given a TOTP or recovery code and returns its user.
+    async fn complete_challenge(
+        &self,
+        challenge_token: String,
+        code: String,
+    ) -> Result<Uuid, RepositoryError>;
+}
+
+#[async_trait::async_trait]
+impl TwoFactorHandler for TwoFactorHandlerImpl {
+    #[tracing::instrument(skip(self))]
+    async fn enroll(
+        &self,
+        user_id: Uuid,
+        account_name: String,
+    ) -> Result<TwoFactorEnrollment, RepositoryError> {
+        let two_factor = self.two_factor_repository.get_two_factor(user_id).await?;
+        if two_factor.is_some_and(|two_factor| two_factor.enabled_at.is_some()) {
+            return Err(RepositoryError::Conflict(TwoFactorAlreadyEnabled));
+        }
+
+        let secret = totp::generate_secret();
+        let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| generate_recovery_code())
+            .collect();
+        let recovery_code_hashes = recovery_codes
+            .iter()
+            .map(|code| hash_token(&normalize_recovery_code(code)))
+            .collect();
+
+        self.two_factor_repository
+            .save_pending_two_factor(user_id, self.cipher.encrypt(&secret), recovery_code_hashes)
+            .await?;
+
+        Ok(TwoFactorEnrollment {
+            secret: totp::encode_secret(&secret),
+            otpauth_uri: totp::otpauth_uri(&self.issuer, &account_name, &secret),
+            recovery_codes,
+        })
+    }
+
+    #[tracing::instrument(skip(self, code))]
+    async fn confirm(&self, user_id: Uuid, code: String) -> Result<(), RepositoryError> {
+        let two_factor = self
+            .two_factor_repository
+            .get_two_factor(user_id)
+            .await?
+            .ok_or(RepositoryError::NotFound)?;
+        if two_factor.enabled_at.is_some() {
+            return Err(RepositoryError::Conflict(TwoFactorAlreadyEnabled));
+        }
+
+        let secret = self.decrypt_secret(&two_factor.secret)?;
+        let step = totp::verify_code(&secret, &code, Utc::now(), None)
+            .ok_or(RepositoryError::InvalidToken(InvalidTwoFactorCode))?;
+
+        self.two_factor_repository
+            .enable_two_factor(user_id, step)
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn disable(&self, user_id: Uuid) -> Result<(), RepositoryError> {
+        self.two_factor_repository.delete_two_factor(user_id).await
+    }
+
+    #[tracing::instrument(skip(self, code))]
+    async fn verify_code(&self, user_id: Uuid, code: String) -> Result<(), RepositoryError> {
+        let two_factor = self
+            .two_factor_repository
+            .get_two_factor(user_id)
+            .await?
+            .filter(|two_factor| two_factor.enabled_at.is_some())
+            .ok_or(RepositoryError::NotFound)?;
+
+        if !self.use_code(user_id, &two_factor, &code).await? {
+            return Err(RepositoryError::InvalidToken(InvalidTwoFactorCode));
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn is_enabled(&self, user_id: Uuid) -> Result<bool, RepositoryError> {
+        let two_factor = self.two_factor_repository.get_two_factor(user_id).await?;
+        Ok(two_factor.is_some_and(|two_factor| two_factor.enabled_at.is_some()))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn create_challenge(&self, user_id: Uuid) -> Result<String, RepositoryError> {
+        let token = generate_token();
+        self.two_factor_repository
+            .create_challenge(
+                user_id,
+                hash_token(&token),
+                Utc::now() + self.challenge_lifetime,
+            )
+            .await?;
+        Ok(token)
+    }
+
+    #[tracing::instrument(skip(self, challenge_token))]
+    async fn challenge_user(&self, challenge_token: String) -> Result<Uuid, RepositoryError> {
+        let challenge = self.pending_challenge(&challenge_token).await?;
+        Ok(challenge.user_id)
+    }
+
+    #[tracing::instrument(skip(self, challenge_token, code))]
+    async fn complete_challenge(
+        &self,
+        challenge_token: String,
+        code: String,
+    ) -> Result<Uuid, RepositoryError> {
+        let invalid_challenge = || RepositoryError::InvalidToken(InvalidTwoFactorChallenge);
+
+        let challenge = self.pending_challenge(&challenge_token).await?;
+
+        let two_factor = self
+            .two_factor_repository
+            .get_two_factor(challenge.user_id)
+            .await?
+            .filter(|two_factor| two_factor.enabled_at.is_some())
+            .ok_or_else(invalid_challenge)?;
+
+        if !self.use_code(challenge.user_id, &two_factor, &code).await? {
+            if challenge.failed_attempts + 1 >= MAX_CHALLENGE_ATTEMPTS {
+                self.two_factor_repository
+                    .delete_challenge(challenge.id)
+                    .await?;
+            } else {
+                self.two_factor_repository
+                    .increment_challenge_failures(challenge.id)
+                    .await?;
+            }
+            return Err(RepositoryError::InvalidToken(InvalidTwoFactorCode));
+        }
+
+        // Deleting is what consumes the challenge, so a concurrent request with the same
+        // token and another valid code cannot get a second set of tokens.
+        if !self
+            .two_factor_repository
+            .delete_challenge(challenge.id)
+            .await?
+        {
+            return Err(invalid_challenge());
+        }
+        Ok(challenge.user_id)
+    }
+}
+
+impl TwoFactorHandlerImpl {
+    async fn pending_challenge(
+        &self,
+        challenge_token: &str,
+    ) -> Result<TwoFactorChallenge, RepositoryError> {
+        let invalid_challenge = || RepositoryError::InvalidToken(InvalidTwoFactorChallenge);
+
+        let challenge = self
+            .two_factor_repository
+            .get_challenge_by_hash(hash_token(challenge_token))
+            .await?
+            .ok_or_else(invalid_challenge)?;
+        if challenge.expires_at <= Utc::now() {
+            self.two_factor_repository
+                .delete_challenge(challenge.id)
+                .await?;
+            return Err(invalid_challenge());
+        }
+        Ok(challenge)
+    }
+
+    /// Returns whether the code is the current TOTP code or an unused recovery code,
+    /// marking it as used so it cannot be replayed.
+    async fn use_code(
+        &self,
+        user_id: Uuid,
+        two_factor: &TwoFactor,
+        code: &str,
+    ) -> Result<bool, RepositoryError> {
+        let secret = self.decrypt_secret(&two_factor.secret)?;
+        match totp::verify_code(&secret, code, Utc::now(), two_factor.last_used_step) {
+            Some(step) => {
+                self.two_factor_repository
+                    .update_last_used_step(user_id, step)
+                    .await
+            }
+            None => {
+                self.two_factor_repository
+                    .use_recovery_code(user_id, hash_token(&normalize_recovery_code(code)))
+                    .await
+            }
+        }
+    }
+
+    fn decrypt_secret(&self, secret: &str) -> Result<Vec<u8>, RepositoryError> {
+        self.cipher
+            .decrypt(secret)
+            .map_err(RepositoryError::EncryptionError)
+    }
+}
+
+/// Ten base32 characters split in two groups, e.g. `k3v7q-x2mzp`.
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut bytes);
+    let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+    format!("{}-{}", &code[..5], &code[5..10])
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::two_factor::{mocks::*, MockTwoFactorRepository};
+
+    fn cipher() -> SecretCipher {
+        SecretCipher::new(&[7; 32]).unwrap()
+    }
+
+    fn enabled_two_factor() -> TwoFactor {
+        TwoFactor {
+            secret: cipher().encrypt(b"12345678901234567890"),
+            enabled_at: Some(Utc::now()),
+            last_used_step: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn enrolls_with_encrypted_secret_and_hashed_recovery_codes() {
+        let mut repo = MockTwoFactorRepository::new();
+
+        repo.expect_get_two_factor().return_once(|_| Ok(None));
+        repo.expect_save_pending_two_factor()
+            .withf(|_, secret, hashes| {
+                cipher().decrypt(secret).is_ok() && hashes.len() == RECOVERY_CODE_COUNT
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let enrollment = TwoFactorHandlerImpl {
+            two_factor_repository: Box::new(repo),
+            cipher: cipher(),
+            issuer: "users-actix".to_string(),
+            challenge_lifetime: Duration::minutes(5),
+        }
+        .enroll(Uuid::new_v4(), "john@example.com".to_string())
+        .await
+        .expect("Failed to enroll");
+
+        assert!(enrollment
+            .otpauth_uri
+            .contains(&format!("secret={}", enrollment.secret)));
+        assert_eq!(enrollment.recovery_codes.len(), RECOVERY_CODE_COUNT);
+    }
+
+    #[tokio::test]
+    async fn does_not_enroll_twice() {
+        let user_id = Uuid::new_v4();
+        let mut repo = MockTwoFactorRepository::new();
+
+        repo.expect_get_two_factor()
+            .return_once(move |_| Ok(Some(enabled_two_factor())));
+        repo.expect_save_pending_two_factor().never();
+
+        let result = TwoFactorHandlerImpl {
+            two_factor_repository: Box::new(repo),
+            cipher: cipher(),
+            issuer: "users-actix".to_string(),
+            challenge_lifetime: Duration::minutes(5),
+        }
+        .enroll(user_id, "john@example.com".to_string())
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn completes_challenge_with_recovery_code() {
+        let challenge = factori::create!(TwoFactorChallenge);
+        let user_id = challenge.user_id;
+        let mut repo = MockTwoFactorRepository::new();
+
+        repo.expect_get_challenge_by_hash()
+            .return_once(move |_| Ok(Some(challenge)));
+        repo.expect_get_two_factor()
+            .return_once(move |_| Ok(Some(enabled_two_factor())));
+        repo.expect_use_recovery_code()
+            .withf(|_, hash| *hash == hash_token("k3v7qx2mzp"))
+            .return_once(|_, _| Ok(true));
+        repo.expect_delete_challenge().return_once(|_| Ok(true));
+
+        let result = TwoFactorHandlerImpl {
+            two_factor_repository: Box::new(repo),
+            cipher: cipher(),
+            issuer: "users-actix".to_string(),
+            challenge_lifetime: Duration::minutes(5),
+        }
+        .complete_challenge("token".to_string(), "K3V7Q-X2MZP".to_string())
+        .await;
+
+        assert_eq!(result.unwrap(), user_id);
+    }
+
+    #[tokio::test]
+    async fn drops_challenge_after_too_many_wrong_codes() {
+        let challenge = factori::create!(
+            TwoFactorChallenge,
+            failed_attempts: MAX_CHALLENGE_ATTEMPTS - 1
+        );
+        let mut repo = MockTwoFactorRepository::new();
+
+        repo.expect_get_challenge_by_hash()
+            .return_once(move |_| Ok(Some(challenge)));
+        repo.expect_get_two_factor()
+            .return_once(move |_| Ok(Some(enabled_two_factor())));
+        repo.expect_use_recovery_code()
+            .return_once(|_, _| Ok(false));
+        repo.expect_increment_challenge_failures().never();
+        repo.expect_delete_challenge()
+            .times(1)
+            .returning(|_| Ok(true));
+
+        let result = TwoFactorHandlerImpl {
+            two_factor_repository: Box::new(repo),
+            cipher: cipher(),
+            issuer: "users-actix".to_string(),
+            challenge_lifetime: Duration::minutes(5),
+        }
+        .complete_challenge("token".to_string(), "invalid".to_string())
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::InvalidToken(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_code_of_enabled_two_factor() {
+        let mut repo = MockTwoFactorRepository::new();
+
+        repo.expect_get_two_factor()
+            .return_once(move |_| Ok(Some(enabled_two_factor())));
+        repo.expect_use_recovery_code()
+            .return_once(|_, _| Ok(false));
+
+        let result = TwoFactorHandlerImpl {
+            two_factor_repository: Box::new(repo),
+            cipher: cipher(),
+            issuer: "users-actix".to_string(),
+            challenge_lifetime: Duration::minutes(5),
+        }
+        .verify_code(Uuid::new_v4(), "000000".to_string())
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(RepositoryError::InvalidToken(InvalidTwoFactorCode))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_challenge() {
+        let challenge = factori::create!(
+            TwoFactorChallenge,
+            expires_at: Utc::now() - Duration::seconds(1)
+        );
+        let mut repo = MockTwoFactorRepository::new();
+
+        repo.expect_get_challenge_by_hash()
+            .return_once(move |_| Ok(Some(challenge)));
+        repo.expect_delete_challenge().return_once(|_| Ok(true));
+        repo.expect_get_two_factor().never();
+
+        let result = TwoFactorHandlerImpl {
+            two_factor_repository: Box::new(repo),
+            cipher: cipher(),
+            issuer: "users-actix".to_string(),
+            challenge_lifetime: Duration::minutes(5),
+        }
+        .complete_challenge("token".to_string(), "invalid".to_string())
+        .await;
+
+        assert!(matches!(result, Err(RepositoryError::InvalidToken(_))));
+    }
+}
@@ -1,11 +1,17 @@
+use std::sync::Arc;
+
+use argon2::password_hash::Error;
 use uuid::Uuid;
 
-use crate::domain::user::payload::LoginUserPayload;
+use crate::domain::password_policy::PasswordPolicy;
+
+use crate::domain::user::password::{dummy_hash, needs_rehash, verify_passwords};
+use crate::domain::user::payload::{ChangePasswordPayload, LoginUserPayload};
 use crate::repositories::error::ErrorMessage::{ExistingEmail, ExistingNickame};
 use crate::{
     domain::user::{
         payload::{NewUserPayload, UpdateUserPayload},
-        PublicUser, UserRepository,
+        PublicUser, Role, UserRepository,
     },
     repositories::error::RepositoryError,
 };
@@ -14,6 +20,7 @@ pub type DynUserHandler = dyn UserHandler + Send + Sync;
 
 pub struct UserHandlerImpl {
     pub user_repository: Box<dyn UserRepository + Send + Sync>,
+    pub password_policy: Arc<PasswordPolicy>,
 }
 
 #[cfg_attr(test, mockall::automock)]
@@ -27,25 +34,39 @@ pub trait UserHandler {
         update_payload: UpdateUserPayload,
     ) -> Result<(), RepositoryError>;
 
-    async fn get_user_by_nickname(
-        &self,
-        nickname: String,
-    ) -> Result<Option<PublicUser>, RepositoryError>;
+    async fn update_user_role(&self, id: Uuid, role: Role) -> Result<(), RepositoryError>;
 
     async fn get_user_by_id(&self, id: Uuid) -> Result<Option<PublicUser>, RepositoryError>;
 
     async fn delete_user(&self, id: Uuid) -> Result<(), RepositoryError>;
 
+    /// Fails with [`RepositoryError::InvalidCredentials`] whether the email is unknown or
+    /// the password wrong, after verifying a hash in both cases so timing does not tell
+    /// them apart either.
     async fn get_user_by_login(
         &self,
         login_payload: LoginUserPayload,
-    ) -> Result<Uuid, RepositoryError>;
+    ) -> Result<PublicUser, RepositoryError>;
+
+    /// Fails with [`RepositoryError::HashingError`] if the password is wrong.
+    async fn verify_password(&self, id: Uuid, password: String) -> Result<(), RepositoryError>;
+
+    /// Fails with [`RepositoryError::HashingError`] if the current password is wrong.
+    async fn change_password(
+        &self,
+        id: Uuid,
+        payload: ChangePasswordPayload,
+    ) -> Result<(), RepositoryError>;
 }
 
 #[async_trait::async_trait]
 impl UserHandler for UserHandlerImpl {
-    #[tracing::instrument(skip(self))]
+    #[tracing::instrument(skip(self, new_user))]
     async fn create_user(&self, new_user: NewUserPayload) -> Result<PublicUser, RepositoryError> {
+        self.password_policy
+            .check(&new_user.password, &new_user.nickname, &new_user.email)
+            .map_err(RepositoryError::WeakPassword)?;
+
         let user_with_nickname = self
             .user_repository
             .get_user_by_nickname(new_user.nickname.clone())
@@ -87,15 +108,13 @@ impl UserHandler for UserHandlerImpl {
     }
 
     #[tracing::instrument(skip(self))]
-    async fn get_user_by_nickname(
-        &self,
-        nickname: String,
-    ) -> Result<Option<PublicUser>, RepositoryError> {
-        let user = self.user_repository.get_user_by_nickname(nickname).await?;
+    async fn update_user_role(&self, id: Uuid, role: Role) -> Result<(), RepositoryError> {
+        let user = self.user_repository.get_user_by_id(id).await?;
         if user.is_none() {
             return Err(RepositoryError::NotFound);
         }
-        Ok(user)
+        self.user_repository.update_user_role(id, role).await?;
+        Ok(())
     }
 
     #[tracing::instrument(skip(self))]
@@ -117,33 +136,82 @@ impl UserHandler for UserHandlerImpl {
         Ok(())
     }
 
-    #[tracing::instrument(skip(self))]
+    #[tracing::instrument(skip(self, login_payload))]
     async fn get_user_by_login(
         &self,
         login_payload: LoginUserPayload,
-    ) -> Result<Uuid, RepositoryError> {
+    ) -> Result<PublicUser, RepositoryError> {
         let user = self
             .user_repository
-            .get_user_by_email(login_payload.email.clone())
+            .get_user_with_password_by_email(login_payload.email)
             .await?;
 
-        if user.is_none() {
-            return Err(RepositoryError::NotFound);
+        let password_hash = match &user {
+            Some(user) => user.password.clone(),
+            None => dummy_hash().await?,
+        };
+        let verification = verify_passwords(login_payload.password.clone(), password_hash).await;
+
+        let user = match (user, verification) {
+            (Some(user), Ok(())) => user,
+            (_, Ok(()) | Err(RepositoryError::HashingError(Error::Password))) => {
+                return Err(RepositoryError::InvalidCredentials)
+            }
+            (_, Err(error)) => return Err(error),
+        };
+
+        if needs_rehash(&user.password) {
+            // The login goes through anyway, the upgrade is retried on the next one.
+            if let Err(error) = self
+                .user_repository
+                .upgrade_password(user.id, user.password.clone(), login_payload.password)
+                .await
+            {
+                tracing::warn!(%error, user_id = %user.id, "failed to upgrade password hash");
+            }
         }
 
-        let user = self
+        Ok(user.into())
+    }
+
+    #[tracing::instrument(skip(self, password))]
+    async fn verify_password(&self, id: Uuid, password: String) -> Result<(), RepositoryError> {
+        let password_hash = self
             .user_repository
-            .get_user_by_login(login_payload)
-            .await?;
+            .get_password_hash(id)
+            .await?
+            .ok_or(RepositoryError::NotFound)?;
+
+        verify_passwords(password, password_hash).await
+    }
+
+    #[tracing::instrument(skip(self, payload))]
+    async fn change_password(
+        &self,
+        id: Uuid,
+        payload: ChangePasswordPayload,
+    ) -> Result<(), RepositoryError> {
+        self.verify_password(id, payload.current_password).await?;
 
-        Ok(user.id)
+        let user = self
+            .user_repository
+            .get_user_by_id(id)
+            .await?
+            .ok_or(RepositoryError::NotFound)?;
+        self.password_policy
+            .check(&payload.new_password, &user.nickname, &user.email)
+            .map_err(RepositoryError::WeakPassword)?;
+
+        self.user_repository
+            .update_password(id, payload.new_password)
+            .await
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::domain::user::{mocks::*, MockUserRepository};
+    use crate::domain::user::{mocks::*, password, MockUserRepository};
 
     #[tokio::test]
     async fn creates_user_correctly() {
@@ -160,6 +228,7 @@ mod test {
 
         let handler = UserHandlerImpl {
             user_repository: Box::new(repo),
+            password_policy: Arc::new(PasswordPolicy::default()),
         };
 
         handler
@@ -182,6 +251,7 @@ mod test {
 
         let handler = UserHandlerImpl {
             user_repository: Box::new(repo),
+            password_policy: Arc::new(PasswordPolicy::default()),
         };
 
         let result = handler.create_user(new_user_payload).await;
@@ -203,10 +273,118 @@ mod test {
 
         let handler = UserHandlerImpl {
             user_repository: Box::new(repo),
+            password_policy: Arc::new(PasswordPolicy::default()),
         };
 
         let result = handler.create_user(new_user_payload).await;
 
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn does_not_change_password_if_current_one_is_wrong() {
+        let password_hash = password::hash_password("password".to_string())
+            .await
+            .unwrap();
+        let mut repo = MockUserRepository::new();
+
+        repo.expect_get_password_hash()
+            .return_once(|_| Ok(Some(password_hash)));
+
+        repo.expect_update_password().never();
+
+        let handler = UserHandlerImpl {
+            user_repository: Box::new(repo),
+            password_policy: Arc::new(PasswordPolicy::default()),
+        };
+
+        let result = handler
+            .change_password(
+                Uuid::new_v4(),
+                ChangePasswordPayload {
+                    current_password: "wrong password".to_string(),
+                    new_password: "new password".to_string(),
+                },
+            )
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(RepositoryError::HashingError(
+                argon2::password_hash::Error::Password
+            ))
+        ));
+    }
+
+    fn login_payload(password: &str) -> LoginUserPayload {
+        LoginUserPayload {
+            email: "johndoe@gmail.com".to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_email_and_wrong_password_alike() {
+        let password_hash = password::hash_password("password".to_string())
+            .await
+            .unwrap();
+        let mut repo = MockUserRepository::new();
+
+        repo.expect_get_user_with_password_by_email()
+            .return_once(|_| Ok(None));
+
+        let unknown_email = UserHandlerImpl {
+            user_repository: Box::new(repo),
+            password_policy: Arc::new(PasswordPolicy::default()),
+        }
+        .get_user_by_login(login_payload("password"))
+        .await;
+
+        let mut repo = MockUserRepository::new();
+
+        repo.expect_get_user_with_password_by_email()
+            .return_once(|_| Ok(Some(factori::create!(User, password: password_hash))));
+
+        let wrong_password = UserHandlerImpl {
+            user_repository: Box::new(repo),
+            password_policy: Arc::new(PasswordPolicy::default()),
+        }
+        .get_user_by_login(login_payload("wrong password"))
+        .await;
+
+        assert!(matches!(
+            unknown_email,
+            Err(RepositoryError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            wrong_password,
+            Err(RepositoryError::InvalidCredentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn upgrades_outdated_hash_on_login() {
+        let bcrypt_hash = bcrypt::hash("password", 4).unwrap();
+        let mut repo = MockUserRepository::new();
+
+        repo.expect_get_user_with_password_by_email()
+            .return_once(|_| Ok(Some(factori::create!(User, password: bcrypt_hash))));
+
+        repo.expect_upgrade_password()
+            .withf(|_, current_hash, password| {
+                current_hash.starts_with("$2b$") && password == "password"
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let handler = UserHandlerImpl {
+            user_repository: Box::new(repo),
+            password_policy: Arc::new(PasswordPolicy::default()),
+        };
+
+        handler
+            .get_user_by_login(login_payload("password"))
+            .await
+            .expect("Failed to log in");
+    }
 }
@@ -0,0 +1,119 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use uuid::Uuid;
+
+pub type DynMailTransport = dyn MailTransport + Send + Sync;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub struct MailError(pub String);
+
+impl std::error::Error for MailError {}
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not send mail: {}", self.0)
+    }
+}
+
+/// Delivers outgoing mail. Only local transports exist for now; a relay can be plugged
+/// in by implementing this trait.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait MailTransport {
+    async fn send(&self, mail: Mail) -> Result<(), MailError>;
+}
+
+/// Sends `mail` from a spawned task and only logs failures, so the response does not
+/// wait on delivery and its timing does not tell whether there was any mail to send.
+pub fn send_in_background(mail_transport: Arc<DynMailTransport>, mail: Mail) {
+    tokio::spawn(async move {
+        if let Err(error) = mail_transport.send(mail).await {
+            tracing::error!(%error, "failed to send mail");
+        }
+    });
+}
+
+/// Writes every mail to its own file in `directory`, for local development.
+pub struct FileMailTransport {
+    pub directory: PathBuf,
+    pub from: String,
+}
+
+#[async_trait::async_trait]
+impl MailTransport for FileMailTransport {
+    async fn send(&self, mail: Mail) -> Result<(), MailError> {
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .map_err(|e| MailError(e.to_string()))?;
+
+        let now = Utc::now();
+        let path = self.directory.join(format!(
+            "{}-{}.eml",
+            now.format("%Y%m%d%H%M%S"),
+            Uuid::new_v4()
+        ));
+        let contents = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n\r\n{}\r\n",
+            self.from,
+            mail.to,
+            mail.subject,
+            now.to_rfc2822(),
+            mail.body
+        );
+        tokio::fs::write(&path, contents)
+            .await
+            .map_err(|e| MailError(e.to_string()))?;
+
+        tracing::info!(path = %path.display(), to = %mail.to, "mail written to file");
+        Ok(())
+    }
+}
+
+/// Keeps sent mail in memory so it can be inspected, mostly from tests.
+#[derive(Default, Clone)]
+pub struct InMemoryMailTransport {
+    sent: Arc<Mutex<Vec<Mail>>>,
+}
+
+impl InMemoryMailTransport {
+    #[cfg(test)]
+    pub fn sent(&self) -> Vec<Mail> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl MailTransport for InMemoryMailTransport {
+    async fn send(&self, mail: Mail) -> Result<(), MailError> {
+        self.sent.lock().unwrap().push(mail);
+        Ok(())
+    }
+}
+
+/// Picks the transport named by `MAIL_TRANSPORT`, either `file` (the default, writing to
+/// `MAIL_DIRECTORY`) or `memory`.
+pub fn transport_from_env() -> Result<Arc<DynMailTransport>, String> {
+    let transport = std::env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "file".to_string());
+    match transport.as_str() {
+        "file" => Ok(Arc::new(FileMailTransport {
+            directory: std::env::var("MAIL_DIRECTORY")
+                .unwrap_or_else(|_| "mail".to_string())
+                .into(),
+            from: std::env::var("MAIL_FROM").unwrap_or_else(|_| "no-reply@localhost".to_string()),
+        })),
+        "memory" => Ok(Arc::new(InMemoryMailTransport::default())),
+        other => Err(format!("Unknown MAIL_TRANSPORT {other}")),
+    }
+}
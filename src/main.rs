@@ -5,16 +5,56 @@ mod routes;
 mod utils;
 mod error;
 mod auth;
+mod mail;
 use std::sync::Arc;
 use std::env;
 use dotenv::dotenv;
 pub mod response;
 
 use actix_web::{web, App, HttpServer};
+use auth::{
+    cipher::SecretCipher,
+    cookie::CookieSettings,
+    keys::JwtKeys,
+    oidc::{OidcProvider, OidcSettings},
+    JwtSettings,
+};
 use sqlx::{PgPool, postgres::PgPoolOptions};
-use handlers::user::{DynUserHandler, UserHandlerImpl};
-use repositories::user::SqlUserRepository;
-use routes::{user::user_routes, auth::auth_routes};
+use domain::{
+    email_verification::EmailVerificationPolicy,
+    password_policy::PasswordPolicy,
+    user::password::{self, PasswordConfig},
+};
+use handlers::{
+    email_change::{DynEmailChangeHandler, EmailChangeHandlerImpl},
+    email_verification::{DynEmailVerificationHandler, EmailVerificationHandlerImpl},
+    login_attempt::{DynLoginAttemptHandler, LoginAttemptHandlerImpl, LoginAttemptPolicy},
+    magic_link::{DynMagicLinkHandler, MagicLinkHandlerImpl},
+    oauth::{DynOAuthHandler, OAuthHandlerImpl},
+    oidc::{DynOidcHandler, OidcHandlerImpl},
+    password_reset::{DynPasswordResetHandler, PasswordResetHandlerImpl},
+    personal_access_token::{DynPersonalAccessTokenHandler, PersonalAccessTokenHandlerImpl},
+    refresh_token::{DynRefreshTokenHandler, RefreshTokenHandlerImpl},
+    session::{DynSessionHandler, SessionHandlerImpl},
+    token_revocation::{DynTokenRevocationHandler, TokenRevocationHandlerImpl},
+    two_factor::{DynTwoFactorHandler, TwoFactorHandlerImpl},
+    user::{DynUserHandler, UserHandlerImpl},
+};
+use repositories::{
+    email_change::SqlEmailChangeRepository, email_verification::SqlEmailVerificationRepository,
+    login_attempt::SqlLoginAttemptRepository, magic_link::SqlMagicLinkRepository,
+    oauth::SqlOAuthRepository, oidc::SqlOidcRepository, password_reset::SqlPasswordResetRepository,
+    personal_access_token::SqlPersonalAccessTokenRepository,
+    refresh_token::SqlRefreshTokenRepository, session::SqlSessionRepository,
+    token_revocation::SqlTokenRevocationRepository,
+    two_factor::SqlTwoFactorRepository, user::SqlUserRepository,
+};
+use error::error_handlers;
+use utils::env_var_or;
+use routes::{
+    user::user_routes, auth::auth_routes, well_known::well_known_routes, metrics::metrics_routes,
+    oauth::oauth_routes,
+};
 
 #[tokio::main]
 async fn main() {
@@ -23,22 +63,202 @@ async fn main() {
     env_logger::init();
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL not set in .env file");
+    let jwt_keys = JwtKeys::from_env()
+        .unwrap_or_else(|e| panic!("{e}"))
+        .with_settings(JwtSettings::from_env());
+    let two_factor_cipher = SecretCipher::from_env().unwrap_or_else(|e| panic!("{e}"));
+    let cookie_settings = CookieSettings::from_env().unwrap_or_else(|e| panic!("{e}"));
+    let mail_transport = mail::transport_from_env().unwrap_or_else(|e| panic!("{e}"));
+    password::configure(PasswordConfig::from_env().unwrap_or_else(|e| panic!("{e}")));
+    // Built up front so the first login with an unknown email is not slower than the others.
+    password::dummy_hash().await.expect("Could not hash the dummy password");
 
     let pool: PgPool = PgPoolOptions::new()
         .connect(&database_url)
         .await.expect("Could not connect to database");
 
-    let user_repository = Box::new(SqlUserRepository { pool });
+    let refresh_token_lifetime_days = env_var_or("REFRESH_TOKEN_LIFETIME_DAYS", 30);
+    let login_attempt_policy = LoginAttemptPolicy {
+        max_attempts_per_account: env_var_or("LOGIN_MAX_ATTEMPTS_PER_ACCOUNT", 5),
+        max_attempts_per_ip: env_var_or("LOGIN_MAX_ATTEMPTS_PER_IP", 20),
+        lockout: chrono::Duration::minutes(env_var_or("LOGIN_LOCKOUT_MINUTES", 15)),
+        ..LoginAttemptPolicy::default()
+    };
 
-    let user_handler: Arc<DynUserHandler> = Arc::new(UserHandlerImpl { user_repository });
+    let user_repository = Box::new(SqlUserRepository { pool: pool.clone() });
+    let refresh_token_repository = Box::new(SqlRefreshTokenRepository { pool: pool.clone() });
+    let session_repository = Box::new(SqlSessionRepository { pool: pool.clone() });
+    let token_revocation_repository = Box::new(SqlTokenRevocationRepository { pool: pool.clone() });
+    let personal_access_token_repository =
+        Box::new(SqlPersonalAccessTokenRepository { pool: pool.clone() });
+    let login_attempt_repository = Box::new(SqlLoginAttemptRepository { pool: pool.clone() });
+    let two_factor_repository = Box::new(SqlTwoFactorRepository { pool: pool.clone() });
+    let password_reset_repository = Box::new(SqlPasswordResetRepository { pool: pool.clone() });
+    let password_reset_user_repository = Box::new(SqlUserRepository { pool: pool.clone() });
+    let email_verification_repository =
+        Box::new(SqlEmailVerificationRepository { pool: pool.clone() });
+    let email_verification_user_repository = Box::new(SqlUserRepository { pool: pool.clone() });
+    let email_change_repository = Box::new(SqlEmailChangeRepository { pool: pool.clone() });
+    let email_change_user_repository = Box::new(SqlUserRepository { pool: pool.clone() });
+    let magic_link_repository = Box::new(SqlMagicLinkRepository { pool: pool.clone() });
+    let magic_link_user_repository = Box::new(SqlUserRepository { pool: pool.clone() });
+    let oidc_repository = Box::new(SqlOidcRepository { pool: pool.clone() });
+    let oidc_user_repository = Box::new(SqlUserRepository { pool: pool.clone() });
+    let oauth_repository = Box::new(SqlOAuthRepository { pool });
 
+    let password_policy = Arc::new(PasswordPolicy::from_env().unwrap_or_else(|e| panic!("{e}")));
+
+    let user_handler: Arc<DynUserHandler> = Arc::new(UserHandlerImpl {
+        user_repository,
+        password_policy: password_policy.clone(),
+    });
+
+    let refresh_token_handler: Arc<DynRefreshTokenHandler> = Arc::new(RefreshTokenHandlerImpl {
+        refresh_token_repository,
+        token_lifetime: chrono::Duration::days(refresh_token_lifetime_days),
+    });
+
+    let session_handler: Arc<DynSessionHandler> = Arc::new(SessionHandlerImpl {
+        session_repository,
+        session_lifetime: chrono::Duration::days(refresh_token_lifetime_days),
+    });
+
+    let token_revocation_handler: Arc<DynTokenRevocationHandler> =
+        Arc::new(TokenRevocationHandlerImpl::new(
+            token_revocation_repository,
+            jwt_keys.settings().max_token_age(),
+            chrono::Duration::seconds(30),
+        ));
+
+    let personal_access_token_handler: Arc<DynPersonalAccessTokenHandler> =
+        Arc::new(PersonalAccessTokenHandlerImpl {
+            personal_access_token_repository,
+        });
+
+    let login_attempt_handler: Arc<DynLoginAttemptHandler> = Arc::new(LoginAttemptHandlerImpl {
+        login_attempt_repository,
+        policy: login_attempt_policy,
+    });
+
+    let two_factor_handler: Arc<DynTwoFactorHandler> = Arc::new(TwoFactorHandlerImpl {
+        two_factor_repository,
+        cipher: two_factor_cipher,
+        issuer: env::var("TWO_FACTOR_ISSUER").unwrap_or_else(|_| "users-actix".to_string()),
+        challenge_lifetime: chrono::Duration::seconds(auth::TWO_FACTOR_CHALLENGE_LIFETIME_SECONDS),
+    });
+
+    let password_reset_handler: Arc<DynPasswordResetHandler> =
+        Arc::new(PasswordResetHandlerImpl {
+            password_reset_repository,
+            user_repository: password_reset_user_repository,
+            mail_transport: mail_transport.clone(),
+            password_policy,
+            token_lifetime: chrono::Duration::minutes(env_var_or(
+                "PASSWORD_RESET_TOKEN_LIFETIME_MINUTES",
+                60,
+            )),
+            reset_url: env::var("PASSWORD_RESET_URL")
+                .unwrap_or_else(|_| "http://localhost:3000/password-reset".to_string()),
+        });
+
+    let email_verification_handler: Arc<DynEmailVerificationHandler> =
+        Arc::new(EmailVerificationHandlerImpl {
+            email_verification_repository,
+            user_repository: email_verification_user_repository,
+            mail_transport: mail_transport.clone(),
+            token_lifetime: chrono::Duration::hours(env_var_or(
+                "EMAIL_VERIFICATION_TOKEN_LIFETIME_HOURS",
+                48,
+            )),
+            resend_interval: chrono::Duration::seconds(env_var_or(
+                "EMAIL_VERIFICATION_RESEND_INTERVAL_SECONDS",
+                60,
+            )),
+            verify_url: env::var("EMAIL_VERIFICATION_URL")
+                .unwrap_or_else(|_| "http://localhost:3000/verify-email".to_string()),
+        });
+    let email_change_handler: Arc<DynEmailChangeHandler> = Arc::new(EmailChangeHandlerImpl {
+        email_change_repository,
+        user_repository: email_change_user_repository,
+        mail_transport: mail_transport.clone(),
+        token_lifetime: chrono::Duration::hours(env_var_or("EMAIL_CHANGE_TOKEN_LIFETIME_HOURS", 24)),
+        confirm_url: env::var("EMAIL_CHANGE_URL")
+            .unwrap_or_else(|_| "http://localhost:3000/email-change".to_string()),
+    });
+    let magic_link_handler: Arc<DynMagicLinkHandler> = Arc::new(MagicLinkHandlerImpl {
+        magic_link_repository,
+        user_repository: magic_link_user_repository,
+        mail_transport,
+        token_lifetime: chrono::Duration::minutes(env_var_or("MAGIC_LINK_TOKEN_LIFETIME_MINUTES", 15)),
+        resend_interval: chrono::Duration::seconds(env_var_or(
+            "MAGIC_LINK_RESEND_INTERVAL_SECONDS",
+            60,
+        )),
+        login_url: env::var("MAGIC_LINK_URL")
+            .unwrap_or_else(|_| "http://localhost:3000/magic-link".to_string()),
+    });
+    let oidc_handler: Option<Arc<DynOidcHandler>> = OidcSettings::from_env()
+        .unwrap_or_else(|e| panic!("{e}"))
+        .map(|settings| -> Arc<DynOidcHandler> {
+            Arc::new(OidcHandlerImpl {
+                oidc_repository,
+                user_repository: oidc_user_repository,
+                provider: OidcProvider::new(settings),
+                login_lifetime: chrono::Duration::minutes(env_var_or("OIDC_LOGIN_LIFETIME_MINUTES", 10)),
+            })
+        });
+    let oauth_handler: Arc<DynOAuthHandler> = Arc::new(OAuthHandlerImpl {
+        oauth_repository,
+        code_lifetime: chrono::Duration::seconds(env_var_or("OAUTH_CODE_LIFETIME_SECONDS", 60)),
+    });
+    let email_verification_policy: EmailVerificationPolicy =
+        env_var_or("EMAIL_VERIFICATION_POLICY", EmailVerificationPolicy::default());
+
+    let jwt_keys = web::Data::new(jwt_keys);
+    let cookie_settings = web::Data::new(cookie_settings);
     let user_handler = web::Data::from(user_handler.clone());
+    let refresh_token_handler = web::Data::from(refresh_token_handler.clone());
+    let session_handler = web::Data::from(session_handler.clone());
+    let token_revocation_handler = web::Data::from(token_revocation_handler.clone());
+    let personal_access_token_handler = web::Data::from(personal_access_token_handler.clone());
+    let login_attempt_handler = web::Data::from(login_attempt_handler.clone());
+    let two_factor_handler = web::Data::from(two_factor_handler.clone());
+    let password_reset_handler = web::Data::from(password_reset_handler.clone());
+    let email_verification_handler = web::Data::from(email_verification_handler.clone());
+    let email_change_handler = web::Data::from(email_change_handler.clone());
+    let magic_link_handler = web::Data::from(magic_link_handler.clone());
+    let oidc_handler = oidc_handler.map(web::Data::from);
+    let oauth_handler = web::Data::from(oauth_handler.clone());
+    let email_verification_policy = web::Data::new(email_verification_policy);
 
     HttpServer::new(move || {
         App::new()
+            .app_data(jwt_keys.clone())
+            .app_data(cookie_settings.clone())
             .app_data(user_handler.clone())
+            .app_data(refresh_token_handler.clone())
+            .app_data(session_handler.clone())
+            .app_data(token_revocation_handler.clone())
+            .app_data(personal_access_token_handler.clone())
+            .app_data(login_attempt_handler.clone())
+            .app_data(two_factor_handler.clone())
+            .app_data(password_reset_handler.clone())
+            .app_data(email_verification_handler.clone())
+            .app_data(email_change_handler.clone())
+            .app_data(magic_link_handler.clone())
+            .app_data(oauth_handler.clone())
+            .app_data(email_verification_policy.clone())
+            .configure(|cfg| {
+                if let Some(oidc_handler) = &oidc_handler {
+                    cfg.app_data(oidc_handler.clone());
+                }
+            })
+            .configure(error_handlers)
             .configure(user_routes)
             .configure(auth_routes)
+            .configure(oauth_routes)
+            .configure(well_known_routes)
+            .configure(metrics_routes)
     })
     .bind(("127.0.0.1", port.parse::<u16>().unwrap()))
     .expect("Unable to run server on port {port}. Quitting")
@@ -0,0 +1,92 @@
+use crate::domain::email_change::{EmailChangeRepository, EmailChangeRequest};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error::{ErrorMessage::ExistingEmail, RepositoryError};
+
+/// SQLSTATE of a unique constraint violation.
+const UNIQUE_VIOLATION: &str = "23505";
+
+pub struct SqlEmailChangeRepository {
+    pub pool: PgPool,
+}
+
+#[async_trait::async_trait]
+impl EmailChangeRepository for SqlEmailChangeRepository {
+    async fn create_request(
+        &self,
+        user_id: Uuid,
+        new_email: String,
+        token_hash: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM email_change_requests WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut transaction)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO email_change_requests (id, user_id, new_email, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(new_email)
+        .bind(token_hash)
+        .bind(expires_at)
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn get_request_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<EmailChangeRequest>, RepositoryError> {
+        let row = sqlx::query_as::<_, EmailChangeRequest>(
+            "SELECT id, user_id, new_email, expires_at::TIMESTAMPTZ
+            FROM email_change_requests WHERE token_hash = $1",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn apply_request(&self, request: EmailChangeRequest) -> Result<bool, RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+
+        let result = sqlx::query("DELETE FROM email_change_requests WHERE id = $1")
+            .bind(request.id)
+            .execute(&mut transaction)
+            .await?;
+        if result.rows_affected() != 1 {
+            return Ok(false);
+        }
+
+        let now = Utc::now();
+        sqlx::query(
+            "UPDATE users SET email = $1, email_verified_at = $2, update_time = $2 WHERE id = $3",
+        )
+        .bind(request.new_email)
+        .bind(now)
+        .bind(request.user_id)
+        .execute(&mut transaction)
+        .await
+        .map_err(|error| match error {
+            // Someone may have signed up with the address since the request was made.
+            sqlx::Error::Database(error) if error.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+                RepositoryError::Conflict(ExistingEmail)
+            }
+            error => error.into(),
+        })?;
+
+        transaction.commit().await?;
+        Ok(true)
+    }
+}
@@ -1,6 +1,8 @@
 use argon2::password_hash::Error as Argon2Error;
 use sqlx::Error as SqlxError;
 use std::fmt;
+
+use crate::domain::{oauth::OAuthErrorCode, password_policy::PasswordViolation};
 use strum::EnumMessage;
 use strum_macros;
 
@@ -8,8 +10,22 @@ use strum_macros;
 pub enum RepositoryError {
     NotFound,
     Conflict(ErrorMessage),
+    InvalidToken(ErrorMessage),
     SqlxError(SqlxError),
     HashingError(Argon2Error),
+    /// Seconds the caller has to wait before trying again.
+    TooManyAttempts(u64),
+    EncryptionError(String),
+    /// Every rule of the password policy a new password breaks.
+    WeakPassword(Vec<PasswordViolation>),
+    /// Unknown email or wrong password, told apart by nothing.
+    InvalidCredentials,
+    /// Too much work is already queued, see [`crate::domain::user::password`].
+    Overloaded,
+    /// The OpenID Connect provider could not be reached or answered unexpectedly.
+    IdentityProvider(String),
+    /// A request to the OAuth2 authorization server it refuses, with a description.
+    OAuth(OAuthErrorCode, String),
 }
 
 impl std::error::Error for RepositoryError {}
@@ -18,16 +34,39 @@ impl fmt::Display for RepositoryError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             RepositoryError::NotFound => write!(f, "Not found"),
-            RepositoryError::Conflict(error_message) => {
+            RepositoryError::Conflict(error_message)
+            | RepositoryError::InvalidToken(error_message) => {
                 let message = error_message.get_message().unwrap().to_string();
                 write!(f, "{message}")
             }
             RepositoryError::SqlxError(error) => write!(f, "Internal error: {}", error),
             RepositoryError::HashingError(error) => write!(f, "Internal error: {}", error),
+            RepositoryError::EncryptionError(error) => write!(f, "Internal error: {}", error),
+            RepositoryError::WeakPassword(violations) => {
+                write!(f, "{}", format_violations(violations))
+            }
+            RepositoryError::InvalidCredentials => write!(f, "Invalid credentials"),
+            RepositoryError::Overloaded => write!(f, "Service temporarily unavailable"),
+            RepositoryError::IdentityProvider(error) => {
+                write!(f, "Identity provider error: {}", error)
+            }
+            RepositoryError::OAuth(code, description) => write!(f, "{code}: {description}"),
+            RepositoryError::TooManyAttempts(seconds) => {
+                write!(f, "Too many attempts, retry in {seconds} seconds")
+            }
         }
     }
 }
 
+pub fn format_violations(violations: &[PasswordViolation]) -> String {
+    let rules = violations
+        .iter()
+        .map(|violation| violation.to_string())
+        .collect::<Vec<_>>()
+        .join("; ");
+    format!("The password {rules}")
+}
+
 impl From<SqlxError> for RepositoryError {
     fn from(error: SqlxError) -> Self {
         RepositoryError::SqlxError(error)
@@ -47,4 +86,30 @@ pub enum ErrorMessage {
     ExistingNickame,
     #[strum(message = "This email is already in use")]
     ExistingEmail,
+    #[strum(message = "Invalid refresh token")]
+    InvalidRefreshToken,
+    #[strum(message = "Refresh token has already been used")]
+    RefreshTokenReuse,
+    #[strum(message = "Invalid access token")]
+    InvalidAccessToken,
+    #[strum(message = "Two-factor authentication is already enabled")]
+    TwoFactorAlreadyEnabled,
+    #[strum(message = "Invalid two-factor code")]
+    InvalidTwoFactorCode,
+    #[strum(message = "Invalid or expired two-factor challenge")]
+    InvalidTwoFactorChallenge,
+    #[strum(message = "Invalid or expired password reset token")]
+    InvalidPasswordResetToken,
+    #[strum(message = "Invalid or expired email verification token")]
+    InvalidEmailVerificationToken,
+    #[strum(message = "Invalid or expired email change token")]
+    InvalidEmailChangeToken,
+    #[strum(message = "Invalid or expired sign-in link")]
+    InvalidMagicLinkToken,
+    #[strum(message = "Invalid or expired sign-in request")]
+    InvalidOidcState,
+    #[strum(message = "Invalid ID token")]
+    InvalidIdToken,
+    #[strum(message = "The identity provider did not share an email address")]
+    MissingOidcEmail,
 }
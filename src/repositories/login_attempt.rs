@@ -0,0 +1,83 @@
+use crate::domain::login_attempt::{LoginAttempt, LoginAttemptRepository};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use super::error::RepositoryError;
+
+pub struct SqlLoginAttemptRepository {
+    pub pool: PgPool,
+}
+
+#[async_trait::async_trait]
+impl LoginAttemptRepository for SqlLoginAttemptRepository {
+    async fn get_attempts(&self, keys: Vec<String>) -> Result<Vec<LoginAttempt>, RepositoryError> {
+        let rows = sqlx::query_as::<_, LoginAttempt>(
+            "SELECT key, failed_attempts, blocked_until::TIMESTAMPTZ FROM login_attempts WHERE key = ANY($1)",
+        )
+        .bind(keys)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    async fn start_attempt(
+        &self,
+        key: String,
+        threshold: i32,
+        window_start: DateTime<Utc>,
+        hold_until: DateTime<Utc>,
+    ) -> Result<Option<LoginAttempt>, RepositoryError> {
+        let row = sqlx::query_as::<_, LoginAttempt>(
+            "INSERT INTO login_attempts (key, failed_attempts, blocked_until, last_failure)
+            VALUES ($1, 1, CASE WHEN $3 <= 1 THEN $5 END, $2)
+            ON CONFLICT (key) DO UPDATE SET
+                failed_attempts = CASE WHEN login_attempts.last_failure < $4 THEN 1
+                    ELSE login_attempts.failed_attempts + 1 END,
+                blocked_until = CASE WHEN login_attempts.last_failure >= $4
+                    AND login_attempts.failed_attempts + 1 >= $3 THEN $5 END,
+                last_failure = $2
+            WHERE login_attempts.blocked_until IS NULL OR login_attempts.blocked_until <= $2
+            RETURNING key, failed_attempts, blocked_until::TIMESTAMPTZ",
+        )
+        .bind(key)
+        .bind(Utc::now())
+        .bind(threshold)
+        .bind(window_start)
+        .bind(hold_until)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn take_back_attempt(&self, key: String) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "UPDATE login_attempts SET failed_attempts = GREATEST(failed_attempts - 1, 0), blocked_until = NULL
+            WHERE key = $1",
+        )
+        .bind(key)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_blocked_until(
+        &self,
+        key: String,
+        blocked_until: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query("UPDATE login_attempts SET blocked_until = $1 WHERE key = $2")
+            .bind(blocked_until)
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_attempts(&self, key: String) -> Result<(), RepositoryError> {
+        sqlx::query("DELETE FROM login_attempts WHERE key = $1")
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
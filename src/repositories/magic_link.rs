@@ -0,0 +1,78 @@
+use crate::domain::magic_link::{MagicLinkRepository, MagicLinkToken};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error::RepositoryError;
+
+pub struct SqlMagicLinkRepository {
+    pub pool: PgPool,
+}
+
+#[async_trait::async_trait]
+impl MagicLinkRepository for SqlMagicLinkRepository {
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        token_hash: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM magic_link_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut transaction)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO magic_link_tokens (id, user_id, token_hash, expires_at, creation_time)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(expires_at)
+        .bind(Utc::now())
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn get_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<MagicLinkToken>, RepositoryError> {
+        let row = sqlx::query_as::<_, MagicLinkToken>(
+            "SELECT id, user_id, expires_at::TIMESTAMPTZ, creation_time::TIMESTAMPTZ
+            FROM magic_link_tokens WHERE token_hash = $1",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn get_token_by_user(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<MagicLinkToken>, RepositoryError> {
+        let row = sqlx::query_as::<_, MagicLinkToken>(
+            "SELECT id, user_id, expires_at::TIMESTAMPTZ, creation_time::TIMESTAMPTZ
+            FROM magic_link_tokens WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn delete_token(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        let result = sqlx::query("DELETE FROM magic_link_tokens WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+}
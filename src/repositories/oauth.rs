@@ -0,0 +1,193 @@
+use crate::domain::{
+    oauth::{
+        payload::NewOAuthClientPayload, OAuthAuthorizationCode, OAuthClient, OAuthGrant,
+        OAuthRepository,
+    },
+    personal_access_token::Scope,
+};
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error::RepositoryError;
+
+pub struct SqlOAuthRepository {
+    pub pool: PgPool,
+}
+
+#[derive(sqlx::FromRow)]
+struct GrantId {
+    id: Uuid,
+}
+
+#[async_trait::async_trait]
+impl OAuthRepository for SqlOAuthRepository {
+    async fn create_client(
+        &self,
+        owner_id: Uuid,
+        payload: NewOAuthClientPayload,
+        secret_hash: Option<String>,
+    ) -> Result<OAuthClient, RepositoryError> {
+        let row = sqlx::query_as::<_, OAuthClient>(
+            "INSERT INTO oauth_clients (id, owner_id, name, redirect_uris, scopes, secret_hash)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, owner_id, name, redirect_uris, scopes, secret_hash IS NOT NULL AS confidential,
+            secret_hash, creation_time::TIMESTAMPTZ",
+        )
+        .bind(Uuid::new_v4())
+        .bind(owner_id)
+        .bind(payload.name)
+        .bind(payload.redirect_uris)
+        .bind(payload.scopes)
+        .bind(secret_hash)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn get_clients_by_owner(
+        &self,
+        owner_id: Uuid,
+    ) -> Result<Vec<OAuthClient>, RepositoryError> {
+        let rows = sqlx::query_as::<_, OAuthClient>(
+            "SELECT id, owner_id, name, redirect_uris, scopes, secret_hash IS NOT NULL AS confidential,
+            secret_hash, creation_time::TIMESTAMPTZ FROM oauth_clients WHERE owner_id = $1 ORDER BY creation_time",
+        )
+        .bind(owner_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    async fn get_client(&self, id: Uuid) -> Result<Option<OAuthClient>, RepositoryError> {
+        let row = sqlx::query_as::<_, OAuthClient>(
+            "SELECT id, owner_id, name, redirect_uris, scopes, secret_hash IS NOT NULL AS confidential,
+            secret_hash, creation_time::TIMESTAMPTZ FROM oauth_clients WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn delete_client(&self, owner_id: Uuid, id: Uuid) -> Result<bool, RepositoryError> {
+        let result = sqlx::query("DELETE FROM oauth_clients WHERE id = $1 AND owner_id = $2")
+            .bind(id)
+            .bind(owner_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn create_authorization_code(
+        &self,
+        code_hash: String,
+        authorization_code: OAuthAuthorizationCode,
+    ) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+
+        // Codes the client never redeemed would otherwise pile up.
+        sqlx::query("DELETE FROM oauth_authorization_codes WHERE expires_at <= $1")
+            .bind(Utc::now())
+            .execute(&mut transaction)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO oauth_authorization_codes
+            (id, code_hash, client_id, user_id, redirect_uri, scopes, code_challenge, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(code_hash)
+        .bind(authorization_code.client_id)
+        .bind(authorization_code.user_id)
+        .bind(authorization_code.redirect_uri)
+        .bind(authorization_code.scopes)
+        .bind(authorization_code.code_challenge)
+        .bind(authorization_code.expires_at)
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn take_authorization_code(
+        &self,
+        code_hash: String,
+    ) -> Result<Option<OAuthAuthorizationCode>, RepositoryError> {
+        let row = sqlx::query_as::<_, OAuthAuthorizationCode>(
+            "DELETE FROM oauth_authorization_codes WHERE code_hash = $1
+            RETURNING client_id, user_id, redirect_uri, scopes, code_challenge, expires_at::TIMESTAMPTZ",
+        )
+        .bind(code_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn save_grant(
+        &self,
+        user_id: Uuid,
+        client_id: Uuid,
+        scopes: Vec<Scope>,
+    ) -> Result<Uuid, RepositoryError> {
+        let row = sqlx::query_as::<_, GrantId>(
+            "INSERT INTO oauth_grants (id, user_id, client_id, scopes) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, client_id) DO UPDATE SET scopes = EXCLUDED.scopes, update_time = $5
+            RETURNING id",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(client_id)
+        .bind(scopes)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.id)
+    }
+
+    async fn get_grant(
+        &self,
+        user_id: Uuid,
+        client_id: Uuid,
+    ) -> Result<Option<OAuthGrant>, RepositoryError> {
+        let row = sqlx::query_as::<_, OAuthGrant>(
+            "SELECT g.id, g.client_id, c.name AS client_name, g.scopes, g.creation_time::TIMESTAMPTZ, g.update_time::TIMESTAMPTZ
+            FROM oauth_grants g JOIN oauth_clients c ON c.id = g.client_id
+            WHERE g.user_id = $1 AND g.client_id = $2",
+        )
+        .bind(user_id)
+        .bind(client_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn get_grants_by_user(&self, user_id: Uuid) -> Result<Vec<OAuthGrant>, RepositoryError> {
+        let rows = sqlx::query_as::<_, OAuthGrant>(
+            "SELECT g.id, g.client_id, c.name AS client_name, g.scopes, g.creation_time::TIMESTAMPTZ, g.update_time::TIMESTAMPTZ
+            FROM oauth_grants g JOIN oauth_clients c ON c.id = g.client_id
+            WHERE g.user_id = $1 ORDER BY g.creation_time",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    async fn delete_grant(
+        &self,
+        user_id: Uuid,
+        client_id: Uuid,
+    ) -> Result<Option<Uuid>, RepositoryError> {
+        let row = sqlx::query_as::<_, GrantId>(
+            "DELETE FROM oauth_grants WHERE user_id = $1 AND client_id = $2 RETURNING id",
+        )
+        .bind(user_id)
+        .bind(client_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|row| row.id))
+    }
+}
@@ -0,0 +1,91 @@
+use crate::domain::oidc::{ExternalIdentity, OidcLoginRequest, OidcRepository};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error::RepositoryError;
+
+pub struct SqlOidcRepository {
+    pub pool: PgPool,
+}
+
+#[async_trait::async_trait]
+impl OidcRepository for SqlOidcRepository {
+    async fn create_login_request(
+        &self,
+        state_hash: String,
+        nonce: String,
+        code_verifier: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+
+        // Most sign-ins are abandoned at the provider, nothing else would clean them up.
+        sqlx::query("DELETE FROM oidc_login_requests WHERE expires_at <= $1")
+            .bind(Utc::now())
+            .execute(&mut transaction)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO oidc_login_requests (id, state_hash, nonce, code_verifier, expires_at)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(state_hash)
+        .bind(nonce)
+        .bind(code_verifier)
+        .bind(expires_at)
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn take_login_request(
+        &self,
+        state_hash: String,
+    ) -> Result<Option<OidcLoginRequest>, RepositoryError> {
+        let row = sqlx::query_as::<_, OidcLoginRequest>(
+            "DELETE FROM oidc_login_requests WHERE state_hash = $1
+            RETURNING nonce, code_verifier, expires_at::TIMESTAMPTZ",
+        )
+        .bind(state_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn get_identity(
+        &self,
+        issuer: String,
+        subject: String,
+    ) -> Result<Option<ExternalIdentity>, RepositoryError> {
+        let row = sqlx::query_as::<_, ExternalIdentity>(
+            "SELECT user_id FROM external_identities WHERE issuer = $1 AND subject = $2",
+        )
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn create_identity(
+        &self,
+        user_id: Uuid,
+        issuer: String,
+        subject: String,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "INSERT INTO external_identities (id, user_id, issuer, subject) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(issuer)
+        .bind(subject)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
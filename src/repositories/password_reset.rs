@@ -0,0 +1,95 @@
+use crate::domain::{
+    password_reset::{PasswordResetRepository, PasswordResetToken},
+    user::password::hash_password,
+};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error::RepositoryError;
+
+pub struct SqlPasswordResetRepository {
+    pub pool: PgPool,
+}
+
+#[async_trait::async_trait]
+impl PasswordResetRepository for SqlPasswordResetRepository {
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        token_hash: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM password_reset_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut transaction)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO password_reset_tokens (id, user_id, token_hash, expires_at) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(expires_at)
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn get_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<PasswordResetToken>, RepositoryError> {
+        let row = sqlx::query_as::<_, PasswordResetToken>(
+            "SELECT id, user_id, expires_at::TIMESTAMPTZ, used_at::TIMESTAMPTZ
+            FROM password_reset_tokens WHERE token_hash = $1",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn reset_password(
+        &self,
+        token: PasswordResetToken,
+        password: String,
+    ) -> Result<bool, RepositoryError> {
+        // Hashing may be refused under load, which must not burn the token.
+        let hashed_password = hash_password(password).await?;
+        let now = Utc::now();
+        let mut transaction = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            "UPDATE password_reset_tokens SET used_at = $1 WHERE id = $2 AND used_at IS NULL",
+        )
+        .bind(now)
+        .bind(token.id)
+        .execute(&mut transaction)
+        .await?;
+        if result.rows_affected() != 1 {
+            return Ok(false);
+        }
+
+        sqlx::query("UPDATE users SET password = $1, update_time = $2 WHERE id = $3")
+            .bind(hashed_password)
+            .bind(now)
+            .bind(token.user_id)
+            .execute(&mut transaction)
+            .await?;
+
+        sqlx::query("DELETE FROM password_reset_tokens WHERE user_id = $1 AND id <> $2")
+            .bind(token.user_id)
+            .bind(token.id)
+            .execute(&mut transaction)
+            .await?;
+
+        transaction.commit().await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,95 @@
+use crate::domain::personal_access_token::{
+    payload::NewPersonalAccessTokenPayload, PersonalAccessToken, PersonalAccessTokenRepository,
+};
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error::RepositoryError;
+
+pub struct SqlPersonalAccessTokenRepository {
+    pub pool: PgPool,
+}
+
+#[async_trait::async_trait]
+impl PersonalAccessTokenRepository for SqlPersonalAccessTokenRepository {
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        payload: NewPersonalAccessTokenPayload,
+        token_prefix: String,
+        token_hash: String,
+    ) -> Result<PersonalAccessToken, RepositoryError> {
+        let row = sqlx::query_as::<_, PersonalAccessToken>(
+            "INSERT INTO personal_access_tokens (id, user_id, name, token_prefix, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, user_id, name, token_prefix, scopes, expires_at::TIMESTAMPTZ, last_used_at::TIMESTAMPTZ,
+            creation_time::TIMESTAMPTZ",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(payload.name)
+        .bind(token_prefix)
+        .bind(token_hash)
+        .bind(payload.scopes)
+        .bind(payload.expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn get_tokens_by_user(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<PersonalAccessToken>, RepositoryError> {
+        let rows = sqlx::query_as::<_, PersonalAccessToken>(
+            "SELECT id, user_id, name, token_prefix, scopes, expires_at::TIMESTAMPTZ, last_used_at::TIMESTAMPTZ,
+            creation_time::TIMESTAMPTZ FROM personal_access_tokens WHERE user_id = $1 ORDER BY creation_time",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    async fn get_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<PersonalAccessToken>, RepositoryError> {
+        let row = sqlx::query_as::<_, PersonalAccessToken>(
+            "SELECT id, user_id, name, token_prefix, scopes, expires_at::TIMESTAMPTZ, last_used_at::TIMESTAMPTZ,
+            creation_time::TIMESTAMPTZ FROM personal_access_tokens WHERE token_hash = $1",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn update_last_used(&self, id: Uuid) -> Result<(), RepositoryError> {
+        sqlx::query("UPDATE personal_access_tokens SET last_used_at = $1 WHERE id = $2")
+            .bind(Utc::now())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_token(&self, user_id: Uuid, id: Uuid) -> Result<bool, RepositoryError> {
+        let result =
+            sqlx::query("DELETE FROM personal_access_tokens WHERE id = $1 AND user_id = $2")
+                .bind(id)
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn delete_user_tokens(&self, user_id: Uuid) -> Result<(), RepositoryError> {
+        sqlx::query("DELETE FROM personal_access_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use crate::domain::refresh_token::{RefreshToken, RefreshTokenRepository};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error::RepositoryError;
+
+pub struct SqlRefreshTokenRepository {
+    pub pool: PgPool,
+}
+
+#[async_trait::async_trait]
+impl RefreshTokenRepository for SqlRefreshTokenRepository {
+    async fn create_token(
+        &self,
+        user_id: Uuid,
+        family_id: Uuid,
+        token_hash: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, expires_at) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(family_id)
+        .bind(token_hash)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<RefreshToken>, RepositoryError> {
+        let row = sqlx::query_as::<_, RefreshToken>(
+            "SELECT id, user_id, family_id, expires_at::TIMESTAMPTZ, used_at::TIMESTAMPTZ, revoked_at::TIMESTAMPTZ
+            FROM refresh_tokens WHERE token_hash = $1",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn mark_token_used(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        let result =
+            sqlx::query("UPDATE refresh_tokens SET used_at = $1 WHERE id = $2 AND used_at IS NULL")
+                .bind(Utc::now())
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn revoke_family(&self, family_id: Uuid) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = $1 WHERE family_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(family_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn revoke_user_tokens(&self, user_id: Uuid) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = $1 WHERE user_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
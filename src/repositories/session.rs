@@ -0,0 +1,121 @@
+use crate::domain::session::{Session, SessionRepository};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error::RepositoryError;
+
+pub struct SqlSessionRepository {
+    pub pool: PgPool,
+}
+
+#[async_trait::async_trait]
+impl SessionRepository for SqlSessionRepository {
+    async fn create_session(
+        &self,
+        user_id: Uuid,
+        user_agent: Option<String>,
+        ip: Option<String>,
+    ) -> Result<Session, RepositoryError> {
+        let now = Utc::now();
+        let row = sqlx::query_as::<_, Session>(
+            "INSERT INTO sessions (id, user_id, user_agent, ip, creation_time, last_used_at)
+            VALUES ($1, $2, $3, $4, $5, $5)
+            RETURNING id, user_id, user_agent, ip, creation_time::TIMESTAMPTZ, last_used_at::TIMESTAMPTZ",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(user_agent)
+        .bind(ip)
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn get_sessions_by_user(
+        &self,
+        user_id: Uuid,
+        active_since: DateTime<Utc>,
+    ) -> Result<Vec<Session>, RepositoryError> {
+        let rows = sqlx::query_as::<_, Session>(
+            "SELECT id, user_id, user_agent, ip, creation_time::TIMESTAMPTZ, last_used_at::TIMESTAMPTZ
+            FROM sessions WHERE user_id = $1 AND revoked_at IS NULL AND last_used_at > $2
+            ORDER BY last_used_at DESC",
+        )
+        .bind(user_id)
+        .bind(active_since)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    async fn update_last_used(&self, id: Uuid) -> Result<(), RepositoryError> {
+        sqlx::query("UPDATE sessions SET last_used_at = $1 WHERE id = $2")
+            .bind(Utc::now())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn revoke_session(&self, user_id: Uuid, id: Uuid) -> Result<bool, RepositoryError> {
+        let now = Utc::now();
+        let mut transaction = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            "UPDATE sessions SET revoked_at = $1 WHERE id = $2 AND user_id = $3 AND revoked_at IS NULL",
+        )
+        .bind(now)
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut transaction)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = $1 WHERE family_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(now)
+        .bind(id)
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    async fn revoke_user_sessions(
+        &self,
+        user_id: Uuid,
+        kept_id: Option<Uuid>,
+    ) -> Result<(), RepositoryError> {
+        let now = Utc::now();
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(
+            "UPDATE sessions SET revoked_at = $1
+            WHERE user_id = $2 AND revoked_at IS NULL AND id IS DISTINCT FROM $3",
+        )
+        .bind(now)
+        .bind(user_id)
+        .bind(kept_id)
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = $1
+            WHERE user_id = $2 AND revoked_at IS NULL AND family_id IS DISTINCT FROM $3",
+        )
+        .bind(now)
+        .bind(user_id)
+        .bind(kept_id)
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+}
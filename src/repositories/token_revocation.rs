@@ -0,0 +1,49 @@
+use crate::domain::token_revocation::{TokenRevocation, TokenRevocationRepository};
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error::RepositoryError;
+
+pub struct SqlTokenRevocationRepository {
+    pub pool: PgPool,
+}
+
+#[async_trait::async_trait]
+impl TokenRevocationRepository for SqlTokenRevocationRepository {
+    async fn create_revocation(&self, revocation: TokenRevocation) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "INSERT INTO token_revocations (id, jti, session_id, kept_jti, user_id, revocation_time, expires_at) VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (jti) DO NOTHING",
+        )
+        .bind(Uuid::new_v4())
+        .bind(revocation.jti)
+        .bind(revocation.session_id)
+        .bind(revocation.kept_jti)
+        .bind(revocation.user_id)
+        .bind(revocation.revocation_time)
+        .bind(revocation.expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_active_revocations(&self) -> Result<Vec<TokenRevocation>, RepositoryError> {
+        let rows = sqlx::query_as::<_, TokenRevocation>(
+            "SELECT jti, session_id, kept_jti, user_id, revocation_time::TIMESTAMPTZ, expires_at::TIMESTAMPTZ FROM token_revocations
+            WHERE expires_at > $1",
+        )
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    async fn delete_expired_revocations(&self) -> Result<(), RepositoryError> {
+        sqlx::query("DELETE FROM token_revocations WHERE expires_at <= $1")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
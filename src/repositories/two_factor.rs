@@ -0,0 +1,171 @@
+use crate::domain::two_factor::{TwoFactor, TwoFactorChallenge, TwoFactorRepository};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error::RepositoryError;
+
+pub struct SqlTwoFactorRepository {
+    pub pool: PgPool,
+}
+
+#[async_trait::async_trait]
+impl TwoFactorRepository for SqlTwoFactorRepository {
+    async fn get_two_factor(&self, user_id: Uuid) -> Result<Option<TwoFactor>, RepositoryError> {
+        let row = sqlx::query_as::<_, TwoFactor>(
+            "SELECT secret, enabled_at::TIMESTAMPTZ, last_used_step FROM two_factor WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn save_pending_two_factor(
+        &self,
+        user_id: Uuid,
+        secret: String,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(
+            "INSERT INTO two_factor (user_id, secret, creation_time) VALUES ($1, $2, $3)
+            ON CONFLICT (user_id) DO UPDATE SET secret = $2, creation_time = $3,
+                enabled_at = NULL, last_used_step = NULL",
+        )
+        .bind(user_id)
+        .bind(secret)
+        .bind(Utc::now())
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut transaction)
+            .await?;
+
+        for code_hash in recovery_code_hashes {
+            sqlx::query("INSERT INTO recovery_codes (id, user_id, code_hash) VALUES ($1, $2, $3)")
+                .bind(Uuid::new_v4())
+                .bind(user_id)
+                .bind(code_hash)
+                .execute(&mut transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn enable_two_factor(&self, user_id: Uuid, step: i64) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "UPDATE two_factor SET enabled_at = $1, last_used_step = $2 WHERE user_id = $3",
+        )
+        .bind(Utc::now())
+        .bind(step)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_last_used_step(
+        &self,
+        user_id: Uuid,
+        step: i64,
+    ) -> Result<bool, RepositoryError> {
+        let result = sqlx::query(
+            "UPDATE two_factor SET last_used_step = $1
+            WHERE user_id = $2 AND (last_used_step IS NULL OR last_used_step < $1)",
+        )
+        .bind(step)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn delete_two_factor(&self, user_id: Uuid) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM two_factor WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut transaction)
+            .await?;
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut transaction)
+            .await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_id: Uuid,
+        code_hash: String,
+    ) -> Result<bool, RepositoryError> {
+        let result = sqlx::query(
+            "UPDATE recovery_codes SET used_at = $1
+            WHERE user_id = $2 AND code_hash = $3 AND used_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(user_id)
+        .bind(code_hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn create_challenge(
+        &self,
+        user_id: Uuid,
+        token_hash: String,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "INSERT INTO two_factor_challenges (id, user_id, token_hash, expires_at) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_challenge_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<TwoFactorChallenge>, RepositoryError> {
+        let row = sqlx::query_as::<_, TwoFactorChallenge>(
+            "SELECT id, user_id, expires_at::TIMESTAMPTZ, failed_attempts
+            FROM two_factor_challenges WHERE token_hash = $1",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn increment_challenge_failures(&self, id: Uuid) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "UPDATE two_factor_challenges SET failed_attempts = failed_attempts + 1 WHERE id = $1",
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_challenge(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        let result = sqlx::query("DELETE FROM two_factor_challenges WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+}
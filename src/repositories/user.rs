@@ -1,7 +1,7 @@
 use crate::domain::user::{
-    password::{hash_password, verify_passwords},
-    payload::{LoginUserPayload, NewUserPayload, UpdateUserPayload},
-    PublicUser, UserRepository,
+    password::hash_password,
+    payload::{NewUserPayload, UpdateUserPayload},
+    PublicUser, Role, User, UserRepository,
 };
 use chrono::Utc;
 use sqlx::{PgPool, Postgres, QueryBuilder};
@@ -22,10 +22,10 @@ struct UserPassword {
 impl UserRepository for SqlUserRepository {
     async fn create_user(&self, user: NewUserPayload) -> Result<PublicUser, RepositoryError> {
         let uuid = Uuid::new_v4();
-        let hashed_password = hash_password(user.password)?;
+        let hashed_password = hash_password(user.password).await?;
         let row = sqlx::query_as::<_, PublicUser>(
             "INSERT INTO users (id, name, nickname, email, password, bio) VALUES ($1, $2, $3, $4, $5, $6)
-            RETURNING id, name, nickname, email, bio, creation_time::TIMESTAMPTZ",
+            RETURNING id, name, nickname, email, bio, role, email_verified_at::TIMESTAMPTZ, creation_time::TIMESTAMPTZ",
         )
         .bind(uuid)
         .bind(user.name)
@@ -45,12 +45,22 @@ impl UserRepository for SqlUserRepository {
         Ok(())
     }
 
+    async fn update_user_role(&self, id: Uuid, role: Role) -> Result<(), RepositoryError> {
+        sqlx::query("UPDATE users SET role = $1, update_time = $2 WHERE id = $3")
+            .bind(role)
+            .bind(Utc::now())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     async fn get_user_by_nickname(
         &self,
         nickname: String,
     ) -> Result<Option<PublicUser>, RepositoryError> {
         let row = sqlx::query_as::<_, PublicUser>(
-            "SELECT id, name, nickname, email, bio, creation_time::TIMESTAMPTZ FROM users WHERE nickname = $1",
+            "SELECT id, name, nickname, email, bio, role, email_verified_at::TIMESTAMPTZ, creation_time::TIMESTAMPTZ FROM users WHERE nickname = $1",
         )
         .bind(nickname)
         .fetch_optional(&self.pool)
@@ -60,7 +70,7 @@ impl UserRepository for SqlUserRepository {
 
     async fn get_user_by_id(&self, id: Uuid) -> Result<Option<PublicUser>, RepositoryError> {
         let row = sqlx::query_as::<_, PublicUser>(
-            "SELECT id, name, nickname, email, bio, creation_time::TIMESTAMPTZ FROM users WHERE id = $1",
+            "SELECT id, name, nickname, email, bio, role, email_verified_at::TIMESTAMPTZ, creation_time::TIMESTAMPTZ FROM users WHERE id = $1",
         )
         .bind(id)
         .fetch_optional(&self.pool)
@@ -73,7 +83,7 @@ impl UserRepository for SqlUserRepository {
         email: String,
     ) -> Result<Option<PublicUser>, RepositoryError> {
         let row = sqlx::query_as::<_, PublicUser>(
-            "SELECT id, name, nickname, email, bio, creation_time::TIMESTAMPTZ FROM users WHERE email = $1",
+            "SELECT id, name, nickname, email, bio, role, email_verified_at::TIMESTAMPTZ, creation_time::TIMESTAMPTZ FROM users WHERE email = $1",
         )
         .bind(email)
         .fetch_optional(&self.pool)
@@ -89,30 +99,65 @@ impl UserRepository for SqlUserRepository {
         Ok(())
     }
 
-    async fn get_user_by_login(
+    async fn get_user_with_password_by_email(
         &self,
-        login_payload: LoginUserPayload,
-    ) -> Result<PublicUser, RepositoryError> {
-        let email = login_payload.email;
-        let payload_password = login_payload.password;
-
-        let row = sqlx::query_as::<_, UserPassword>("SELECT password FROM users WHERE email = $1")
-            .bind(email.clone())
-            .fetch_one(&self.pool)
-            .await?;
+        email: String,
+    ) -> Result<Option<User>, RepositoryError> {
+        let row = sqlx::query_as::<_, User>(
+            "SELECT id, name, nickname, email, password, bio, role, email_verified_at::TIMESTAMPTZ, creation_time::TIMESTAMPTZ, update_time::TIMESTAMPTZ FROM users WHERE email = $1",
+        )
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?;
 
-        let hashed_password = row.password;
+        Ok(row)
+    }
 
-        verify_passwords(payload_password, hashed_password)?;
+    async fn upgrade_password(
+        &self,
+        id: Uuid,
+        current_hash: String,
+        password: String,
+    ) -> Result<(), RepositoryError> {
+        let hashed_password = hash_password(password).await?;
+        // Matching on the current hash keeps a concurrent password change from being undone.
+        sqlx::query("UPDATE users SET password = $1 WHERE id = $2 AND password = $3")
+            .bind(hashed_password)
+            .bind(id)
+            .bind(current_hash)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
 
-        let row = sqlx::query_as::<_, PublicUser>(
-            "SELECT id, name, nickname, email, bio, creation_time::TIMESTAMPTZ FROM users WHERE email = $1",
+    async fn update_password(&self, id: Uuid, password: String) -> Result<(), RepositoryError> {
+        let hashed_password = hash_password(password).await?;
+        sqlx::query("UPDATE users SET password = $1, update_time = $2 WHERE id = $3")
+            .bind(hashed_password)
+            .bind(Utc::now())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn mark_email_verified(&self, id: Uuid) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "UPDATE users SET email_verified_at = $1 WHERE id = $2 AND email_verified_at IS NULL",
         )
-        .bind(email)
-        .fetch_one(&self.pool)
+        .bind(Utc::now())
+        .bind(id)
+        .execute(&self.pool)
         .await?;
+        Ok(())
+    }
 
-        Ok(row)
+    async fn get_password_hash(&self, id: Uuid) -> Result<Option<String>, RepositoryError> {
+        let row = sqlx::query_as::<_, UserPassword>("SELECT password FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| row.password))
     }
 }
 
@@ -1,2 +1,19 @@
 pub mod user;
 pub mod auth;
+pub mod well_known;
+pub mod personal_access_token;
+pub mod two_factor;
+pub mod metrics;
+pub mod session;
+pub mod oauth;
+
+use actix_web::{web, HttpRequest};
+
+use crate::error::AppError;
+
+/// Looks up a handler for the extractors grouping several of them.
+fn app_data<T: ?Sized + 'static>(req: &HttpRequest) -> Result<web::Data<T>, AppError> {
+    req.app_data::<web::Data<T>>().cloned().ok_or_else(|| {
+        AppError::internal(format!("{} is not configured", std::any::type_name::<T>()))
+    })
+}
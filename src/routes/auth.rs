@@ -1,43 +1,667 @@
 use actix_web::{
+    dev::Payload,
+    http::header,
     web::{self, ServiceConfig},
-    HttpResponse,
+    FromRequest, HttpRequest, HttpResponse,
 };
+use futures_util::future::{ready, Ready};
 use serde::Serialize;
+use uuid::Uuid;
 use validator::Validate;
 
 use crate::{
-    auth::create_jwt,
-    domain::user::{payload::LoginUserPayload, validation::format_error_msg},
+    auth::{
+        cookie::{check_csrf, CookieSettings, TokenDelivery, REFRESH_TOKEN_COOKIE},
+        create_jwt,
+        keys::JwtKeys,
+        AuthenticatedUser, Credential, TWO_FACTOR_CHALLENGE_LIFETIME_SECONDS,
+    },
+    domain::{
+        email_change::payload::EmailChangeConfirmPayload,
+        email_verification::{
+            payload::{ResendVerificationPayload, VerifyEmailPayload},
+            EmailVerificationPolicy,
+        },
+        magic_link::payload::{MagicLinkConsumePayload, MagicLinkRequestPayload},
+        oidc::payload::OidcCallbackPayload,
+        password_reset::payload::{PasswordResetConfirmPayload, PasswordResetRequestPayload},
+        refresh_token::payload::RefreshTokenPayload,
+        two_factor::payload::TwoFactorLoginPayload,
+        user::{payload::LoginUserPayload, PublicUser},
+    },
     error::AppError,
-    handlers::user::DynUserHandler,
+    handlers::{
+        email_change::DynEmailChangeHandler, email_verification::DynEmailVerificationHandler,
+        login_attempt::{throttled, DynLoginAttemptHandler},
+        magic_link::DynMagicLinkHandler,
+        oidc::DynOidcHandler, password_reset::DynPasswordResetHandler,
+        refresh_token::DynRefreshTokenHandler, session::DynSessionHandler,
+        token_revocation::DynTokenRevocationHandler, two_factor::DynTwoFactorHandler,
+        user::DynUserHandler,
+    },
+    repositories::error::RepositoryError,
+    response::GenericResponse,
+    routes::{app_data, session::SignOut},
+    utils::generate_token,
 };
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 struct AuthResponse {
     token: String,
+    refresh_token: String,
+}
+
+/// Sent instead of [`AuthResponse`] when the tokens are set as cookies. The CSRF token is
+/// also in a cookie, repeated here for clients that would rather keep it in memory.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CookieAuthResponse {
+    csrf_token: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OidcAuthorizationResponse {
+    authorization_url: String,
+}
+
+/// Sent by the login route instead of [`AuthResponse`] to users with 2FA enabled.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFactorChallengeResponse {
+    challenge_token: String,
+    expires_in: i64,
 }
 
 pub(crate) fn auth_routes(cfg: &mut ServiceConfig) {
-    cfg.service(web::scope("/auth").route("/", web::post().to(login_user)));
+    cfg.service(
+        web::scope("/auth")
+            .route("/", web::post().to(login_user))
+            .route("/2fa", web::post().to(login_two_factor))
+            .route("/verify-email", web::post().to(verify_email))
+            .route(
+                "/verify-email/resend",
+                web::post().to(resend_verification_email),
+            )
+            .route("/password-reset", web::post().to(request_password_reset))
+            .route(
+                "/password-reset/confirm",
+                web::post().to(confirm_password_reset),
+            )
+            .route(
+                "/email-change/confirm",
+                web::post().to(confirm_email_change),
+            )
+            .route("/magic-link", web::post().to(request_magic_link))
+            .route("/magic-link/consume", web::post().to(consume_magic_link))
+            .route("/oidc/authorize", web::post().to(start_oidc_login))
+            .route("/oidc/callback", web::post().to(complete_oidc_login))
+            .route("/refresh", web::post().to(refresh_token))
+            .route("/logout", web::post().to(logout_user)),
+    );
 }
 
+/// What signing a user in takes, gathered into one extractor for the routes that do.
+struct SignIn {
+    session_handler: web::Data<DynSessionHandler>,
+    refresh_token_handler: web::Data<DynRefreshTokenHandler>,
+    two_factor_handler: web::Data<DynTwoFactorHandler>,
+    login_attempt_handler: web::Data<DynLoginAttemptHandler>,
+    email_verification_policy: web::Data<EmailVerificationPolicy>,
+    keys: web::Data<JwtKeys>,
+}
+
+impl FromRequest for SignIn {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(SignIn::from_app_data(req))
+    }
+}
+
+impl SignIn {
+    fn from_app_data(req: &HttpRequest) -> Result<SignIn, AppError> {
+        Ok(SignIn {
+            session_handler: app_data(req)?,
+            refresh_token_handler: app_data(req)?,
+            two_factor_handler: app_data(req)?,
+            login_attempt_handler: app_data(req)?,
+            email_verification_policy: app_data(req)?,
+            keys: app_data(req)?,
+        })
+    }
+
+    /// Hands out tokens to a user who just proved who they are, or a challenge if they
+    /// still have to pass 2FA. Unverified emails are refused here under
+    /// [`EmailVerificationPolicy::BlockLogin`], whichever way the user signed in.
+    async fn sign_in(
+        &self,
+        req: &HttpRequest,
+        delivery: &TokenDelivery,
+        user: &PublicUser,
+    ) -> Result<HttpResponse, AppError> {
+        if *self.email_verification_policy.get_ref() == EmailVerificationPolicy::BlockLogin
+            && user.email_verified_at.is_none()
+        {
+            return Err(AppError::forbidden(
+                "Verify your email address before signing in".to_string(),
+            ));
+        }
+
+        if self.two_factor_handler.is_enabled(user.id).await? {
+            let challenge_token = self.two_factor_handler.create_challenge(user.id).await?;
+            return Ok(HttpResponse::Ok().json(TwoFactorChallengeResponse {
+                challenge_token,
+                expires_in: TWO_FACTOR_CHALLENGE_LIFETIME_SECONDS,
+            }));
+        }
+
+        self.start_session(req, delivery, user).await
+    }
+
+    /// Records the device signing in as a new session and issues its first tokens. Like
+    /// for login attempts, the peer address is used rather than `X-Forwarded-For`.
+    async fn start_session(
+        &self,
+        req: &HttpRequest,
+        delivery: &TokenDelivery,
+        user: &PublicUser,
+    ) -> Result<HttpResponse, AppError> {
+        self.login_attempt_handler
+            .record_success(user.email.clone())
+            .await?;
+
+        let user_agent = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+
+        let session = self
+            .session_handler
+            .create_session(user.id, user_agent, ip)
+            .await?;
+        let refresh_token = self
+            .refresh_token_handler
+            .issue_token(user.id, session.id)
+            .await?;
+
+        auth_response(user, session.id, refresh_token, delivery, &self.keys)
+    }
+}
+
+/// Attempts are counted per account and per peer address before the password is checked,
+/// and either being held back answers 429. `X-Forwarded-For` is ignored since any client
+/// could set it to dodge the address counter.
 async fn login_user(
+    req: HttpRequest,
+    delivery: TokenDelivery,
     body: web::Json<LoginUserPayload>,
     handler: web::Data<DynUserHandler>,
+    sign_in: SignIn,
 ) -> Result<HttpResponse, AppError> {
     let payload = body.into_inner();
 
-    if let Err(e) = payload.validate() {
-        return Err(AppError::bad_request(format_error_msg(e.field_errors())));
-    }
+    payload.validate()?;
+
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    let user = throttled(
+        sign_in.login_attempt_handler.get_ref(),
+        payload.email.clone(),
+        ip,
+        handler.get_user_by_login(payload),
+    )
+    .await?;
+
+    sign_in.sign_in(&req, &delivery, &user).await
+}
+
+/// Second step of the login for users with 2FA enabled, exchanging the challenge token
+/// and a TOTP or recovery code for the same tokens [`login_user`] hands out otherwise.
+/// Wrong codes count against the account like wrong passwords do.
+async fn login_two_factor(
+    req: HttpRequest,
+    delivery: TokenDelivery,
+    body: web::Json<TwoFactorLoginPayload>,
+    handler: web::Data<DynUserHandler>,
+    sign_in: SignIn,
+) -> Result<HttpResponse, AppError> {
+    let payload = body.into_inner();
 
-    let user_uuid = handler.get_user_by_login(payload).await?;
+    payload.validate()?;
 
-    // TODO: refactor, im not sure if this logic should be at this layer
-    let jwt_user = create_jwt(user_uuid).map_err(|_| AppError {
-        message: "Internal Error".to_string(),
-        r#type: crate::error::ErrorType::InternalError,
+    let user_uuid = sign_in
+        .two_factor_handler
+        .challenge_user(payload.challenge_token.clone())
+        .await?;
+    let user = handler.get_user_by_id(user_uuid).await?.ok_or_else(|| {
+        AppError::unauthorized("Invalid or expired two-factor challenge".to_string())
     })?;
 
-    Ok(HttpResponse::Ok().json(AuthResponse { token: jwt_user }))
+    throttled(
+        sign_in.login_attempt_handler.get_ref(),
+        user.email.clone(),
+        req.peer_addr().map(|addr| addr.ip()),
+        sign_in
+            .two_factor_handler
+            .complete_challenge(payload.challenge_token, payload.code),
+    )
+    .await?;
+
+    sign_in.start_session(&req, &delivery, &user).await
+}
+
+/// Browser clients in cookie mode send their refresh token in its cookie instead of the
+/// body, along with the CSRF token since the route changes state.
+async fn refresh_token(
+    req: HttpRequest,
+    delivery: TokenDelivery,
+    body: Option<web::Json<RefreshTokenPayload>>,
+    handler: web::Data<DynUserHandler>,
+    session_handler: web::Data<DynSessionHandler>,
+    refresh_token_handler: web::Data<DynRefreshTokenHandler>,
+    keys: web::Data<JwtKeys>,
+) -> Result<HttpResponse, AppError> {
+    let presented_token = match &delivery {
+        TokenDelivery::Body => {
+            let payload = body
+                .ok_or_else(|| AppError::bad_request("Missing refresh token".to_string()))?
+                .into_inner();
+            payload.validate()?;
+            payload.refresh_token
+        }
+        TokenDelivery::Cookie(_) => {
+            check_csrf(&req)?;
+            req.cookie(REFRESH_TOKEN_COOKIE)
+                .map(|cookie| cookie.value().to_string())
+                .ok_or_else(|| AppError::unauthorized("Invalid refresh token".to_string()))?
+        }
+    };
+
+    let (rotated_token, refresh_token) =
+        refresh_token_handler.rotate_token(presented_token).await?;
+
+    // Look the user up again so role changes apply from the next refresh on.
+    let user = handler
+        .get_user_by_id(rotated_token.user_id)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Invalid refresh token".to_string()))?;
+
+    let session_id = rotated_token.family_id;
+    session_handler.touch_session(session_id).await?;
+
+    auth_response(&user, session_id, refresh_token, &delivery, &keys)
+}
+
+/// Ends the session the token was issued in. Tokens issued before sessions existed carry
+/// none, so the refresh token to revoke can still be passed in the body for them. OAuth
+/// access tokens only revoke themselves: their `sid` is the grant of the user, which
+/// outlives them and is revoked from the grants routes.
+/// Browser clients also get their cookies cleared.
+async fn logout_user(
+    user: AuthenticatedUser,
+    body: Option<web::Json<RefreshTokenPayload>>,
+    cookie_settings: web::Data<CookieSettings>,
+    session_handler: web::Data<DynSessionHandler>,
+    refresh_token_handler: web::Data<DynRefreshTokenHandler>,
+    revocation_handler: web::Data<DynTokenRevocationHandler>,
+) -> Result<HttpResponse, AppError> {
+    let (claims, session_id) = match user.credential {
+        Credential::Jwt(claims) => {
+            let session_id = claims.sid;
+            (claims, session_id)
+        }
+        Credential::OAuth { claims, .. } => (claims, None),
+        Credential::PersonalAccessToken { .. } => {
+            return Err(AppError::bad_request(
+                "Personal access tokens are revoked from the tokens routes".to_string(),
+            ))
+        }
+    };
+
+    if let Some(session_id) = session_id {
+        match session_handler.revoke_session(claims.sub, session_id).await {
+            Ok(()) | Err(RepositoryError::NotFound) => {}
+            Err(error) => return Err(error.into()),
+        }
+        revocation_handler
+            .revoke_session_tokens(claims.sub, session_id)
+            .await?;
+    }
+
+    revocation_handler
+        .revoke_token(claims.jti, claims.sub, claims.expires_at())
+        .await?;
+
+    if let Some(body) = body {
+        refresh_token_handler
+            .revoke_token(claims.sub, body.into_inner().refresh_token)
+            .await?;
+    }
+
+    let mut response = HttpResponse::Ok();
+    for cookie in cookie_settings.removal_cookies() {
+        response.cookie(cookie);
+    }
+    Ok(response.finish())
+}
+
+async fn verify_email(
+    body: web::Json<VerifyEmailPayload>,
+    email_verification_handler: web::Data<DynEmailVerificationHandler>,
+) -> Result<HttpResponse, AppError> {
+    let payload = body.into_inner();
+
+    payload.validate()?;
+
+    email_verification_handler
+        .verify_email(payload.token)
+        .await?;
+
+    Ok(HttpResponse::Ok().into())
+}
+
+/// Takes an email rather than a token since unverified users may not be able to sign in.
+/// Answers the same whatever happened, see
+/// [`EmailVerificationHandler::resend_verification`](crate::handlers::email_verification::EmailVerificationHandler::resend_verification).
+async fn resend_verification_email(
+    body: web::Json<ResendVerificationPayload>,
+    email_verification_handler: web::Data<DynEmailVerificationHandler>,
+) -> Result<HttpResponse, AppError> {
+    let payload = body.into_inner();
+
+    payload.validate()?;
+
+    email_verification_handler
+        .resend_verification(payload.email)
+        .await?;
+
+    Ok(HttpResponse::Accepted().json(GenericResponse {
+        status: 202,
+        message: "If this email needs to be verified, a new link has been sent to it".to_string(),
+    }))
+}
+
+/// Answers the same whether or not an account uses the email, so the route cannot be
+/// used to find out who is registered.
+async fn request_password_reset(
+    body: web::Json<PasswordResetRequestPayload>,
+    password_reset_handler: web::Data<DynPasswordResetHandler>,
+) -> Result<HttpResponse, AppError> {
+    let payload = body.into_inner();
+
+    payload.validate()?;
+
+    password_reset_handler.request_reset(payload.email).await?;
+
+    Ok(HttpResponse::Accepted().json(GenericResponse {
+        status: 202,
+        message: "If an account uses this email, a password reset link has been sent to it"
+            .to_string(),
+    }))
+}
+
+/// Sets the new password and signs the user out everywhere, in case the reset was
+/// prompted by someone else having access to the account.
+async fn confirm_password_reset(
+    body: web::Json<PasswordResetConfirmPayload>,
+    password_reset_handler: web::Data<DynPasswordResetHandler>,
+    sign_out: SignOut,
+) -> Result<HttpResponse, AppError> {
+    let payload = body.into_inner();
+
+    payload.validate()?;
+
+    let user_uuid = password_reset_handler
+        .confirm_reset(payload.token, payload.password)
+        .await?;
+
+    sign_out.everywhere(user_uuid).await?;
+
+    Ok(HttpResponse::Ok().into())
+}
+
+/// Public since the link is opened from the new mailbox, possibly signed out.
+async fn confirm_email_change(
+    body: web::Json<EmailChangeConfirmPayload>,
+    email_change_handler: web::Data<DynEmailChangeHandler>,
+) -> Result<HttpResponse, AppError> {
+    let payload = body.into_inner();
+
+    payload.validate()?;
+
+    email_change_handler.confirm_change(payload.token).await?;
+
+    Ok(HttpResponse::Ok().into())
+}
+
+/// Answers the same whether or not an account uses the email, like
+/// [`request_password_reset`].
+async fn request_magic_link(
+    body: web::Json<MagicLinkRequestPayload>,
+    magic_link_handler: web::Data<DynMagicLinkHandler>,
+) -> Result<HttpResponse, AppError> {
+    let payload = body.into_inner();
+
+    payload.validate()?;
+
+    magic_link_handler.request_link(payload.email).await?;
+
+    Ok(HttpResponse::Accepted().json(GenericResponse {
+        status: 202,
+        message: "If an account uses this email, a sign-in link has been sent to it".to_string(),
+    }))
+}
+
+/// Signs in like [`login_user`] does, 2FA included, with the link standing in for the
+/// password.
+async fn consume_magic_link(
+    req: HttpRequest,
+    delivery: TokenDelivery,
+    body: web::Json<MagicLinkConsumePayload>,
+    handler: web::Data<DynUserHandler>,
+    magic_link_handler: web::Data<DynMagicLinkHandler>,
+    sign_in: SignIn,
+) -> Result<HttpResponse, AppError> {
+    let payload = body.into_inner();
+
+    payload.validate()?;
+
+    let user_uuid = magic_link_handler.consume_link(payload.token).await?;
+
+    let user = handler
+        .get_user_by_id(user_uuid)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Invalid or expired sign-in link".to_string()))?;
+
+    sign_in.sign_in(&req, &delivery, &user).await
+}
+
+/// The OpenID Connect handler is only registered when a provider is configured.
+fn oidc_enabled(
+    oidc_handler: Option<web::Data<DynOidcHandler>>,
+) -> Result<web::Data<DynOidcHandler>, AppError> {
+    oidc_handler
+        .ok_or_else(|| AppError::not_found("OpenID Connect sign-in is not enabled".to_string()))
+}
+
+/// Returns the URL of the identity provider the client should send the user to.
+async fn start_oidc_login(
+    oidc_handler: Option<web::Data<DynOidcHandler>>,
+) -> Result<HttpResponse, AppError> {
+    let authorization_url = oidc_enabled(oidc_handler)?.start_login().await?;
+
+    Ok(HttpResponse::Ok().json(OidcAuthorizationResponse { authorization_url }))
+}
+
+/// Signs in like [`login_user`] does, 2FA included, with the code the identity provider
+/// redirected back with standing in for the password.
+async fn complete_oidc_login(
+    req: HttpRequest,
+    delivery: TokenDelivery,
+    body: web::Json<OidcCallbackPayload>,
+    handler: web::Data<DynUserHandler>,
+    oidc_handler: Option<web::Data<DynOidcHandler>>,
+    sign_in: SignIn,
+) -> Result<HttpResponse, AppError> {
+    let oidc_handler = oidc_enabled(oidc_handler)?;
+    let payload = body.into_inner();
+
+    payload.validate()?;
+
+    let user_uuid = oidc_handler
+        .complete_login(payload.code, payload.state)
+        .await?;
+
+    let user = handler
+        .get_user_by_id(user_uuid)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Invalid or expired sign-in request".to_string()))?;
+
+    sign_in.sign_in(&req, &delivery, &user).await
+}
+
+// TODO: refactor, im not sure if this logic should be at this layer
+fn auth_response(
+    user: &PublicUser,
+    session_id: Uuid,
+    refresh_token: String,
+    delivery: &TokenDelivery,
+    keys: &JwtKeys,
+) -> Result<HttpResponse, AppError> {
+    let jwt_user = create_jwt(user.id, user.role, Some(session_id), keys)
+        .map_err(|_| AppError::internal("Internal Error".to_string()))?;
+
+    match delivery {
+        TokenDelivery::Body => Ok(HttpResponse::Ok().json(AuthResponse {
+            token: jwt_user,
+            refresh_token,
+        })),
+        TokenDelivery::Cookie(cookie_settings) => {
+            let csrf_token = generate_token();
+            let mut response = HttpResponse::Ok();
+            for cookie in cookie_settings.session_cookies(
+                jwt_user,
+                keys.settings().access_token_lifetime,
+                refresh_token,
+                csrf_token.clone(),
+            ) {
+                response.cookie(cookie);
+            }
+            Ok(response.json(CookieAuthResponse { csrf_token }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use actix_web::{test, App};
+
+    use super::*;
+    use crate::{
+        auth::{create_oauth_jwt, decode_jwt},
+        domain::{personal_access_token::Scope, user::mocks::*},
+        handlers::{
+            login_attempt::MockLoginAttemptHandler, oidc::MockOidcHandler,
+            refresh_token::MockRefreshTokenHandler, session::MockSessionHandler,
+            token_revocation::MockTokenRevocationHandler, two_factor::MockTwoFactorHandler,
+            user::MockUserHandler,
+        },
+    };
+
+    #[actix_web::test]
+    async fn refuses_unverified_email_on_oidc_sign_in() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser, email_verified_at: None))));
+        let mut oidc_handler = MockOidcHandler::new();
+        oidc_handler
+            .expect_complete_login()
+            .returning(|_, _| Ok(Uuid::new_v4()));
+        let mut session_handler = MockSessionHandler::new();
+        session_handler.expect_create_session().never();
+        let user_handler: Arc<DynUserHandler> = Arc::new(user_handler);
+        let oidc_handler: Arc<DynOidcHandler> = Arc::new(oidc_handler);
+        let session_handler: Arc<DynSessionHandler> = Arc::new(session_handler);
+        let refresh_token_handler: Arc<DynRefreshTokenHandler> =
+            Arc::new(MockRefreshTokenHandler::new());
+        let two_factor_handler: Arc<DynTwoFactorHandler> = Arc::new(MockTwoFactorHandler::new());
+        let login_attempt_handler: Arc<DynLoginAttemptHandler> =
+            Arc::new(MockLoginAttemptHandler::new());
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(JwtKeys::from_secret(b"secret")))
+                .app_data(web::Data::new(EmailVerificationPolicy::BlockLogin))
+                .app_data(web::Data::from(user_handler))
+                .app_data(web::Data::from(oidc_handler))
+                .app_data(web::Data::from(session_handler))
+                .app_data(web::Data::from(refresh_token_handler))
+                .app_data(web::Data::from(two_factor_handler))
+                .app_data(web::Data::from(login_attempt_handler))
+                .configure(auth_routes),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/auth/oidc/callback")
+            .set_json(serde_json::json!({ "code": "code", "state": "state" }));
+
+        let status = test::call_service(&app, req.to_request()).await.status();
+
+        assert_eq!(status, 403);
+    }
+
+    #[actix_web::test]
+    async fn logout_with_oauth_token_spares_its_grant() {
+        let keys = JwtKeys::from_secret(b"secret");
+        let token = create_oauth_jwt(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            &[Scope::UsersRead],
+            Some(Uuid::new_v4()),
+            &keys,
+        )
+        .unwrap();
+        let jti = decode_jwt(&token, &keys).unwrap().jti;
+        let mut revocation_handler = MockTokenRevocationHandler::new();
+        revocation_handler
+            .expect_is_token_revoked()
+            .returning(|_, _, _, _| Ok(false));
+        revocation_handler.expect_revoke_session_tokens().never();
+        revocation_handler
+            .expect_revoke_token()
+            .withf(move |revoked_jti, _, _| *revoked_jti == jti)
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        let mut session_handler = MockSessionHandler::new();
+        session_handler.expect_revoke_session().never();
+        let revocation_handler: Arc<DynTokenRevocationHandler> = Arc::new(revocation_handler);
+        let session_handler: Arc<DynSessionHandler> = Arc::new(session_handler);
+        let refresh_token_handler: Arc<DynRefreshTokenHandler> =
+            Arc::new(MockRefreshTokenHandler::new());
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(keys))
+                .app_data(web::Data::new(CookieSettings::default()))
+                .app_data(web::Data::from(revocation_handler))
+                .app_data(web::Data::from(session_handler))
+                .app_data(web::Data::from(refresh_token_handler))
+                .configure(auth_routes),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/auth/logout")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")));
+
+        let status = test::call_service(&app, req.to_request()).await.status();
+
+        assert_eq!(status, 200);
+    }
 }
@@ -0,0 +1,153 @@
+use actix_web::{
+    web::{self, ServiceConfig},
+    HttpResponse,
+};
+
+use crate::{
+    auth::{AuthenticatedUser, Credential},
+    domain::{
+        personal_access_token::Scope,
+        user::{password, Role},
+    },
+    error::AppError,
+    handlers::user::DynUserHandler,
+};
+
+pub(crate) fn metrics_routes(cfg: &mut ServiceConfig) {
+    cfg.route("/metrics", web::get().to(get_metrics));
+}
+
+/// Prometheus text exposition, restricted to admins. Personal access tokens never carry
+/// their owner's role, so scrapers use one with the `metrics:read` scope instead, which
+/// stops working if its owner is no longer an admin.
+async fn get_metrics(
+    user: AuthenticatedUser,
+    user_handler: web::Data<DynUserHandler>,
+) -> Result<HttpResponse, AppError> {
+    let allowed = match &user.credential {
+        Credential::Jwt(_) => user.has_role(Role::Admin),
+        Credential::PersonalAccessToken { scopes } if scopes.contains(&Scope::MetricsRead) => {
+            user_handler
+                .get_user_by_id(user.id)
+                .await?
+                .is_some_and(|owner| owner.role >= Role::Admin)
+        }
+        Credential::PersonalAccessToken { .. } | Credential::OAuth { .. } => false,
+    };
+    if !allowed {
+        return Err(AppError::forbidden(
+            "You do not have permission to perform this action".to_string(),
+        ));
+    }
+
+    let body = format!(
+        "# HELP password_hashing_queue_depth Password hashing calls waiting for a free slot.\n\
+        # TYPE password_hashing_queue_depth gauge\n\
+        password_hashing_queue_depth {}\n\
+        # HELP password_hashing_in_flight Password hashing calls currently running.\n\
+        # TYPE password_hashing_in_flight gauge\n\
+        password_hashing_in_flight {}\n",
+        password::queue_depth(),
+        password::in_flight()
+    );
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use actix_web::{http::header, test, App};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        auth::{create_jwt, keys::JwtKeys},
+        domain::{personal_access_token::mocks::*, user::mocks::*},
+        handlers::{
+            personal_access_token::{
+                DynPersonalAccessTokenHandler, MockPersonalAccessTokenHandler,
+            },
+            token_revocation::{DynTokenRevocationHandler, MockTokenRevocationHandler},
+            user::MockUserHandler,
+        },
+    };
+
+    const ADMIN: Uuid = Uuid::from_u128(1);
+
+    async fn call(req: test::TestRequest) -> actix_web::http::StatusCode {
+        let mut revocation_handler = MockTokenRevocationHandler::new();
+        revocation_handler
+            .expect_is_token_revoked()
+            .returning(|_, _, _, _| Ok(false));
+        let revocation_handler: Arc<DynTokenRevocationHandler> = Arc::new(revocation_handler);
+        // The token names who owns it and with which scope.
+        let mut personal_access_token_handler = MockPersonalAccessTokenHandler::new();
+        personal_access_token_handler
+            .expect_authenticate()
+            .returning(|token| {
+                let (owner, scope) = token.split_once(':').unwrap();
+                Ok(factori::create!(
+                    PersonalAccessToken,
+                    user_id: if owner == "uat_admin" { ADMIN } else { Uuid::new_v4() },
+                    scopes: vec![scope.parse().unwrap()]
+                ))
+            });
+        let personal_access_token_handler: Arc<DynPersonalAccessTokenHandler> =
+            Arc::new(personal_access_token_handler);
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_get_user_by_id().returning(|id| {
+            let role = if id == ADMIN { Role::Admin } else { Role::User };
+            Ok(Some(factori::create!(PublicUser, id: id, role: role)))
+        });
+        let user_handler: Arc<DynUserHandler> = Arc::new(user_handler);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(JwtKeys::from_secret(b"secret")))
+                .app_data(web::Data::from(revocation_handler))
+                .app_data(web::Data::from(personal_access_token_handler))
+                .app_data(web::Data::from(user_handler))
+                .configure(metrics_routes),
+        )
+        .await;
+
+        test::call_service(&app, req.to_request()).await.status()
+    }
+
+    #[actix_web::test]
+    async fn serves_metrics_to_admins_only() {
+        let mut statuses = vec![];
+        for role in [None, Some(Role::User), Some(Role::Admin)] {
+            let mut req = test::TestRequest::get().uri("/metrics");
+            if let Some(role) = role {
+                let token =
+                    create_jwt(Uuid::new_v4(), role, None, &JwtKeys::from_secret(b"secret"))
+                        .unwrap();
+                req = req.insert_header((header::AUTHORIZATION, format!("Bearer {token}")));
+            }
+            statuses.push(call(req).await);
+        }
+
+        assert_eq!(statuses, [401, 403, 200]);
+    }
+
+    #[actix_web::test]
+    async fn serves_metrics_to_metrics_token_of_an_admin() {
+        let mut statuses = vec![];
+        for token in [
+            "uat_admin:metrics:read",
+            "uat_admin:users:read",
+            "uat_user:metrics:read",
+        ] {
+            let req = test::TestRequest::get()
+                .uri("/metrics")
+                .insert_header((header::AUTHORIZATION, format!("Bearer {token}")));
+            statuses.push(call(req).await);
+        }
+
+        assert_eq!(statuses, [200, 403, 403]);
+    }
+}
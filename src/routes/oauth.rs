@@ -0,0 +1,505 @@
+use actix_web::{
+    http::{header, StatusCode},
+    web::{self, ServiceConfig},
+    HttpRequest, HttpResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Serialize;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    auth::{create_oauth_jwt, keys::JwtKeys, AuthenticatedUser, Credential, VerifiedUser},
+    domain::{
+        oauth::{
+            payload::{
+                AuthorizationDecisionPayload, AuthorizationRequest, NewOAuthClientPayload,
+                TokenRequestPayload,
+            },
+            OAuthClient, OAuthErrorCode,
+        },
+        personal_access_token::Scope,
+        user::Role,
+    },
+    error::AppError,
+    handlers::{
+        oauth::{ClientCredentials, DynOAuthHandler, TokenGrant},
+        token_revocation::DynTokenRevocationHandler,
+    },
+    repositories::error::RepositoryError,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NewOAuthClientResponse {
+    #[serde(flatten)]
+    client: OAuthClient,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_secret: Option<String>,
+}
+
+/// What the consent page shows the user before they decide.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConsentResponse {
+    client_id: Uuid,
+    client_name: String,
+    scopes: Vec<Scope>,
+    redirect_uri: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthorizationDecisionResponse {
+    redirect_url: String,
+}
+
+/// Field names are set by RFC 6749, like those of [`OAuthErrorResponse`].
+#[derive(Serialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: i64,
+    scope: String,
+}
+
+#[derive(Serialize)]
+struct OAuthErrorResponse {
+    error: OAuthErrorCode,
+    error_description: String,
+}
+
+pub(crate) fn oauth_routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        web::scope("/oauth")
+            .route("/authorize", web::get().to(get_authorization))
+            .route("/authorize", web::post().to(decide_authorization))
+            .route("/token", web::post().to(issue_token)),
+    );
+}
+
+pub(crate) fn oauth_client_routes(cfg: &mut ServiceConfig) {
+    cfg.route("/{userId}/oauth-clients", web::get().to(get_clients))
+        .route("/{userId}/oauth-clients", web::post().to(register_client))
+        .route(
+            "/{userId}/oauth-clients/{clientId}",
+            web::delete().to(delete_client),
+        );
+}
+
+pub(crate) fn oauth_grant_routes(cfg: &mut ServiceConfig) {
+    cfg.route("/{userId}/oauth-grants", web::get().to(get_grants))
+        .route(
+            "/{userId}/oauth-grants/{clientId}",
+            web::delete().to(revoke_grant),
+        );
+}
+
+#[tracing::instrument(skip(handler))]
+async fn get_clients(
+    user: AuthenticatedUser,
+    params: web::Path<Uuid>,
+    handler: web::Data<DynOAuthHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+
+    user.authorize_user(id, Role::Admin)?;
+    user.require_scope(Scope::UsersRead)?;
+
+    let clients = handler.get_user_clients(id).await?;
+    Ok(HttpResponse::Ok().json(clients))
+}
+
+#[tracing::instrument(skip(handler))]
+async fn register_client(
+    user: VerifiedUser,
+    params: web::Path<Uuid>,
+    body: web::Json<NewOAuthClientPayload>,
+    handler: web::Data<DynOAuthHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+    let payload = body.into_inner();
+
+    user.authorize_self(id)?;
+    user.require_scope(Scope::UsersWrite)?;
+    user.reject_oauth()?;
+    payload.validate()?;
+
+    // A client can only be granted scopes its creator holds.
+    for scope in &payload.scopes {
+        user.require_scope(*scope)?;
+    }
+    if payload.scopes.contains(&Scope::MetricsRead) {
+        return Err(AppError::bad_request(
+            "OAuth clients cannot be granted the metrics:read scope".to_string(),
+        ));
+    }
+
+    let (client, client_secret) = handler.register_client(id, payload).await?;
+    Ok(HttpResponse::Created().json(NewOAuthClientResponse {
+        client,
+        client_secret,
+    }))
+}
+
+/// Tokens already issued to the client stop working along with it.
+#[tracing::instrument(skip(handler, revocation_handler))]
+async fn delete_client(
+    user: AuthenticatedUser,
+    params: web::Path<(Uuid, Uuid)>,
+    handler: web::Data<DynOAuthHandler>,
+    revocation_handler: web::Data<DynTokenRevocationHandler>,
+) -> Result<HttpResponse, AppError> {
+    let (id, client_id) = params.into_inner();
+
+    user.authorize_user(id, Role::Admin)?;
+    user.require_scope(Scope::UsersWrite)?;
+    user.reject_oauth()?;
+
+    handler.delete_client(id, client_id).await?;
+    revocation_handler.revoke_user_tokens(client_id).await?;
+    Ok(HttpResponse::Ok().into())
+}
+
+#[tracing::instrument(skip(handler))]
+async fn get_grants(
+    user: AuthenticatedUser,
+    params: web::Path<Uuid>,
+    handler: web::Data<DynOAuthHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+
+    user.authorize_user(id, Role::Admin)?;
+    user.require_scope(Scope::UsersRead)?;
+
+    let grants = handler.get_user_grants(id).await?;
+    Ok(HttpResponse::Ok().json(grants))
+}
+
+/// Withdraws the consent to the client, whose access tokens stop working right away.
+/// It has to ask for consent again to act for the user.
+#[tracing::instrument(skip(handler, revocation_handler))]
+async fn revoke_grant(
+    user: AuthenticatedUser,
+    params: web::Path<(Uuid, Uuid)>,
+    handler: web::Data<DynOAuthHandler>,
+    revocation_handler: web::Data<DynTokenRevocationHandler>,
+) -> Result<HttpResponse, AppError> {
+    let (id, client_id) = params.into_inner();
+
+    user.authorize_user(id, Role::Admin)?;
+    user.require_scope(Scope::UsersWrite)?;
+    user.reject_oauth()?;
+
+    let grant_id = handler.revoke_grant(id, client_id).await?;
+    revocation_handler
+        .revoke_session_tokens(id, grant_id)
+        .await?;
+    Ok(HttpResponse::Ok().into())
+}
+
+/// Consent can only come from the user themselves, not from a token handed to an app.
+fn require_sign_in(user: &AuthenticatedUser) -> Result<(), AppError> {
+    if let Credential::Jwt(_) = user.credential {
+        return Ok(());
+    }
+    Err(AppError::forbidden(
+        "Only a signed in user can authorize apps".to_string(),
+    ))
+}
+
+/// Called by the consent page with the query the client sent the user to it with.
+#[tracing::instrument(skip(handler))]
+async fn get_authorization(
+    user: AuthenticatedUser,
+    query: web::Query<AuthorizationRequest>,
+    handler: web::Data<DynOAuthHandler>,
+) -> Result<HttpResponse, AppError> {
+    let request = query.into_inner();
+
+    require_sign_in(&user)?;
+
+    let redirect_uri = request.redirect_uri.clone();
+    let (client, scopes) = handler.check_authorization(request).await?;
+    Ok(HttpResponse::Ok().json(ConsentResponse {
+        client_id: client.id,
+        client_name: client.name,
+        scopes,
+        redirect_uri,
+    }))
+}
+
+/// Records whether the user approved, returning the URL the consent page should send
+/// them back to the client with.
+#[tracing::instrument(skip(handler))]
+async fn decide_authorization(
+    user: VerifiedUser,
+    query: web::Query<AuthorizationRequest>,
+    body: web::Json<AuthorizationDecisionPayload>,
+    handler: web::Data<DynOAuthHandler>,
+) -> Result<HttpResponse, AppError> {
+    require_sign_in(&user)?;
+
+    let redirect_url = handler
+        .authorize(user.id, query.into_inner(), body.approved)
+        .await?;
+    Ok(HttpResponse::Ok().json(AuthorizationDecisionResponse { redirect_url }))
+}
+
+/// The token endpoint of RFC 6749, which answers errors in its own format rather than
+/// ours since clients are generic OAuth libraries.
+async fn issue_token(
+    req: HttpRequest,
+    form: web::Form<TokenRequestPayload>,
+    handler: web::Data<DynOAuthHandler>,
+    keys: web::Data<JwtKeys>,
+) -> Result<HttpResponse, AppError> {
+    let grant = match token_grant(&req, form.into_inner(), handler.get_ref()).await {
+        Ok(grant) => grant,
+        Err(RepositoryError::OAuth(error, error_description)) => {
+            let mut response = if error == OAuthErrorCode::InvalidClient {
+                let mut response = HttpResponse::build(StatusCode::UNAUTHORIZED);
+                response.insert_header((header::WWW_AUTHENTICATE, "Basic"));
+                response
+            } else {
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+            };
+            return Ok(response
+                .insert_header((header::CACHE_CONTROL, "no-store"))
+                .json(OAuthErrorResponse {
+                    error,
+                    error_description,
+                }));
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    let access_token = create_oauth_jwt(
+        grant.subject,
+        grant.client_id,
+        &grant.scopes,
+        grant.grant_id,
+        &keys,
+    )
+    .map_err(|e| AppError::internal(e.to_string()))?;
+    tracing::info!(client_id = %grant.client_id, subject = %grant.subject, "issued OAuth access token");
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(TokenResponse {
+            access_token,
+            token_type: "Bearer",
+            expires_in: keys.settings().access_token_lifetime.num_seconds(),
+            scope: grant
+                .scopes
+                .iter()
+                .map(|scope| scope.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+        }))
+}
+
+async fn token_grant(
+    req: &HttpRequest,
+    payload: TokenRequestPayload,
+    handler: &DynOAuthHandler,
+) -> Result<TokenGrant, RepositoryError> {
+    let client = client_credentials(req, &payload)?;
+
+    match payload.grant_type.as_str() {
+        "authorization_code" => {
+            let (Some(code), Some(redirect_uri), Some(code_verifier)) =
+                (payload.code, payload.redirect_uri, payload.code_verifier)
+            else {
+                return Err(RepositoryError::OAuth(
+                    OAuthErrorCode::InvalidRequest,
+                    "code, redirect_uri and code_verifier are required".to_string(),
+                ));
+            };
+            handler
+                .exchange_code(client, code, redirect_uri, code_verifier)
+                .await
+        }
+        "client_credentials" => handler.client_credentials(client, payload.scope).await,
+        _ => Err(RepositoryError::OAuth(
+            OAuthErrorCode::UnsupportedGrantType,
+            "Only the authorization_code and client_credentials grants are supported".to_string(),
+        )),
+    }
+}
+
+/// Reads the client from HTTP Basic, or else from the form. Sending both is refused.
+fn client_credentials(
+    req: &HttpRequest,
+    payload: &TokenRequestPayload,
+) -> Result<ClientCredentials, RepositoryError> {
+    let invalid_client = || {
+        RepositoryError::OAuth(
+            OAuthErrorCode::InvalidClient,
+            "Invalid client authentication".to_string(),
+        )
+    };
+
+    let Some(header) = req.headers().get(header::AUTHORIZATION) else {
+        let client_id = payload.client_id.clone().ok_or_else(invalid_client)?;
+        return Ok(ClientCredentials {
+            client_id,
+            client_secret: payload.client_secret.clone(),
+        });
+    };
+
+    if payload.client_secret.is_some() {
+        return Err(RepositoryError::OAuth(
+            OAuthErrorCode::InvalidRequest,
+            "Use a single way to authenticate the client".to_string(),
+        ));
+    }
+    let credentials = header
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .ok_or_else(invalid_client)?;
+    let (client_id, client_secret) = credentials.split_once(':').ok_or_else(invalid_client)?;
+
+    Ok(ClientCredentials {
+        client_id: client_id.to_string(),
+        client_secret: Some(client_secret.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use actix_web::{test, App};
+
+    use super::*;
+    use crate::{
+        domain::email_verification::EmailVerificationPolicy,
+        handlers::{
+            oauth::MockOAuthHandler,
+            token_revocation::{DynTokenRevocationHandler, MockTokenRevocationHandler},
+        },
+    };
+
+    async fn call(
+        oauth_handler: MockOAuthHandler,
+        req: test::TestRequest,
+    ) -> actix_web::dev::ServiceResponse {
+        let mut revocation_handler = MockTokenRevocationHandler::new();
+        revocation_handler
+            .expect_is_token_revoked()
+            .returning(|_, _, _, _| Ok(false));
+        let revocation_handler: Arc<DynTokenRevocationHandler> = Arc::new(revocation_handler);
+        let oauth_handler: Arc<DynOAuthHandler> = Arc::new(oauth_handler);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(JwtKeys::from_secret(b"secret")))
+                .app_data(web::Data::new(EmailVerificationPolicy::Optional))
+                .app_data(web::Data::from(revocation_handler))
+                .app_data(web::Data::from(oauth_handler))
+                .configure(oauth_routes),
+        )
+        .await;
+
+        test::call_service(&app, req.to_request()).await
+    }
+
+    fn oauth_bearer() -> (header::HeaderName, String) {
+        let token = create_oauth_jwt(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            &[Scope::UsersRead, Scope::UsersWrite],
+            Some(Uuid::new_v4()),
+            &JwtKeys::from_secret(b"secret"),
+        )
+        .unwrap();
+        (header::AUTHORIZATION, format!("Bearer {token}"))
+    }
+
+    fn authorize_uri() -> String {
+        format!(
+            "/oauth/authorize?response_type=code&client_id={}&redirect_uri=https://partner.example.com/callback&code_challenge=challenge&code_challenge_method=S256",
+            Uuid::new_v4()
+        )
+    }
+
+    #[actix_web::test]
+    async fn answers_invalid_client_with_basic_challenge() {
+        let mut oauth_handler = MockOAuthHandler::new();
+        oauth_handler
+            .expect_exchange_code()
+            .return_once(|_, _, _, _| {
+                Err(RepositoryError::OAuth(
+                    OAuthErrorCode::InvalidClient,
+                    "Invalid client".to_string(),
+                ))
+            });
+
+        let req = test::TestRequest::post().uri("/oauth/token").set_form([
+            ("grant_type", "authorization_code"),
+            ("code", "code"),
+            ("redirect_uri", "https://partner.example.com/callback"),
+            ("code_verifier", "verifier"),
+            ("client_id", "client"),
+        ]);
+        let res = call(oauth_handler, req).await;
+
+        assert_eq!(res.status(), 401);
+        assert_eq!(
+            res.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+            "Basic"
+        );
+        assert_eq!(
+            res.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-store"
+        );
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "invalid_client");
+        assert_eq!(body["error_description"], "Invalid client");
+    }
+
+    #[actix_web::test]
+    async fn refuses_basic_auth_along_with_form_secret() {
+        let mut oauth_handler = MockOAuthHandler::new();
+        oauth_handler.expect_client_credentials().never();
+
+        let req = test::TestRequest::post()
+            .uri("/oauth/token")
+            .insert_header((
+                header::AUTHORIZATION,
+                format!("Basic {}", STANDARD.encode("client:secret")),
+            ))
+            .set_form([
+                ("grant_type", "client_credentials"),
+                ("client_secret", "secret"),
+            ]);
+        let res = call(oauth_handler, req).await;
+
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "invalid_request");
+    }
+
+    #[actix_web::test]
+    async fn consent_requires_sign_in_token() {
+        let mut oauth_handler = MockOAuthHandler::new();
+        oauth_handler.expect_check_authorization().never();
+
+        let req = test::TestRequest::get()
+            .uri(&authorize_uri())
+            .insert_header(oauth_bearer());
+        assert_eq!(call(oauth_handler, req).await.status(), 403);
+
+        let mut oauth_handler = MockOAuthHandler::new();
+        oauth_handler.expect_authorize().never();
+        let req = test::TestRequest::post()
+            .uri(&authorize_uri())
+            .insert_header(oauth_bearer())
+            .set_json(serde_json::json!({ "approved": true }));
+        assert_eq!(call(oauth_handler, req).await.status(), 403);
+    }
+}
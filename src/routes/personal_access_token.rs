@@ -0,0 +1,99 @@
+use actix_web::{
+    web::{self, ServiceConfig},
+    HttpResponse,
+};
+use serde::Serialize;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    auth::{AuthenticatedUser, VerifiedUser},
+    domain::{
+        personal_access_token::{
+            payload::NewPersonalAccessTokenPayload, PersonalAccessToken, Scope,
+        },
+        user::Role,
+    },
+    error::AppError,
+    handlers::personal_access_token::DynPersonalAccessTokenHandler,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NewPersonalAccessTokenResponse {
+    #[serde(flatten)]
+    personal_access_token: PersonalAccessToken,
+    token: String,
+}
+
+pub(crate) fn personal_access_token_routes(cfg: &mut ServiceConfig) {
+    cfg.route("/{userId}/tokens", web::get().to(get_tokens))
+        .route("/{userId}/tokens", web::post().to(create_token))
+        .route("/{userId}/tokens/{tokenId}", web::delete().to(delete_token));
+}
+
+#[tracing::instrument(skip(handler))]
+async fn get_tokens(
+    user: AuthenticatedUser,
+    params: web::Path<Uuid>,
+    handler: web::Data<DynPersonalAccessTokenHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+
+    user.authorize_user(id, Role::Admin)?;
+    user.require_scope(Scope::UsersRead)?;
+
+    let tokens = handler.get_user_tokens(id).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[tracing::instrument(skip(handler))]
+async fn create_token(
+    user: VerifiedUser,
+    params: web::Path<Uuid>,
+    body: web::Json<NewPersonalAccessTokenPayload>,
+    handler: web::Data<DynPersonalAccessTokenHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+    let payload = body.into_inner();
+
+    user.authorize_self(id)?;
+    user.require_scope(Scope::UsersWrite)?;
+    // Apps acting for the user must not outlive the short lifetime of their tokens.
+    user.reject_oauth()?;
+    payload.validate()?;
+
+    // A token can only hand out scopes its creator holds.
+    for scope in &payload.scopes {
+        user.require_scope(*scope)?;
+    }
+    if payload.scopes.contains(&Scope::MetricsRead) && !user.has_role(Role::Admin) {
+        return Err(AppError::forbidden(
+            "Only admins can grant the metrics:read scope".to_string(),
+        ));
+    }
+
+    let (personal_access_token, token) = handler.create_token(id, payload).await?;
+    Ok(
+        HttpResponse::Created().json(NewPersonalAccessTokenResponse {
+            personal_access_token,
+            token,
+        }),
+    )
+}
+
+#[tracing::instrument(skip(handler))]
+async fn delete_token(
+    user: AuthenticatedUser,
+    params: web::Path<(Uuid, Uuid)>,
+    handler: web::Data<DynPersonalAccessTokenHandler>,
+) -> Result<HttpResponse, AppError> {
+    let (id, token_id) = params.into_inner();
+
+    user.authorize_user(id, Role::Admin)?;
+    user.require_scope(Scope::UsersWrite)?;
+    user.reject_oauth()?;
+
+    handler.delete_token(id, token_id).await?;
+    Ok(HttpResponse::Ok().into())
+}
@@ -0,0 +1,181 @@
+use actix_web::{
+    dev::Payload,
+    web::{self, ServiceConfig},
+    FromRequest, HttpRequest, HttpResponse,
+};
+use futures_util::future::{ready, Ready};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::{AuthenticatedUser, Claims, Credential},
+    domain::{personal_access_token::Scope, session::Session, user::Role},
+    error::AppError,
+    handlers::{
+        personal_access_token::DynPersonalAccessTokenHandler,
+        refresh_token::DynRefreshTokenHandler, session::DynSessionHandler,
+        token_revocation::DynTokenRevocationHandler,
+    },
+    routes::app_data,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionResponse {
+    #[serde(flatten)]
+    session: Session,
+    /// Whether the request was made from this session.
+    current: bool,
+}
+
+/// What signing a user out of their devices takes, for the routes that do so after the
+/// password changed.
+pub(crate) struct SignOut {
+    session_handler: web::Data<DynSessionHandler>,
+    refresh_token_handler: web::Data<DynRefreshTokenHandler>,
+    revocation_handler: web::Data<DynTokenRevocationHandler>,
+    personal_access_token_handler: web::Data<DynPersonalAccessTokenHandler>,
+}
+
+impl FromRequest for SignOut {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(SignOut::from_app_data(req))
+    }
+}
+
+impl SignOut {
+    fn from_app_data(req: &HttpRequest) -> Result<SignOut, AppError> {
+        Ok(SignOut {
+            session_handler: app_data(req)?,
+            refresh_token_handler: app_data(req)?,
+            revocation_handler: app_data(req)?,
+            personal_access_token_handler: app_data(req)?,
+        })
+    }
+
+    /// Ends every session and revokes every token of the user. Personal access tokens go
+    /// too, since whoever knew the old password could have created some.
+    pub(crate) async fn everywhere(&self, user_id: Uuid) -> Result<(), AppError> {
+        self.session_handler
+            .revoke_other_sessions(user_id, None)
+            .await?;
+        self.revocation_handler.revoke_user_tokens(user_id).await?;
+        self.personal_access_token_handler
+            .revoke_user_tokens(user_id)
+            .await?;
+        Ok(())
+    }
+
+    /// Like [`SignOut::everywhere`] but for the session and access token making the
+    /// request. The session gets a new refresh token, returned, since its previous one is
+    /// revoked along with the others.
+    pub(crate) async fn everywhere_else(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+        claims: &Claims,
+    ) -> Result<String, AppError> {
+        self.session_handler
+            .revoke_other_sessions(user_id, Some(session_id))
+            .await?;
+        self.revocation_handler
+            .revoke_other_tokens(user_id, claims.jti)
+            .await?;
+        self.personal_access_token_handler
+            .revoke_user_tokens(user_id)
+            .await?;
+        self.refresh_token_handler.revoke_user_tokens(user_id).await?;
+        Ok(self
+            .refresh_token_handler
+            .issue_token(user_id, session_id)
+            .await?)
+    }
+}
+
+pub(crate) fn session_routes(cfg: &mut ServiceConfig) {
+    cfg.route("/{userId}/sessions", web::get().to(get_sessions))
+        .route(
+            "/{userId}/sessions",
+            web::delete().to(delete_other_sessions),
+        )
+        .route(
+            "/{userId}/sessions/{sessionId}",
+            web::delete().to(delete_session),
+        );
+}
+
+#[tracing::instrument(skip(handler))]
+async fn get_sessions(
+    user: AuthenticatedUser,
+    params: web::Path<Uuid>,
+    handler: web::Data<DynSessionHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+
+    user.authorize_user(id, Role::Admin)?;
+    user.require_scope(Scope::UsersRead)?;
+
+    let current_session_id = match &user.credential {
+        Credential::Jwt(claims) => claims.sid,
+        Credential::PersonalAccessToken { .. } | Credential::OAuth { .. } => None,
+    };
+    let sessions: Vec<SessionResponse> = handler
+        .get_user_sessions(id)
+        .await?
+        .into_iter()
+        .map(|session| SessionResponse {
+            current: Some(session.id) == current_session_id,
+            session,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// Signs the device out right away, access tokens included, so a stolen session can be
+/// killed without waiting for its tokens to expire.
+#[tracing::instrument(skip(handler, revocation_handler))]
+async fn delete_session(
+    user: AuthenticatedUser,
+    params: web::Path<(Uuid, Uuid)>,
+    handler: web::Data<DynSessionHandler>,
+    revocation_handler: web::Data<DynTokenRevocationHandler>,
+) -> Result<HttpResponse, AppError> {
+    let (id, session_id) = params.into_inner();
+
+    user.authorize_user(id, Role::Admin)?;
+    user.require_scope(Scope::UsersWrite)?;
+    user.reject_oauth()?;
+
+    handler.revoke_session(id, session_id).await?;
+    revocation_handler
+        .revoke_session_tokens(id, session_id)
+        .await?;
+    Ok(HttpResponse::Ok().into())
+}
+
+/// Logs out everywhere but in the session making the request.
+#[tracing::instrument(skip(handler, revocation_handler))]
+async fn delete_other_sessions(
+    user: AuthenticatedUser,
+    params: web::Path<Uuid>,
+    handler: web::Data<DynSessionHandler>,
+    revocation_handler: web::Data<DynTokenRevocationHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+
+    user.authorize_self(id)?;
+    let Credential::Jwt(claims) = &user.credential else {
+        return Err(AppError::forbidden(
+            "Only tokens from a sign-in are tied to a session".to_string(),
+        ));
+    };
+
+    handler.revoke_other_sessions(id, claims.sid).await?;
+    revocation_handler
+        .revoke_other_tokens(id, claims.jti)
+        .await?;
+    Ok(HttpResponse::Ok().into())
+}
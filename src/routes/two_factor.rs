@@ -0,0 +1,120 @@
+use actix_web::{
+    web::{self, ServiceConfig},
+    HttpRequest, HttpResponse,
+};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    auth::{AuthenticatedUser, VerifiedUser},
+    domain::{
+        personal_access_token::Scope,
+        two_factor::payload::{ConfirmTwoFactorPayload, DisableTwoFactorPayload},
+        user::Role,
+    },
+    error::AppError,
+    handlers::{
+        login_attempt::{throttled, DynLoginAttemptHandler},
+        two_factor::DynTwoFactorHandler,
+        user::DynUserHandler,
+    },
+};
+
+pub(crate) fn two_factor_routes(cfg: &mut ServiceConfig) {
+    cfg.route("/{userId}/2fa", web::post().to(enroll_two_factor))
+        .route("/{userId}/2fa", web::delete().to(disable_two_factor))
+        .route("/{userId}/2fa/confirm", web::post().to(confirm_two_factor));
+}
+
+#[tracing::instrument(skip(handler, two_factor_handler))]
+async fn enroll_two_factor(
+    user: VerifiedUser,
+    params: web::Path<Uuid>,
+    handler: web::Data<DynUserHandler>,
+    two_factor_handler: web::Data<DynTwoFactorHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+
+    user.authorize_self(id)?;
+    user.require_scope(Scope::UsersWrite)?;
+    user.reject_oauth()?;
+
+    let account = handler
+        .get_user_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::not_found("Not found".to_string()))?;
+
+    let enrollment = two_factor_handler.enroll(id, account.email).await?;
+    Ok(HttpResponse::Created().json(enrollment))
+}
+
+#[tracing::instrument(skip(two_factor_handler))]
+async fn confirm_two_factor(
+    user: VerifiedUser,
+    params: web::Path<Uuid>,
+    body: web::Json<ConfirmTwoFactorPayload>,
+    two_factor_handler: web::Data<DynTwoFactorHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+    let payload = body.into_inner();
+
+    user.authorize_self(id)?;
+    user.require_scope(Scope::UsersWrite)?;
+    user.reject_oauth()?;
+    payload.validate()?;
+
+    two_factor_handler.confirm(id, payload.code).await?;
+    Ok(HttpResponse::Ok().into())
+}
+
+/// Users confirm with their password or a current code, checked like at sign-in so a
+/// stolen access token is not enough. Admins may disable 2FA of other users without
+/// either, for those who lost their device and recovery codes.
+#[tracing::instrument(skip(req, body, handler, two_factor_handler, login_attempt_handler))]
+async fn disable_two_factor(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    params: web::Path<Uuid>,
+    body: Option<web::Json<DisableTwoFactorPayload>>,
+    handler: web::Data<DynUserHandler>,
+    two_factor_handler: web::Data<DynTwoFactorHandler>,
+    login_attempt_handler: web::Data<DynLoginAttemptHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+
+    user.authorize_user(id, Role::Admin)?;
+    user.require_scope(Scope::UsersWrite)?;
+    user.reject_oauth()?;
+
+    if user.id == id {
+        let payload = body.map(web::Json::into_inner).unwrap_or(DisableTwoFactorPayload {
+            password: None,
+            code: None,
+        });
+        payload.validate()?;
+
+        let check = match (payload.password, payload.code) {
+            (Some(password), _) => handler.verify_password(id, password),
+            (None, Some(code)) => two_factor_handler.verify_code(id, code),
+            (None, None) => {
+                return Err(AppError::bad_request(
+                    "Confirm with your password or a two-factor code".to_string(),
+                ))
+            }
+        };
+        let account = handler
+            .get_user_by_id(id)
+            .await?
+            .ok_or_else(|| AppError::not_found("Not found".to_string()))?;
+        throttled(
+            login_attempt_handler.get_ref(),
+            account.email,
+            req.peer_addr().map(|addr| addr.ip()),
+            check,
+        )
+        .await?;
+    }
+
+    two_factor_handler.disable(id).await?;
+    Ok(HttpResponse::Ok().into())
+}
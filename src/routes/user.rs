@@ -1,67 +1,112 @@
-use crate::{auth::get_id_auth_header, domain::user::validation::format_error_msg};
+use crate::{
+    auth::{guard::RequireRole, AuthenticatedUser, Credential, VerifiedUser},
+    domain::{personal_access_token::Scope, user::Role},
+    routes::{
+        oauth::{oauth_client_routes, oauth_grant_routes},
+        personal_access_token::personal_access_token_routes,
+        session::{session_routes, SignOut},
+        two_factor::two_factor_routes,
+    },
+};
 use actix_web::{
     web::{self, ServiceConfig},
     HttpRequest, HttpResponse,
 };
+use serde::Serialize;
 use uuid::Uuid;
 use validator::Validate;
 
 use crate::{
-    domain::user::payload::{NewUserPayload, UpdateUserPayload},
+    domain::{
+        email_change::payload::EmailChangePayload,
+        user::payload::{
+            ChangePasswordPayload, NewUserPayload, UpdateUserPayload, UpdateUserRolePayload,
+        },
+    },
     error::AppError,
-    handlers::user::DynUserHandler,
+    handlers::{
+        email_change::DynEmailChangeHandler,
+        email_verification::DynEmailVerificationHandler,
+        login_attempt::{throttled, DynLoginAttemptHandler},
+        oauth::DynOAuthHandler,
+        token_revocation::DynTokenRevocationHandler,
+        user::DynUserHandler,
+    },
+    response::GenericResponse,
 };
 
+/// Every mutating route except sign up requires an [`AuthenticatedUser`] (401 otherwise)
+/// and is checked with [`AuthenticatedUser::authorize_user`] or [`RequireRole`] (403).
+/// Routes that change the account itself take a [`VerifiedUser`] instead.
+/// The user is extracted before the body so unauthenticated requests never get to
+/// validation errors.
+///
+/// Resources belonging to a user get their routes from their own module, configured
+/// into this scope so their paths start at `/{userId}`.
 pub(crate) fn user_routes(cfg: &mut ServiceConfig) {
     cfg.service(
         web::scope("/users")
             .route("/", web::post().to(create_user))
             .route("/{userId}", web::patch().to(update_user_by_id))
             .route("/{userId}", web::get().to(get_user_by_id))
-            .route("/{userId}", web::delete().to(delete_user)),
+            .route("/{userId}", web::delete().to(delete_user))
+            .route("/{userId}/password", web::post().to(change_password))
+            .route("/{userId}/email", web::post().to(request_email_change))
+            .service(
+                web::resource("/{userId}/role")
+                    .wrap(RequireRole::new(Role::Admin))
+                    .route(web::put().to(update_user_role)),
+            )
+            .service(
+                web::resource("/{userId}/lockout")
+                    .wrap(RequireRole::new(Role::Admin))
+                    .route(web::delete().to(unlock_user)),
+            )
+            .configure(personal_access_token_routes)
+            .configure(session_routes)
+            .configure(two_factor_routes)
+            .configure(oauth_client_routes)
+            .configure(oauth_grant_routes),
     );
 }
 
-#[tracing::instrument(skip(handler))]
+#[tracing::instrument(skip(handler, email_verification_handler))]
 async fn create_user(
     body: web::Json<NewUserPayload>,
     handler: web::Data<DynUserHandler>,
+    email_verification_handler: web::Data<DynEmailVerificationHandler>,
 ) -> Result<HttpResponse, AppError> {
     let payload = body.into_inner();
 
-    if let Err(e) = payload.validate() {
-        return Err(AppError::bad_request(format_error_msg(e.field_errors())));
-    }
+    payload.validate()?;
 
     let new_user = handler.create_user(payload).await?;
-    Ok(HttpResponse::Ok().json(new_user))
+    // The account exists either way, the user can ask for another mail.
+    if let Err(error) = email_verification_handler
+        .send_verification(new_user.clone())
+        .await
+    {
+        tracing::error!(%error, user_id = %new_user.id, "failed to send verification mail");
+    }
+    Ok(HttpResponse::Created().json(new_user))
 }
 
 #[tracing::instrument(skip(handler))]
 async fn update_user_by_id(
+    user: VerifiedUser,
     params: web::Path<Uuid>,
     body: web::Json<UpdateUserPayload>,
     handler: web::Data<DynUserHandler>,
-    req: HttpRequest,
 ) -> Result<HttpResponse, AppError> {
     let id = params.into_inner();
     let payload = body.into_inner();
 
-    match req.headers().get("Authorization") {
-        Some(auth_header) => {
-            let uuid = get_id_auth_header(auth_header)?;
-            if uuid != id {
-                return Err(AppError::bad_request("Unauthorized".to_string()));
-            }
-            if let Err(e) = payload.validate() {
-                return Err(AppError::bad_request(format_error_msg(e.field_errors())));
-            }
-
-            handler.update_user_by_id(id, payload).await?;
-            Ok(HttpResponse::Ok().into())
-        }
-        None => Ok(HttpResponse::Unauthorized().body("Unauthorized")),
-    }
+    user.authorize_user(id, Role::Admin)?;
+    user.require_scope(Scope::UsersWrite)?;
+    payload.validate()?;
+
+    handler.update_user_by_id(id, payload).await?;
+    Ok(HttpResponse::Ok().into())
 }
 
 #[tracing::instrument(skip(handler))]
@@ -75,12 +120,961 @@ async fn get_user_by_id(
     Ok(HttpResponse::Ok().json(user))
 }
 
-#[tracing::instrument(skip(handler))]
+/// The OAuth clients of the user go along with them, so the tokens they got through
+/// the client credentials grant are revoked too. The clients are listed before the
+/// delete cascades to them, and revoked after it so they cannot get a token in between.
+#[tracing::instrument(skip(handler, oauth_handler, revocation_handler))]
 async fn delete_user(
+    user: AuthenticatedUser,
     params: web::Path<Uuid>,
     handler: web::Data<DynUserHandler>,
+    oauth_handler: web::Data<DynOAuthHandler>,
+    revocation_handler: web::Data<DynTokenRevocationHandler>,
 ) -> Result<HttpResponse, AppError> {
     let id = params.into_inner();
+
+    user.authorize_user(id, Role::Admin)?;
+    user.require_scope(Scope::UsersWrite)?;
+    user.reject_oauth()?;
+
+    let clients = oauth_handler.get_user_clients(id).await?;
     handler.delete_user(id).await?;
+    revocation_handler.revoke_user_tokens(id).await?;
+    for client in clients {
+        revocation_handler.revoke_user_tokens(client.id).await?;
+    }
+    Ok(HttpResponse::Ok().into())
+}
+
+/// The email only changes once the link mailed to the new address is followed and the
+/// token posted to `/auth/email-change/confirm`. The password is checked like at sign-in.
+#[tracing::instrument(skip(req, handler, email_change_handler, login_attempt_handler, body))]
+async fn request_email_change(
+    req: HttpRequest,
+    user: VerifiedUser,
+    params: web::Path<Uuid>,
+    body: web::Json<EmailChangePayload>,
+    handler: web::Data<DynUserHandler>,
+    email_change_handler: web::Data<DynEmailChangeHandler>,
+    login_attempt_handler: web::Data<DynLoginAttemptHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+    let payload = body.into_inner();
+
+    user.authorize_self(id)?;
+    user.require_scope(Scope::UsersWrite)?;
+    user.reject_oauth()?;
+    payload.validate()?;
+
+    let account = handler
+        .get_user_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::not_found("Not found".to_string()))?;
+    throttled(
+        login_attempt_handler.get_ref(),
+        account.email,
+        req.peer_addr().map(|addr| addr.ip()),
+        email_change_handler.request_change(id, payload),
+    )
+    .await?;
+
+    Ok(HttpResponse::Accepted().json(GenericResponse {
+        status: 202,
+        message: "A confirmation link has been sent to the new email".to_string(),
+    }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangePasswordResponse {
+    refresh_token: String,
+}
+
+/// Signs the user out everywhere but in the session making the request. The current
+/// password is checked like at sign-in, so a stolen access token cannot be used to
+/// guess it.
+#[tracing::instrument(skip(req, handler, login_attempt_handler, sign_out, body))]
+async fn change_password(
+    req: HttpRequest,
+    user: VerifiedUser,
+    params: web::Path<Uuid>,
+    body: web::Json<ChangePasswordPayload>,
+    handler: web::Data<DynUserHandler>,
+    login_attempt_handler: web::Data<DynLoginAttemptHandler>,
+    sign_out: SignOut,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+    let payload = body.into_inner();
+
+    user.authorize_self(id)?;
+    let Credential::Jwt(claims) = &user.credential else {
+        return Err(AppError::forbidden(
+            "Only tokens from a sign-in can change the password".to_string(),
+        ));
+    };
+    // Only tokens issued before sessions were recorded lack one, and they expire shortly.
+    let session_id = claims.sid.ok_or_else(|| {
+        AppError::unauthorized("Sign in again to change the password".to_string())
+    })?;
+    payload.validate()?;
+
+    let account = handler
+        .get_user_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::not_found("Not found".to_string()))?;
+    throttled(
+        login_attempt_handler.get_ref(),
+        account.email,
+        req.peer_addr().map(|addr| addr.ip()),
+        handler.change_password(id, payload),
+    )
+    .await?;
+
+    let refresh_token = sign_out.everywhere_else(id, session_id, claims).await?;
+
+    Ok(HttpResponse::Ok().json(ChangePasswordResponse { refresh_token }))
+}
+
+#[tracing::instrument(skip(handler, revocation_handler))]
+async fn update_user_role(
+    params: web::Path<Uuid>,
+    body: web::Json<UpdateUserRolePayload>,
+    handler: web::Data<DynUserHandler>,
+    revocation_handler: web::Data<DynTokenRevocationHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+    let payload = body.into_inner();
+
+    handler.update_user_role(id, payload.role).await?;
+    // Tokens carry the role they were issued with, so make the change effective now.
+    revocation_handler.revoke_user_tokens(id).await?;
+    Ok(HttpResponse::Ok().into())
+}
+
+/// Clears the failed login counter of the account so its owner can sign in again right
+/// away. Counters kept for client addresses are not touched.
+#[tracing::instrument(skip(handler, login_attempt_handler))]
+async fn unlock_user(
+    params: web::Path<Uuid>,
+    handler: web::Data<DynUserHandler>,
+    login_attempt_handler: web::Data<DynLoginAttemptHandler>,
+) -> Result<HttpResponse, AppError> {
+    let id = params.into_inner();
+
+    let user = handler
+        .get_user_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::not_found("Not found".to_string()))?;
+
+    login_attempt_handler.unlock_account(user.email).await?;
     Ok(HttpResponse::Ok().into())
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use actix_web::{http::header, test, App};
+
+    use argon2::password_hash::Error;
+
+    use super::*;
+    use crate::{
+        auth::{create_jwt, create_oauth_jwt, keys::JwtKeys},
+        domain::{
+            email_verification::EmailVerificationPolicy, oauth::mocks::*,
+            personal_access_token::mocks::*, user::mocks::*,
+        },
+        error::error_handlers,
+        handlers::{
+            email_change::MockEmailChangeHandler,
+            email_verification::MockEmailVerificationHandler,
+            login_attempt::MockLoginAttemptHandler,
+            oauth::{DynOAuthHandler, MockOAuthHandler},
+            personal_access_token::{
+                DynPersonalAccessTokenHandler, MockPersonalAccessTokenHandler,
+            },
+            refresh_token::{DynRefreshTokenHandler, MockRefreshTokenHandler},
+            session::{DynSessionHandler, MockSessionHandler},
+            token_revocation::MockTokenRevocationHandler,
+            two_factor::{DynTwoFactorHandler, MockTwoFactorHandler},
+            user::MockUserHandler,
+        },
+        repositories::error::RepositoryError,
+    };
+
+    const READ_ONLY_TOKEN_OWNER: Uuid = Uuid::from_u128(1);
+
+    fn bearer(id: Uuid, role: Role) -> (header::HeaderName, String) {
+        let token = create_jwt(id, role, None, &JwtKeys::from_secret(b"secret")).unwrap();
+        (header::AUTHORIZATION, format!("Bearer {token}"))
+    }
+
+    /// A token an app was granted every scope with.
+    fn oauth_bearer(id: Uuid) -> (header::HeaderName, String) {
+        let token = create_oauth_jwt(
+            id,
+            Uuid::new_v4(),
+            &[Scope::UsersRead, Scope::UsersWrite],
+            Some(Uuid::new_v4()),
+            &JwtKeys::from_secret(b"secret"),
+        )
+        .unwrap();
+        (header::AUTHORIZATION, format!("Bearer {token}"))
+    }
+
+    /// Handlers the routes under test may call besides the user handler.
+    #[derive(Default)]
+    struct Handlers {
+        email_verification: MockEmailVerificationHandler,
+        two_factor: MockTwoFactorHandler,
+        login_attempt: MockLoginAttemptHandler,
+        session: MockSessionHandler,
+        refresh_token: MockRefreshTokenHandler,
+        email_change: MockEmailChangeHandler,
+        personal_access_token: MockPersonalAccessTokenHandler,
+        oauth: MockOAuthHandler,
+        revocation: MockTokenRevocationHandler,
+    }
+
+    async fn call(
+        user_handler: MockUserHandler,
+        req: test::TestRequest,
+    ) -> actix_web::http::StatusCode {
+        let mut handlers = Handlers::default();
+        handlers
+            .login_attempt
+            .expect_unlock_account()
+            .returning(|_| Ok(()));
+        handlers
+            .oauth
+            .expect_get_user_clients()
+            .returning(|_| Ok(vec![]));
+        call_with(user_handler, handlers, req).await
+    }
+
+    async fn call_with(
+        user_handler: MockUserHandler,
+        handlers: Handlers,
+        req: test::TestRequest,
+    ) -> actix_web::http::StatusCode {
+        let mut revocation_handler = handlers.revocation;
+        revocation_handler
+            .expect_is_token_revoked()
+            .returning(|_, _, _, _| Ok(false));
+        revocation_handler
+            .expect_revoke_user_tokens()
+            .returning(|_| Ok(()));
+        let revocation_handler: Arc<DynTokenRevocationHandler> = Arc::new(revocation_handler);
+        let mut personal_access_token_handler = handlers.personal_access_token;
+        personal_access_token_handler
+            .expect_authenticate()
+            .returning(|_| {
+                Ok(factori::create!(
+                    PersonalAccessToken,
+                    user_id: READ_ONLY_TOKEN_OWNER,
+                    scopes: vec![Scope::UsersRead]
+                ))
+            });
+        let personal_access_token_handler: Arc<DynPersonalAccessTokenHandler> =
+            Arc::new(personal_access_token_handler);
+        let user_handler: Arc<DynUserHandler> = Arc::new(user_handler);
+        let email_verification_handler: Arc<DynEmailVerificationHandler> =
+            Arc::new(handlers.email_verification);
+        let two_factor_handler: Arc<DynTwoFactorHandler> = Arc::new(handlers.two_factor);
+        let session_handler: Arc<DynSessionHandler> = Arc::new(handlers.session);
+        let email_change_handler: Arc<DynEmailChangeHandler> = Arc::new(handlers.email_change);
+        let refresh_token_handler: Arc<DynRefreshTokenHandler> =
+            Arc::new(handlers.refresh_token);
+        let login_attempt_handler: Arc<DynLoginAttemptHandler> =
+            Arc::new(handlers.login_attempt);
+        let oauth_handler: Arc<DynOAuthHandler> = Arc::new(handlers.oauth);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(JwtKeys::from_secret(b"secret")))
+                .app_data(web::Data::from(revocation_handler))
+                .app_data(web::Data::from(personal_access_token_handler))
+                .app_data(web::Data::from(user_handler))
+                .app_data(web::Data::from(email_verification_handler))
+                .app_data(web::Data::from(two_factor_handler))
+                .app_data(web::Data::from(login_attempt_handler))
+                .app_data(web::Data::from(session_handler))
+                .app_data(web::Data::from(refresh_token_handler))
+                .app_data(web::Data::from(email_change_handler))
+                .app_data(web::Data::from(oauth_handler))
+                .app_data(web::Data::new(EmailVerificationPolicy::BlockWrites))
+                .configure(error_handlers)
+                .configure(user_routes),
+        )
+        .await;
+
+        test::call_service(&app, req.to_request()).await.status()
+    }
+
+    #[actix_web::test]
+    async fn rejects_anonymous_delete() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_delete_user().never();
+
+        let req = test::TestRequest::delete().uri(&format!("/users/{}", Uuid::new_v4()));
+
+        assert_eq!(call(user_handler, req).await, 401);
+    }
+
+    #[actix_web::test]
+    async fn rejects_delete_with_invalid_token() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_delete_user().never();
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{}", Uuid::new_v4()))
+            .insert_header((header::AUTHORIZATION, "Bearer not-a-token"));
+
+        assert_eq!(call(user_handler, req).await, 401);
+    }
+
+    #[actix_web::test]
+    async fn rejects_delete_of_another_user() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_delete_user().never();
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{}", Uuid::new_v4()))
+            .insert_header(bearer(Uuid::new_v4(), Role::User));
+
+        assert_eq!(call(user_handler, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn deletes_own_account() {
+        let id = Uuid::new_v4();
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_delete_user()
+            .withf(move |user_id| *user_id == id)
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{id}"))
+            .insert_header(bearer(id, Role::User));
+
+        assert_eq!(call(user_handler, req).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn admin_deletes_another_user() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_delete_user()
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{}", Uuid::new_v4()))
+            .insert_header(bearer(Uuid::new_v4(), Role::Admin));
+
+        assert_eq!(call(user_handler, req).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn revokes_client_tokens_of_deleted_user() {
+        let id = Uuid::new_v4();
+        let client = factori::create!(OAuthClient, owner_id: id);
+        let client_id = client.id;
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_delete_user()
+            .times(1)
+            .returning(|_| Ok(()));
+        let mut handlers = Handlers::default();
+        handlers
+            .oauth
+            .expect_get_user_clients()
+            .return_once(move |_| Ok(vec![client]));
+        handlers
+            .revocation
+            .expect_revoke_user_tokens()
+            .withf(move |revoked_id| *revoked_id == client_id)
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{id}"))
+            .insert_header(bearer(id, Role::User));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn rejects_anonymous_update_before_validating_body() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_update_user_by_id().never();
+
+        let req = test::TestRequest::patch()
+            .uri(&format!("/users/{}", Uuid::new_v4()))
+            .set_json(serde_json::json!({ "nickname": "not valid!" }));
+
+        assert_eq!(call(user_handler, req).await, 401);
+    }
+
+    #[actix_web::test]
+    async fn rejects_update_of_another_user_by_moderator() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_update_user_by_id().never();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+
+        let req = test::TestRequest::patch()
+            .uri(&format!("/users/{}", Uuid::new_v4()))
+            .insert_header(bearer(Uuid::new_v4(), Role::Moderator))
+            .set_json(serde_json::json!({ "bio": "hello" }));
+
+        assert_eq!(call(user_handler, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn admin_updates_another_user() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_update_user_by_id()
+            .times(1)
+            .returning(|_, _| Ok(()));
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+
+        let req = test::TestRequest::patch()
+            .uri(&format!("/users/{}", Uuid::new_v4()))
+            .insert_header(bearer(Uuid::new_v4(), Role::Admin))
+            .set_json(serde_json::json!({ "bio": "hello" }));
+
+        assert_eq!(call(user_handler, req).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn rejects_role_change_by_non_admin() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_update_user_role().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::put()
+            .uri(&format!("/users/{id}/role"))
+            .insert_header(bearer(id, Role::Moderator))
+            .set_json(serde_json::json!({ "role": "admin" }));
+
+        assert_eq!(call(user_handler, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_update_with_unprocessable_entity() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_update_user_by_id().never();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::patch()
+            .uri(&format!("/users/{id}"))
+            .insert_header(bearer(id, Role::User))
+            .set_json(serde_json::json!({ "nickname": "not valid!" }));
+
+        assert_eq!(call(user_handler, req).await, 422);
+    }
+
+    #[actix_web::test]
+    async fn rejects_malformed_user_id_with_not_found() {
+        let user_handler = MockUserHandler::new();
+
+        let req = test::TestRequest::get().uri("/users/not-a-uuid");
+
+        assert_eq!(call(user_handler, req).await, 404);
+    }
+
+    #[actix_web::test]
+    async fn rejects_update_with_read_only_access_token() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_update_user_by_id().never();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+
+        let req = test::TestRequest::patch()
+            .uri(&format!("/users/{READ_ONLY_TOKEN_OWNER}"))
+            .insert_header((header::AUTHORIZATION, "Bearer uat_token"))
+            .set_json(serde_json::json!({ "bio": "hello" }));
+
+        assert_eq!(call(user_handler, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_token_list_of_another_user() {
+        let mut handlers = Handlers::default();
+        handlers
+            .personal_access_token
+            .expect_get_user_tokens()
+            .never();
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/users/{}/tokens", Uuid::new_v4()))
+            .insert_header(bearer(Uuid::new_v4(), Role::User));
+
+        assert_eq!(call_with(MockUserHandler::new(), handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_token_with_scope_its_creator_lacks() {
+        let id = Uuid::new_v4();
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+        let mut handlers = Handlers::default();
+        handlers
+            .personal_access_token
+            .expect_authenticate()
+            .return_once(move |_| {
+                Ok(factori::create!(
+                    PersonalAccessToken,
+                    user_id: id,
+                    scopes: vec![Scope::UsersWrite]
+                ))
+            });
+        handlers.personal_access_token.expect_create_token().never();
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/users/{id}/tokens"))
+            .insert_header((header::AUTHORIZATION, "Bearer uat_token"))
+            .set_json(serde_json::json!({
+                "name": "script",
+                "scopes": ["users:read", "users:write"],
+            }));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_metrics_token_created_by_non_admin() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+        let mut handlers = Handlers::default();
+        handlers.personal_access_token.expect_create_token().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::post()
+            .uri(&format!("/users/{id}/tokens"))
+            .insert_header(bearer(id, Role::User))
+            .set_json(serde_json::json!({ "name": "scraper", "scopes": ["metrics:read"] }));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn creates_token_with_scopes_of_its_creator() {
+        let id = Uuid::new_v4();
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+        let mut handlers = Handlers::default();
+        handlers
+            .personal_access_token
+            .expect_create_token()
+            .withf(move |user_id, payload| *user_id == id && payload.scopes == [Scope::UsersRead])
+            .times(1)
+            .returning(|_, _| {
+                Ok((
+                    factori::create!(PersonalAccessToken),
+                    "uat_token".to_string(),
+                ))
+            });
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/users/{id}/tokens"))
+            .insert_header(bearer(id, Role::User))
+            .set_json(serde_json::json!({ "name": "script", "scopes": ["users:read"] }));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 201);
+    }
+
+    #[actix_web::test]
+    async fn rejects_unlock_by_non_admin() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_get_user_by_id().never();
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{}/lockout", Uuid::new_v4()))
+            .insert_header(bearer(Uuid::new_v4(), Role::Moderator));
+
+        assert_eq!(call(user_handler, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn admin_unlocks_account() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .times(1)
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{}/lockout", Uuid::new_v4()))
+            .insert_header(bearer(Uuid::new_v4(), Role::Admin));
+
+        assert_eq!(call(user_handler, req).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn rejects_update_by_unverified_user() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_update_user_by_id().never();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser, email_verified_at: None))));
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::patch()
+            .uri(&format!("/users/{id}"))
+            .insert_header(bearer(id, Role::User))
+            .set_json(serde_json::json!({ "bio": "hello" }));
+
+        assert_eq!(call(user_handler, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_disabling_own_two_factor_without_confirmation() {
+        let mut handlers = Handlers::default();
+        handlers.two_factor.expect_disable().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{id}/2fa"))
+            .insert_header(bearer(id, Role::User));
+
+        assert_eq!(call_with(MockUserHandler::new(), handlers, req).await, 400);
+    }
+
+    #[actix_web::test]
+    async fn counts_wrong_password_when_disabling_two_factor() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+        user_handler
+            .expect_verify_password()
+            .return_once(|_, _| Err(RepositoryError::HashingError(Error::Password)));
+        let mut handlers = Handlers::default();
+        handlers
+            .login_attempt
+            .expect_start_attempt()
+            .returning(|_, _| Ok(()));
+        handlers
+            .login_attempt
+            .expect_record_failure()
+            .times(1)
+            .returning(|_, _| Ok(()));
+        handlers.two_factor.expect_disable().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{id}/2fa"))
+            .insert_header(bearer(id, Role::User))
+            .set_json(serde_json::json!({ "password": "guess" }));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 401);
+    }
+
+    #[actix_web::test]
+    async fn admin_disables_two_factor_of_another_user_without_confirmation() {
+        let mut handlers = Handlers::default();
+        handlers
+            .two_factor
+            .expect_disable()
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{}/2fa", Uuid::new_v4()))
+            .insert_header(bearer(Uuid::new_v4(), Role::Admin));
+
+        assert_eq!(call_with(MockUserHandler::new(), handlers, req).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn creates_user_when_verification_mail_fails() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_create_user()
+            .return_once(|_| Ok(factori::create!(PublicUser)));
+        let mut handlers = Handlers::default();
+        handlers
+            .email_verification
+            .expect_send_verification()
+            .times(1)
+            .returning(|_| Err(RepositoryError::SqlxError(sqlx::Error::PoolClosed)));
+
+        let req = test::TestRequest::post()
+            .uri("/users/")
+            .set_json(serde_json::json!({
+                "nickname": "johndoe",
+                "email": "johndoe@gmail.com",
+                "password": "correct horse battery staple",
+            }));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 201);
+    }
+
+    #[actix_web::test]
+    async fn counts_wrong_current_password() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+        user_handler
+            .expect_change_password()
+            .return_once(|_, _| Err(RepositoryError::HashingError(Error::Password)));
+        let mut handlers = Handlers::default();
+        handlers
+            .login_attempt
+            .expect_start_attempt()
+            .returning(|_, _| Ok(()));
+        handlers
+            .login_attempt
+            .expect_record_failure()
+            .times(1)
+            .returning(|_, _| Ok(()));
+        handlers.session.expect_revoke_other_sessions().never();
+
+        let id = Uuid::new_v4();
+        let token = create_jwt(
+            id,
+            Role::User,
+            Some(Uuid::new_v4()),
+            &JwtKeys::from_secret(b"secret"),
+        )
+        .unwrap();
+        let req = test::TestRequest::post()
+            .uri(&format!("/users/{id}/password"))
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .set_json(serde_json::json!({
+                "currentPassword": "guess",
+                "newPassword": "correct horse battery staple",
+            }));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 401);
+    }
+
+    #[actix_web::test]
+    async fn counts_wrong_password_when_changing_email() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+        let mut handlers = Handlers::default();
+        handlers
+            .email_change
+            .expect_request_change()
+            .return_once(|_, _| Err(RepositoryError::HashingError(Error::Password)));
+        handlers
+            .login_attempt
+            .expect_start_attempt()
+            .returning(|_, _| Ok(()));
+        handlers
+            .login_attempt
+            .expect_record_failure()
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::post()
+            .uri(&format!("/users/{id}/email"))
+            .insert_header(bearer(id, Role::User))
+            .set_json(serde_json::json!({
+                "password": "guess",
+                "newEmail": "new_johndoe@gmail.com",
+            }));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 401);
+    }
+
+    #[actix_web::test]
+    async fn rejects_delete_with_oauth_token() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler.expect_delete_user().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{id}"))
+            .insert_header(oauth_bearer(id));
+
+        assert_eq!(call(user_handler, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_email_change_with_oauth_token() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+        let mut handlers = Handlers::default();
+        handlers.email_change.expect_request_change().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::post()
+            .uri(&format!("/users/{id}/email"))
+            .insert_header(oauth_bearer(id))
+            .set_json(serde_json::json!({
+                "password": "correct horse battery staple",
+                "newEmail": "new_johndoe@gmail.com",
+            }));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_two_factor_enrollment_with_oauth_token() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+        let mut handlers = Handlers::default();
+        handlers.two_factor.expect_enroll().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::post()
+            .uri(&format!("/users/{id}/2fa"))
+            .insert_header(oauth_bearer(id));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_disabling_two_factor_with_oauth_token() {
+        let mut handlers = Handlers::default();
+        handlers.two_factor.expect_verify_code().never();
+        handlers.two_factor.expect_disable().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{id}/2fa"))
+            .insert_header(oauth_bearer(id))
+            .set_json(serde_json::json!({ "code": "123456" }));
+
+        assert_eq!(call_with(MockUserHandler::new(), handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_session_deletion_with_oauth_token() {
+        let mut handlers = Handlers::default();
+        handlers.session.expect_revoke_session().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{id}/sessions/{}", Uuid::new_v4()))
+            .insert_header(oauth_bearer(id));
+
+        assert_eq!(call_with(MockUserHandler::new(), handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_two_factor_confirmation_with_oauth_token() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+        let mut handlers = Handlers::default();
+        handlers.two_factor.expect_confirm().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::post()
+            .uri(&format!("/users/{id}/2fa/confirm"))
+            .insert_header(oauth_bearer(id))
+            .set_json(serde_json::json!({ "code": "123456" }));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_grant_revocation_with_oauth_token() {
+        let mut handlers = Handlers::default();
+        handlers.oauth.expect_revoke_grant().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{id}/oauth-grants/{}", Uuid::new_v4()))
+            .insert_header(oauth_bearer(id));
+
+        assert_eq!(call_with(MockUserHandler::new(), handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_client_registration_with_oauth_token() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+        let mut handlers = Handlers::default();
+        handlers.oauth.expect_register_client().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::post()
+            .uri(&format!("/users/{id}/oauth-clients"))
+            .insert_header(oauth_bearer(id))
+            .set_json(serde_json::json!({
+                "name": "Partner app",
+                "redirectUris": ["https://partner.example.com/callback"],
+                "scopes": ["users:read"],
+            }));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_client_deletion_with_oauth_token() {
+        let mut handlers = Handlers::default();
+        handlers.oauth.expect_delete_client().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{id}/oauth-clients/{}", Uuid::new_v4()))
+            .insert_header(oauth_bearer(id));
+
+        assert_eq!(call_with(MockUserHandler::new(), handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_token_creation_with_oauth_token() {
+        let mut user_handler = MockUserHandler::new();
+        user_handler
+            .expect_get_user_by_id()
+            .returning(|_| Ok(Some(factori::create!(PublicUser))));
+        let mut handlers = Handlers::default();
+        handlers.personal_access_token.expect_create_token().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::post()
+            .uri(&format!("/users/{id}/tokens"))
+            .insert_header(oauth_bearer(id))
+            .set_json(serde_json::json!({ "name": "script", "scopes": ["users:read"] }));
+
+        assert_eq!(call_with(user_handler, handlers, req).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn rejects_token_deletion_with_oauth_token() {
+        let mut handlers = Handlers::default();
+        handlers.personal_access_token.expect_delete_token().never();
+
+        let id = Uuid::new_v4();
+        let req = test::TestRequest::delete()
+            .uri(&format!("/users/{id}/tokens/{}", Uuid::new_v4()))
+            .insert_header(oauth_bearer(id));
+
+        assert_eq!(call_with(MockUserHandler::new(), handlers, req).await, 403);
+    }
+}
@@ -0,0 +1,14 @@
+use actix_web::{
+    web::{self, ServiceConfig},
+    HttpResponse,
+};
+
+use crate::auth::keys::JwtKeys;
+
+pub(crate) fn well_known_routes(cfg: &mut ServiceConfig) {
+    cfg.service(web::scope("/.well-known").route("/jwks.json", web::get().to(get_jwks)));
+}
+
+async fn get_jwks(keys: web::Data<JwtKeys>) -> HttpResponse {
+    HttpResponse::Ok().json(keys.jwks())
+}
@@ -1,5 +1,10 @@
+use std::{env, fmt::Debug, str::FromStr};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::{DateTime, Utc};
 use serde::{Serializer, Serialize};
+use sha2::{Digest, Sha256};
 
 pub fn serialize_dt<S>(dt: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -22,3 +27,58 @@ where
         serializer.serialize_none()
     }
 }
+
+/// Generates an opaque random token, encoded so it can be sent as-is in a JSON body or URL.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Opaque tokens are only ever persisted as their SHA-256 digest.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Mailed links point to a page of the client app, which reads the token from the
+/// `token` query parameter.
+pub fn token_link(url: &str, token: &str) -> String {
+    format!("{url}?token={token}")
+}
+
+/// The S256 PKCE challenge of a code verifier.
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// Reads an optional setting from the environment, panicking at startup if it is set
+/// but cannot be parsed.
+pub fn env_var_or<T>(name: &str, default: T) -> T
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid value for {name}: {e:?}")),
+        Err(_) => default,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn derives_s256_code_challenge() {
+        // From RFC 7636, appendix B.
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}